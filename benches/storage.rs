@@ -0,0 +1,135 @@
+//! Recherches dans une base synthétique de 100 000 utilisateurs, avec les
+//! index de la base et par un parcours complet des tables.
+//!
+//! `cargo bench --bench storage`
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use karak::{
+    db::{Database, Storage},
+    models::{
+        BloodType, MedicalFolder, MedicalReport, PersonalData, ReportContent, ReportID, Role,
+        UserData, UserID,
+    },
+    utils::{input_validation::Username, password_utils::hash},
+};
+
+const USERS: usize = 100_000;
+/// Un utilisateur sur cent est médecin, chaque patient en a deux
+const DOCTOR_EVERY: usize = 100;
+
+struct Dataset {
+    db: Database,
+    users: Vec<UserData>,
+    reports: Vec<MedicalReport>,
+}
+
+fn dataset() -> Dataset {
+    let password = hash("benchmark");
+    let doctors: Vec<UserID> = (0..USERS / DOCTOR_EVERY).map(|_| UserID::new()).collect();
+    let mut users = Vec::with_capacity(USERS);
+    let mut reports = Vec::with_capacity(USERS);
+
+    for n in 0..USERS {
+        let doctor = n % DOCTOR_EVERY == 0;
+        let id = if doctor {
+            doctors[n / DOCTOR_EVERY]
+        } else {
+            UserID::new()
+        };
+        let medical_folder = (!doctor).then(|| {
+            let mut folder = MedicalFolder::new(PersonalData::new(
+                "756.1234.5678.97".to_owned().try_into().unwrap(),
+                BloodType::O,
+            ));
+            folder.doctors.insert(doctors[n % doctors.len()]);
+            folder.doctors.insert(doctors[(n * 7) % doctors.len()]);
+            folder
+        });
+        if !doctor {
+            reports.push(MedicalReport {
+                id: ReportID::new(),
+                title: format!("Rapport {n}"),
+                author: doctors[n % doctors.len()],
+                patient: id,
+                content: ReportContent::Plain(String::new()),
+                details: None,
+                attachments: Vec::new(),
+                revisions: Vec::new(),
+                provenance: Default::default(),
+                parent: None,
+                signature: None,
+                seal: None,
+            });
+        }
+        users.push(UserData {
+            id,
+            role: if doctor { Role::Doctor } else { Role::Patient },
+            username: Username::new(format!("user{n}")),
+            password: password.clone(),
+            must_change_password: false,
+            second_factor: None,
+            keys: None,
+            signing_keys: None,
+            medical_folder,
+            provenance: Default::default(),
+        });
+    }
+
+    let mut db = Database::default();
+    for user in &users {
+        db.store_user(user.clone()).unwrap();
+    }
+    for report in &reports {
+        db.store_report(report.clone()).unwrap();
+    }
+    Dataset { db, users, reports }
+}
+
+fn lookups(c: &mut Criterion) {
+    let Dataset { db, users, reports } = dataset();
+    let name = Username::new(format!("user{}", USERS - 1));
+    let doctor = users[USERS - DOCTOR_EVERY].id;
+    let patient = users[USERS - 1].id;
+
+    let mut group = c.benchmark_group("lookup_username");
+    group.bench_function("index", |b| b.iter(|| db.lookup_username(black_box(&name))));
+    group.bench_function("scan", |b| {
+        b.iter(|| users.iter().find(|user| &user.username == black_box(&name)))
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("get_patients");
+    group.bench_function("index", |b| {
+        b.iter(|| db.get_patients(black_box(doctor)).count())
+    });
+    group.bench_function("scan", |b| {
+        b.iter(|| {
+            users
+                .iter()
+                .filter(|user| user.has_doctor(black_box(doctor)))
+                .count()
+        })
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("list_patient_reports");
+    group.bench_function("index", |b| {
+        b.iter(|| db.list_patient_reports(black_box(patient)).count())
+    });
+    group.bench_function("scan", |b| {
+        b.iter(|| {
+            reports
+                .iter()
+                .filter(|report| report.patient == black_box(patient))
+                .count()
+        })
+    });
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = lookups
+}
+criterion_main!(benches);
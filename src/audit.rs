@@ -0,0 +1,455 @@
+//! Journal d'audit des décisions d'autorisation, stocké en JSON lines.
+//!
+//! Le fichier n'est jamais réécrit: chaque décision y est ajoutée à la suite
+//! des précédentes. Chaque entrée contient le haché de la précédente, de sorte
+//! que toute modification ou suppression d'une entrée puisse être détectée.
+//! Seule une dernière ligne incomplète, laissée par un arrêt pendant un ajout,
+//! est retirée à l'ouverture.
+
+use crate::models::{ReportID, UserID};
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind::NotFound, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use thiserror::Error;
+
+/// Haché précédant la première entrée du journal
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Issue d'une vérification d'accès
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display)]
+pub enum Decision {
+    #[display("accordé")]
+    Granted,
+    #[display("refusé")]
+    Denied,
+    /// Une modification des données, effectuée après un accès accordé
+    #[display("appliqué")]
+    Applied,
+}
+
+/// Les objets concernés par une action
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditTarget {
+    /// L'utilisateur dont le compte ou le dossier est concerné
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<UserID>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub doctor: Option<UserID>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report: Option<ReportID>,
+}
+
+impl AuditTarget {
+    pub fn user(user: UserID) -> Self {
+        Self {
+            user: Some(user),
+            ..Default::default()
+        }
+    }
+
+    pub fn report(patient: UserID, report: ReportID) -> Self {
+        Self {
+            user: Some(patient),
+            report: Some(report),
+            ..Default::default()
+        }
+    }
+
+    pub fn doctor(patient: UserID, doctor: UserID) -> Self {
+        Self {
+            user: Some(patient),
+            doctor: Some(doctor),
+            ..Default::default()
+        }
+    }
+}
+
+/// Une entrée du journal d'audit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub timestamp: DateTime<Utc>,
+    pub subject: UserID,
+    pub action: String,
+    pub target: AuditTarget,
+    pub decision: Decision,
+    /// La règle qui a accordé l'accès, la raison du refus, ou le détail
+    /// d'une modification
+    pub reason: String,
+    /// Haché de l'entrée précédente
+    #[serde(default)]
+    pub prev_hash: String,
+    /// Haché de cette entrée, calculé sur tous les autres champs
+    #[serde(default)]
+    pub hash: String,
+}
+
+impl AuditRecord {
+    pub fn new(
+        timestamp: DateTime<Utc>,
+        subject: UserID,
+        action: &str,
+        target: AuditTarget,
+        decision: Decision,
+        reason: String,
+    ) -> Self {
+        Self {
+            timestamp,
+            subject,
+            action: action.to_owned(),
+            target,
+            decision,
+            reason,
+            prev_hash: String::new(),
+            hash: String::new(),
+        }
+    }
+
+    /// Calcule le haché de l'entrée, sans tenir compte du champ `hash`
+    pub fn compute_hash(&self) -> String {
+        let unhashed = AuditRecord {
+            hash: String::new(),
+            ..self.clone()
+        };
+        let bytes = serde_json::to_vec(&unhashed).expect("audit records are serializable");
+        format!("{:x}", Sha256::digest(bytes))
+    }
+}
+
+/// Une incohérence dans le journal d'audit
+#[derive(Debug, Error)]
+pub enum AuditError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Ligne {line}: entrée illisible ({reason})")]
+    Malformed { line: usize, reason: String },
+    #[error("Ligne {line}: l'entrée ne suit pas la précédente (entrée supprimée ou modifiée ?)")]
+    BrokenLink { line: usize },
+    #[error("Ligne {line}: le contenu de l'entrée a été modifié")]
+    Tampered { line: usize },
+}
+
+/// Critères de recherche dans le journal
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub subject: Option<UserID>,
+    /// Utilisateur dont le compte ou le dossier est concerné
+    pub user: Option<UserID>,
+    pub action: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, record: &AuditRecord) -> bool {
+        self.subject.is_none_or(|subject| record.subject == subject)
+            && self
+                .user
+                .is_none_or(|user| record.target.user == Some(user))
+            && self
+                .action
+                .as_ref()
+                .is_none_or(|action| &record.action == action)
+            && self.since.is_none_or(|since| record.timestamp >= since)
+            && self.until.is_none_or(|until| record.timestamp < until)
+    }
+}
+
+/// Le journal d'audit, gardé en mémoire et recopié dans un fichier s'il en a un
+#[derive(Default)]
+pub struct AuditLog {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    file: Option<File>,
+    records: Vec<AuditRecord>,
+}
+
+/// Le contenu d'un fichier de journal
+struct Contents {
+    records: Vec<AuditRecord>,
+    /// Longueur des lignes lues, sans la dernière si elle est incomplète
+    complete_len: usize,
+    /// Une dernière ligne illisible et sans fin de ligne, laissée par un
+    /// arrêt pendant un ajout
+    torn: Option<AuditError>,
+}
+
+impl AuditLog {
+    /// Ouvre le journal en ajout, en relisant les entrées existantes.
+    ///
+    /// Comme pour le journal de la base de données, une dernière ligne
+    /// incomplète termine la lecture: elle est signalée puis retirée, pour
+    /// que les entrées suivantes commencent sur une nouvelle ligne.
+    pub fn open(path: PathBuf) -> Result<Self, AuditError> {
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(not_found) if not_found.kind() == NotFound => Vec::new(),
+            Err(other) => return Err(other.into()),
+        };
+        let contents = Self::read(&bytes)?;
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        if let Some(torn) = &contents.torn {
+            warn!("Incomplete audit record removed: {torn}");
+            file.set_len(contents.complete_len as u64)?;
+        }
+        if bytes[..contents.complete_len]
+            .last()
+            .is_some_and(|&b| b != b'\n')
+        {
+            file.write_all(b"\n")?;
+        }
+        file.sync_data()?;
+
+        Ok(Self {
+            inner: Mutex::new(Inner {
+                file: Some(file),
+                records: contents.records,
+            }),
+        })
+    }
+
+    fn read(bytes: &[u8]) -> Result<Contents, AuditError> {
+        let mut contents = Contents {
+            records: Vec::new(),
+            complete_len: 0,
+            torn: None,
+        };
+        for (number, line) in bytes.split_inclusive(|&b| b == b'\n').enumerate() {
+            if !line.trim_ascii().is_empty() {
+                match serde_json::from_slice(line) {
+                    Ok(record) => contents.records.push(record),
+                    Err(e) => {
+                        let error = AuditError::Malformed {
+                            line: number + 1,
+                            reason: e.to_string(),
+                        };
+                        if line.ends_with(b"\n") {
+                            return Err(error);
+                        }
+                        contents.torn = Some(error);
+                        break;
+                    }
+                }
+            }
+            contents.complete_len += line.len();
+        }
+        Ok(contents)
+    }
+
+    /// Vérifie la chaîne de hachés d'un fichier de journal.
+    ///
+    /// Retourne le nombre d'entrées vérifiées, ou le premier maillon rompu.
+    /// Une dernière ligne incomplète est signalée après les autres entrées.
+    pub fn verify(path: &Path) -> Result<usize, AuditError> {
+        let Contents { records, torn, .. } = Self::read(&fs::read(path)?)?;
+
+        let mut prev_hash = GENESIS_HASH.to_owned();
+        for (index, record) in records.iter().enumerate() {
+            let line = index + 1;
+            if record.prev_hash != prev_hash {
+                return Err(AuditError::BrokenLink { line });
+            }
+            if record.compute_hash() != record.hash {
+                return Err(AuditError::Tampered { line });
+            }
+            prev_hash = record.hash.clone();
+        }
+        torn.map_or(Ok(records.len()), Err)
+    }
+
+    /// Ajoute une entrée au journal, chaînée à la précédente
+    pub fn append(&self, mut record: AuditRecord) -> Result<(), AuditError> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+
+        record.prev_hash = inner
+            .records
+            .last()
+            .map(|last| last.hash.clone())
+            .unwrap_or_else(|| GENESIS_HASH.to_owned());
+        record.hash = record.compute_hash();
+
+        if let Some(file) = &mut inner.file {
+            let mut line = serde_json::to_string(&record).map_err(io::Error::from)?;
+            line.push('\n');
+            file.write_all(line.as_bytes())?;
+            // L'entrée doit être sur le disque avant la modification qu'elle trace
+            file.sync_data()?;
+        }
+        inner.records.push(record);
+        Ok(())
+    }
+
+    /// Les entrées correspondant au filtre, de la plus ancienne à la plus récente
+    pub fn query(&self, filter: &AuditFilter) -> Vec<AuditRecord> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .records
+            .iter()
+            .filter(|record| filter.matches(record))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    fn record(subject: UserID, patient: UserID, action: &str, age_days: i64) -> AuditRecord {
+        AuditRecord::new(
+            Utc::now() - TimeDelta::days(age_days),
+            subject,
+            action,
+            AuditTarget::user(patient),
+            Decision::Granted,
+            "test".to_owned(),
+        )
+    }
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("karak-audit-{}.log", UserID::new()))
+    }
+
+    #[test]
+    fn test_query() {
+        let log = AuditLog::default();
+        let (doctor, patient, other) = (UserID::new(), UserID::new(), UserID::new());
+
+        log.append(record(doctor, patient, "read-data", 40))
+            .unwrap();
+        log.append(record(doctor, patient, "read-data", 10))
+            .unwrap();
+        log.append(record(doctor, other, "read-data", 10)).unwrap();
+        log.append(record(patient, patient, "update-data", 5))
+            .unwrap();
+
+        let last_month = AuditFilter {
+            user: Some(patient),
+            action: Some("read-data".to_owned()),
+            since: Some(Utc::now() - TimeDelta::days(30)),
+            ..Default::default()
+        };
+        let records = log.query(&last_month);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].subject, doctor);
+
+        let by_patient = AuditFilter {
+            subject: Some(patient),
+            ..Default::default()
+        };
+        assert_eq!(log.query(&by_patient).len(), 1);
+        assert_eq!(log.query(&AuditFilter::default()).len(), 4);
+    }
+
+    #[test]
+    fn test_persistence() {
+        let path = temp_path();
+        let (doctor, patient) = (UserID::new(), UserID::new());
+
+        let log = AuditLog::open(path.clone()).unwrap();
+        log.append(record(doctor, patient, "read-data", 0)).unwrap();
+        drop(log);
+
+        let log = AuditLog::open(path.clone()).unwrap();
+        log.append(record(doctor, patient, "read-report", 0))
+            .unwrap();
+        let records = log.query(&AuditFilter::default());
+        let verified = AuditLog::verify(&path);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].action, "read-data");
+        assert_eq!(records[1].action, "read-report");
+        assert_eq!(records[1].prev_hash, records[0].hash);
+        assert_eq!(verified.unwrap(), 2);
+    }
+
+    #[test]
+    fn test_incomplete_last_line() {
+        let path = temp_path();
+        let (doctor, patient) = (UserID::new(), UserID::new());
+        let log = AuditLog::open(path.clone()).unwrap();
+        for action in ["read-data", "read-report"] {
+            log.append(record(doctor, patient, action, 0)).unwrap();
+        }
+        drop(log);
+
+        // Arrêt pendant l'écriture d'une troisième entrée
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"timestamp":"2024-05-"#).unwrap();
+        drop(file);
+        let reported = AuditLog::verify(&path);
+
+        let log = AuditLog::open(path.clone()).unwrap();
+        log.append(record(doctor, patient, "update-data", 0))
+            .unwrap();
+        let records = log.query(&AuditFilter::default());
+        let verified = AuditLog::verify(&path);
+        std::fs::remove_file(path).unwrap();
+
+        assert!(matches!(
+            reported,
+            Err(AuditError::Malformed { line: 3, .. })
+        ));
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2].prev_hash, records[1].hash);
+        assert_eq!(verified.unwrap(), 3);
+    }
+
+    /// Écrit un journal de trois entrées, le modifie, et vérifie la chaîne
+    fn verify_tampered(tamper: impl FnOnce(&mut Vec<String>)) -> Result<usize, AuditError> {
+        let path = temp_path();
+        let (doctor, patient) = (UserID::new(), UserID::new());
+        let log = AuditLog::open(path.clone()).unwrap();
+        for action in ["read-data", "read-report", "update-data"] {
+            log.append(record(doctor, patient, action, 0)).unwrap();
+        }
+        drop(log);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let mut lines: Vec<String> = content.lines().map(str::to_owned).collect();
+        tamper(&mut lines);
+        std::fs::write(&path, lines.join("\n")).unwrap();
+
+        let result = AuditLog::verify(&path);
+        std::fs::remove_file(path).unwrap();
+        result
+    }
+
+    #[test]
+    fn test_verify_detects_tampering() {
+        assert_eq!(verify_tampered(|_| {}).unwrap(), 3);
+
+        let result = verify_tampered(|lines| {
+            lines[1] = lines[1].replace("read-report", "read-data");
+        });
+        assert!(matches!(result, Err(AuditError::Tampered { line: 2 })));
+
+        let result = verify_tampered(|lines| {
+            lines.remove(1);
+        });
+        assert!(matches!(result, Err(AuditError::BrokenLink { line: 2 })));
+
+        // Recalculer le haché d'une entrée modifiée casse le maillon suivant
+        let result = verify_tampered(|lines| {
+            let mut record: AuditRecord = serde_json::from_str(&lines[0]).unwrap();
+            record.action = "read-audit".to_owned();
+            record.hash = record.compute_hash();
+            lines[0] = serde_json::to_string(&record).unwrap();
+        });
+        assert!(matches!(result, Err(AuditError::BrokenLink { line: 2 })));
+    }
+}
@@ -2,18 +2,23 @@
 //! des conventions objet-action
 
 use casbin::CoreApi;
+use chrono::{DateTime, Utc};
 use log::{error, info};
 use serde::Serialize;
 use serde_json::json;
 use thiserror::Error;
 
-use crate::models::{MedicalReport, Role, UserData};
+use crate::audit::{AuditLog, AuditRecord, AuditTarget, Decision};
+use crate::models::{ClinicalEntry, MedicalReport, Role, UserData};
 
 const CONFIG: &str = "access_control/model.conf";
 const POLICY: &str = "access_control/policy.csv";
 
-/// Un enforcer Casbin
-pub struct Enforcer(casbin::Enforcer);
+/// Un enforcer Casbin, qui consigne chacune de ses décisions
+pub struct Enforcer {
+    casbin: casbin::Enforcer,
+    audit: AuditLog,
+}
 
 type CasbinResult = Result<(), AccessDenied>;
 
@@ -26,87 +31,180 @@ pub struct AccessDenied;
 pub struct Context<'ctx> {
     enforcer: &'ctx Enforcer,
     subject: &'ctx UserData,
+    /// L'heure consignée avec chaque décision
+    now: DateTime<Utc>,
 }
 
 impl Enforcer {
     pub fn load() -> Result<Self, casbin::Error> {
         let mut enforcer = futures::executor::block_on(casbin::Enforcer::new(CONFIG, POLICY))?;
         futures::executor::block_on(enforcer.load_policy())?;
-        Ok(Enforcer(enforcer))
+        Ok(Enforcer {
+            casbin: enforcer,
+            audit: AuditLog::default(),
+        })
+    }
+
+    /// Remplace le journal d'audit (par défaut, gardé uniquement en mémoire)
+    pub fn with_audit_log(mut self, audit: AuditLog) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    pub fn audit_log(&self) -> &AuditLog {
+        &self.audit
     }
 
-    pub fn with_subject<'ctx>(&'ctx self, subject: &'ctx UserData) -> Context<'ctx> {
+    pub fn with_subject<'ctx>(
+        &'ctx self,
+        subject: &'ctx UserData,
+        now: DateTime<Utc>,
+    ) -> Context<'ctx> {
         Context {
             enforcer: self,
             subject,
+            now,
         }
     }
 }
 
 impl Context<'_> {
-    fn enforce<O>(&self, object: O, action: &str) -> CasbinResult
+    fn enforce<O>(&self, object: O, action: &str, target: AuditTarget) -> CasbinResult
     where
         O: Serialize + std::fmt::Debug + std::hash::Hash,
     {
         let subject = self.subject;
 
+        // Le sujet n'est pas journalisé en entier, pour ne pas exposer ses secrets
         info!(
             "Enforcing {}",
-            json!({ "sub": subject, "obj": &object, "act": action })
+            json!({ "sub": subject.id, "role": subject.role, "act": action })
         );
-        match self.enforcer.0.enforce((subject, &object, action)) {
+        let (decision, reason) = match self.enforcer.casbin.enforce_ex((subject, &object, action)) {
             Err(e) => {
                 error!("Casbin error: {e:?}");
-                Err(AccessDenied)
+                (Decision::Denied, format!("Erreur Casbin: {e}"))
             }
-            Ok(r) => {
-                info!("Granted: {r}");
-                if r {
-                    Ok(())
-                } else {
-                    Err(AccessDenied)
-                }
+            Ok((true, rules)) => {
+                let rules: Vec<String> = rules.iter().map(|rule| rule.join(", ")).collect();
+                (Decision::Granted, rules.join(" | "))
             }
+            Ok((false, _)) => (Decision::Denied, "Aucune règle ne correspond".to_owned()),
+        };
+        info!("Granted: {}", decision == Decision::Granted);
+
+        let record = AuditRecord::new(self.now, subject.id, action, target, decision, reason);
+        // Un accès qui ne peut pas être tracé n'est pas accordé
+        if let Err(e) = self.enforcer.audit.append(record) {
+            error!("Audit log error: {e}");
+            return Err(AccessDenied);
+        }
+
+        if decision == Decision::Granted {
+            Ok(())
+        } else {
+            Err(AccessDenied)
         }
     }
 
     pub fn read_data(&self, patient: &UserData) -> CasbinResult {
-        self.enforce(patient, "read-data")
+        self.enforce(patient, "read-data", AuditTarget::user(patient.id))
     }
 
     pub fn update_data(&self, target: &UserData) -> CasbinResult {
-        self.enforce(target, "update-data")
+        self.enforce(target, "update-data", AuditTarget::user(target.id))
     }
 
     pub fn delete_data(&self, target: &UserData) -> CasbinResult {
-        self.enforce(target, "delete-data")
+        self.enforce(target, "delete-data", AuditTarget::user(target.id))
+    }
+
+    pub fn read_clinical_data(&self, patient: &UserData) -> CasbinResult {
+        self.enforce(patient, "read-clinical-data", AuditTarget::user(patient.id))
+    }
+
+    pub fn add_clinical_data(&self, patient: &UserData, entry: &ClinicalEntry) -> CasbinResult {
+        self.enforce(
+            json!({ "patient": patient, "entry": entry }),
+            "add-clinical-data",
+            AuditTarget::user(patient.id),
+        )
     }
 
     pub fn add_report(&self, patient: &UserData, report: &MedicalReport) -> CasbinResult {
         self.enforce(
             json!({ "patient": patient, "report": report }),
             "add-report",
+            AuditTarget::report(patient.id, report.id),
         )
     }
 
     pub fn read_report(&self, report: &MedicalReport, patient: &UserData) -> CasbinResult {
-        self.enforce(json!({"report": report, "patient": patient}), "read-report")
+        self.enforce(
+            json!({"report": report, "patient": patient}),
+            "read-report",
+            AuditTarget::report(patient.id, report.id),
+        )
+    }
+
+    pub fn read_report_history(&self, report: &MedicalReport, patient: &UserData) -> CasbinResult {
+        self.enforce(
+            json!({"report": report, "patient": patient}),
+            "read-report-history",
+            AuditTarget::report(patient.id, report.id),
+        )
     }
 
     pub fn update_report(&self, report: &MedicalReport) -> CasbinResult {
-        self.enforce(report, "update-report")
+        self.enforce(
+            report,
+            "update-report",
+            AuditTarget::report(report.patient, report.id),
+        )
+    }
+
+    pub fn seal_report(&self, report: &MedicalReport) -> CasbinResult {
+        self.enforce(
+            report,
+            "seal-report",
+            AuditTarget::report(report.patient, report.id),
+        )
     }
 
     pub fn update_role(&self, target: &UserData, role: Role) -> CasbinResult {
-        self.enforce(json!({ "target": target, "role": role }), "update-role")
+        self.enforce(
+            json!({ "target": target, "role": role }),
+            "update-role",
+            AuditTarget::user(target.id),
+        )
+    }
+
+    pub fn reset_password(&self, target: &UserData) -> CasbinResult {
+        self.enforce(target, "reset-password", AuditTarget::user(target.id))
+    }
+
+    pub fn unlock_account(&self, target: &UserData) -> CasbinResult {
+        self.enforce(target, "unlock-account", AuditTarget::user(target.id))
+    }
+
+    pub fn read_audit(&self, patient: &UserData) -> CasbinResult {
+        self.enforce(patient, "read-audit", AuditTarget::user(patient.id))
     }
 
     pub fn add_doctor(&self, target: &UserData, doctor: &UserData) -> CasbinResult {
-        self.enforce(json!({"patient": target, "doctor": doctor}), "add-doctor")
+        self.enforce(
+            json!({"patient": target, "doctor": doctor}),
+            "add-doctor",
+            AuditTarget::doctor(target.id, doctor.id),
+        )
     }
 
     pub fn remove_doctor(&self, target: &UserData, doctor: &UserData) -> CasbinResult {
-        self.enforce(json!({"patient": target, "doctor": doctor}), "remove-doctor")
+        self.enforce(
+            json!({"patient": target, "doctor": doctor}),
+            "remove-doctor",
+            AuditTarget::doctor(target.id, doctor.id),
+        )
     }
 }
 
@@ -114,21 +212,20 @@ impl Context<'_> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::audit::AuditFilter;
     use crate::models::{
-        BloodType, PersonalData, Role, UserData, UserID, MedicalReport, ReportID, MedicalFolder,
+        Allergy, BloodType, MedicalFolder, MedicalReport, PersonalData, ReportContent, ReportID,
+        Role, Severity, UserData, UserID,
     };
-    use crate::utils::input_validation::{AVSNumber, Username};
+    use crate::utils::input_validation::{AVSNumber, TextLine, Username};
     use crate::utils::password_utils::hash;
 
     fn create_test_user(id: UserID, username: &str, role: Role, has_folder: bool) -> UserData {
         let medical_folder = if has_folder {
-            Some(MedicalFolder {
-                personal_data: PersonalData {
-                    avs_number: AVSNumber::try_from("756.1234.5678.97".to_string()).unwrap(),
-                    blood_type: BloodType::A,
-                },
-                doctors: Default::default(),
-            })
+            Some(MedicalFolder::new(PersonalData::new(
+                AVSNumber::try_from("756.1234.5678.97".to_string()).unwrap(),
+                BloodType::A,
+            )))
         } else {
             None
         };
@@ -138,7 +235,12 @@ mod test {
             role,
             username: Username::new(username.to_string()),
             password: hash("dummy"),
+            must_change_password: false,
+            second_factor: None,
+            keys: None,
+            signing_keys: None,
             medical_folder,
+            provenance: Default::default(),
         }
     }
 
@@ -158,14 +260,21 @@ mod test {
             title: title.to_string(),
             author,
             patient,
-            content: "Test content".to_string(),
+            content: ReportContent::Plain("Test content".to_string()),
+            details: None,
+            attachments: Vec::new(),
+            revisions: Vec::new(),
+            provenance: Default::default(),
+            parent: None,
+            signature: None,
+            seal: None,
         }
     }
 
     #[test]
     fn test_admin_permissions() {
         let (enforcer, admin, patient, doctor) = setup();
-        let ctx = enforcer.with_subject(&admin);
+        let ctx = enforcer.with_subject(&admin, Utc::now());
 
         // Admin should be able to read any user's data
         assert!(ctx.read_data(&patient).is_ok());
@@ -187,6 +296,11 @@ mod test {
         assert!(ctx.update_role(&doctor, Role::Patient).is_ok());
         assert!(ctx.update_role(&patient, Role::Admin).is_ok());
 
+        // Admin should be able to reset anyone's password
+        assert!(ctx.reset_password(&patient).is_ok());
+        assert!(ctx.reset_password(&doctor).is_ok());
+        assert!(ctx.unlock_account(&patient).is_ok());
+
         // Admin should be able to manage doctor assignments
         assert!(ctx.add_doctor(&patient, &doctor).is_ok());
         assert!(ctx.remove_doctor(&patient, &doctor).is_ok());
@@ -197,7 +311,14 @@ mod test {
             title: "Admin Report".to_string(),
             author: admin.id,
             patient: patient.id,
-            content: "Test content".to_string(),
+            content: ReportContent::Plain("Test content".to_string()),
+            details: None,
+            attachments: Vec::new(),
+            revisions: Vec::new(),
+            provenance: Default::default(),
+            parent: None,
+            signature: None,
+            seal: None,
         };
         assert!(ctx.add_report(&patient, &report).is_ok());
         assert!(ctx.read_report(&report, &patient).is_ok());
@@ -207,7 +328,7 @@ mod test {
     #[test]
     fn test_patient_permissions() {
         let (enforcer, _, patient, doctor) = setup();
-        let ctx = enforcer.with_subject(&patient);
+        let ctx = enforcer.with_subject(&patient, Utc::now());
 
         // Own data access
         assert!(ctx.read_data(&patient).is_ok());
@@ -222,6 +343,9 @@ mod test {
         let other_patient = create_test_user(UserID::new(), "other", Role::Patient, true);
         assert!(ctx.read_data(&other_patient).is_err());
         assert!(ctx.update_role(&patient, Role::Doctor).is_err());
+        assert!(ctx.reset_password(&other_patient).is_err());
+        assert!(ctx.reset_password(&patient).is_err());
+        assert!(ctx.unlock_account(&patient).is_err());
 
         // Report management
         let report = create_test_report(patient.id, patient.id, "Patient Report");
@@ -231,9 +355,12 @@ mod test {
     #[test]
     fn test_doctor_permissions() {
         let (enforcer, _, patient, doctor) = setup();
-        let ctx = enforcer.with_subject(&doctor);
+        let ctx = enforcer.with_subject(&doctor, Utc::now());
         let report = create_test_report(doctor.id, patient.id, "Test Report");
 
+        // Password resets are reserved to admins
+        assert!(ctx.reset_password(&patient).is_err());
+
         // Report permissions
         assert!(ctx.add_report(&patient, &report).is_ok());
         assert!(ctx.read_report(&report, &patient).is_ok());
@@ -243,7 +370,7 @@ mod test {
     #[test]
     fn test_doctor_patient_interactions() {
         let (enforcer, _, mut patient, doctor) = setup();
-        let ctx = enforcer.with_subject(&doctor);
+        let ctx = enforcer.with_subject(&doctor, Utc::now());
 
         // Pre-assignment restrictions
         assert!(ctx.read_data(&patient).is_err());
@@ -270,17 +397,24 @@ mod test {
         let report = create_test_report(doctor.id, patient.id, "Medical Report");
 
         // Doctor's report access
-        let author_ctx = enforcer.with_subject(&doctor);
+        let author_ctx = enforcer.with_subject(&doctor, Utc::now());
         assert!(author_ctx.read_report(&report, &patient).is_ok());
         assert!(author_ctx.update_report(&report).is_ok());
 
         // Another doctor's report restrictions
-        let other_ctx = enforcer.with_subject(&other_doctor);
+        let other_ctx = enforcer.with_subject(&other_doctor, Utc::now());
         assert!(other_ctx.read_report(&report, &patient).is_err());
 
         // Patient's report access
-        let patient_ctx = enforcer.with_subject(&patient);
+        let patient_ctx = enforcer.with_subject(&patient, Utc::now());
         assert!(patient_ctx.read_report(&report, &patient).is_ok());
+
+        // History follows the report's read access
+        assert!(author_ctx.read_report_history(&report, &patient).is_ok());
+        assert!(other_ctx.read_report_history(&report, &patient).is_err());
+        assert!(patient_ctx.read_report_history(&report, &patient).is_ok());
+        assert!(author_ctx.seal_report(&report).is_ok());
+        assert!(patient_ctx.seal_report(&report).is_err());
     }
 
     #[test]
@@ -289,7 +423,7 @@ mod test {
         let other_doctor = create_test_user(UserID::new(), "other_doctor", Role::Doctor, false);
         let report = create_test_report(other_doctor.id, patient.id, "Medical Report");
 
-        let doctor_ctx = enforcer.with_subject(&doctor);
+        let doctor_ctx = enforcer.with_subject(&doctor, Utc::now());
         // Doctor shouldn't see report before assignment
         assert!(doctor_ctx.read_report(&report, &patient).is_err());
 
@@ -315,12 +449,82 @@ mod test {
         let report = create_test_report(doctor1.id, patient.id, "Medical Report");
 
         // Both doctors should have access
-        let doctor1_ctx = enforcer.with_subject(&doctor1);
-        let doctor2_ctx = enforcer.with_subject(&doctor2);
+        let doctor1_ctx = enforcer.with_subject(&doctor1, Utc::now());
+        let doctor2_ctx = enforcer.with_subject(&doctor2, Utc::now());
 
         assert!(doctor1_ctx.read_report(&report, &patient).is_ok());
         assert!(doctor2_ctx.read_report(&report, &patient).is_ok());
     }
 
+    #[test]
+    fn test_clinical_data_access() {
+        let (enforcer, admin, mut patient, doctor) = setup();
+        let allergy = ClinicalEntry::Allergy(Allergy {
+            substance: TextLine::try_from("Pollen".to_owned()).unwrap(),
+            severity: Severity::Mild,
+        });
+
+        let patient_ctx = enforcer.with_subject(&patient, Utc::now());
+        assert!(patient_ctx.read_clinical_data(&patient).is_ok());
+        assert!(patient_ctx.add_clinical_data(&patient, &allergy).is_err());
+
+        let doctor_ctx = enforcer.with_subject(&doctor, Utc::now());
+        assert!(doctor_ctx.read_clinical_data(&patient).is_err());
+        assert!(doctor_ctx.add_clinical_data(&patient, &allergy).is_err());
+
+        if let Some(ref mut folder) = patient.medical_folder {
+            folder.doctors.insert(doctor.id);
+        }
+        assert!(doctor_ctx.read_clinical_data(&patient).is_ok());
+        assert!(doctor_ctx.add_clinical_data(&patient, &allergy).is_ok());
+
+        let admin_ctx = enforcer.with_subject(&admin, Utc::now());
+        assert!(admin_ctx.add_clinical_data(&patient, &allergy).is_ok());
+    }
+
+    #[test]
+    fn test_audit_access() {
+        let (enforcer, admin, patient, doctor) = setup();
+
+        assert!(enforcer
+            .with_subject(&admin, Utc::now())
+            .read_audit(&patient)
+            .is_ok());
+        assert!(enforcer
+            .with_subject(&patient, Utc::now())
+            .read_audit(&patient)
+            .is_ok());
+        assert!(enforcer
+            .with_subject(&doctor, Utc::now())
+            .read_audit(&patient)
+            .is_err());
+    }
 
+    #[test]
+    fn test_decisions_are_audited() {
+        let (enforcer, _, patient, doctor) = setup();
+
+        assert!(enforcer
+            .with_subject(&patient, Utc::now())
+            .read_data(&patient)
+            .is_ok());
+        assert!(enforcer
+            .with_subject(&doctor, Utc::now())
+            .read_data(&patient)
+            .is_err());
+
+        let records = enforcer.audit_log().query(&AuditFilter {
+            user: Some(patient.id),
+            ..Default::default()
+        });
+        assert_eq!(records.len(), 2);
+
+        assert_eq!(records[0].subject, patient.id);
+        assert_eq!(records[0].action, "read-data");
+        assert_eq!(records[0].decision, Decision::Granted);
+        assert!(records[0].reason.contains("r.obj.id == r.sub.id"));
+
+        assert_eq!(records[1].subject, doctor.id);
+        assert_eq!(records[1].decision, Decision::Denied);
+    }
 }
@@ -0,0 +1,158 @@
+//! Stockage des pièces jointes, à côté de la base de données
+//!
+//! Chaque fichier est enregistré sous l'empreinte SHA-256 de son contenu
+//! (`blobs/<empreinte>`), qui est vérifiée à chaque lecture. Les pièces
+//! jointes y sont chiffrées: le magasin ne voit que des données opaques.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, File},
+    io::{self, ErrorKind::NotFound, Write},
+    path::PathBuf,
+};
+
+use crate::utils::input_validation::InvalidInput;
+
+/// L'empreinte SHA-256 d'un fichier, en hexadécimal.
+///
+/// Elle est validée à la lecture, et peut donc servir de nom de fichier.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String")]
+pub struct BlobHash(String);
+
+impl BlobHash {
+    pub fn of(bytes: &[u8]) -> Self {
+        let digest = Sha256::digest(bytes);
+        Self(digest.iter().map(|byte| format!("{byte:02x}")).collect())
+    }
+}
+
+impl fmt::Display for BlobHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl TryFrom<String> for BlobHash {
+    type Error = InvalidInput;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let valid = value.len() == 64
+            && value
+                .bytes()
+                .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte));
+        if valid {
+            Ok(Self(value))
+        } else {
+            Err(InvalidInput)
+        }
+    }
+}
+
+/// Un magasin de fichiers adressés par leur contenu, dans un dossier ou
+/// seulement en mémoire
+#[derive(Debug, Default)]
+pub struct BlobStore {
+    dir: Option<PathBuf>,
+    memory: HashMap<BlobHash, Vec<u8>>,
+}
+
+impl BlobStore {
+    /// Ouvre le magasin d'un dossier, créé s'il n'existe pas
+    pub fn open(dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir: Some(dir),
+            memory: HashMap::new(),
+        })
+    }
+
+    /// Enregistre un fichier, s'il n'est pas déjà présent
+    pub fn put(&mut self, bytes: &[u8]) -> io::Result<BlobHash> {
+        let hash = BlobHash::of(bytes);
+        let Some(dir) = &self.dir else {
+            self.memory.insert(hash.clone(), bytes.to_vec());
+            return Ok(hash);
+        };
+
+        let path = dir.join(hash.to_string());
+        if path.exists() {
+            return Ok(hash);
+        }
+        // Un fichier interrompu ne porte jamais le nom d'une empreinte
+        let temp = dir.join(format!("{hash}.tmp"));
+        let mut file = File::create(&temp)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&temp, &path)?;
+        Ok(hash)
+    }
+
+    /// Lit un fichier, en vérifiant qu'il correspond toujours à son empreinte
+    pub fn get(&self, hash: &BlobHash) -> io::Result<Vec<u8>> {
+        let bytes = match &self.dir {
+            Some(dir) => fs::read(dir.join(hash.to_string()))?,
+            None => self
+                .memory
+                .get(hash)
+                .cloned()
+                .ok_or_else(|| io::Error::from(NotFound))?,
+        };
+        if BlobHash::of(&bytes) != *hash {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("blob {hash} does not match its hash"),
+            ));
+        }
+        Ok(bytes)
+    }
+
+    /// Supprime un fichier; un fichier déjà absent n'est pas une erreur
+    pub fn remove(&mut self, hash: &BlobHash) -> io::Result<()> {
+        let Some(dir) = &self.dir else {
+            self.memory.remove(hash);
+            return Ok(());
+        };
+        match fs::remove_file(dir.join(hash.to_string())) {
+            Err(e) if e.kind() != NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::UserID;
+
+    #[test]
+    fn test_blob_store() {
+        let dir = std::env::temp_dir().join(format!("karak-blobs-{}", UserID::new()));
+        let mut store = BlobStore::open(dir.clone()).unwrap();
+
+        let hash = store.put(b"%PDF-1.7").unwrap();
+        assert_eq!(store.put(b"%PDF-1.7").unwrap(), hash);
+        assert_eq!(store.get(&hash).unwrap(), b"%PDF-1.7");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // Un fichier modifié sur le disque est refusé
+        fs::write(dir.join(hash.to_string()), b"%PDF-1.6").unwrap();
+        assert_eq!(
+            store.get(&hash).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        store.remove(&hash).unwrap();
+        store.remove(&hash).unwrap();
+        assert_eq!(store.get(&hash).unwrap_err().kind(), NotFound);
+        fs::remove_dir(dir).unwrap();
+
+        // Une empreinte ne peut pas désigner un autre chemin
+        assert!(serde_json::from_str::<BlobHash>("\"../database.json\"").is_err());
+        assert!(BlobHash::try_from(hash.to_string()).is_ok());
+    }
+}
@@ -1,27 +1,179 @@
 //! Stockage des données en mémoire, avec sauvegarde en JSON
+//!
+//! Le fichier peut être chiffré (XChaCha20-Poly1305) sous une clé dérivée d'un
+//! secret de l'opérateur. Il commence alors par un en-tête versionné:
+//! `KARAKDB\0`, la version du format, puis le sel de dérivation de la clé.
+//!
+//! Chaque sauvegarde remplace le fichier de manière atomique, et les
+//! [`GENERATIONS`] versions précédentes sont conservées à côté (`database.json.1`
+//! étant la plus récente). Si le fichier principal est illisible à l'ouverture,
+//! la plus récente version lisible est utilisée.
+//!
+//! Entre deux sauvegardes, chaque modification est ajoutée à un journal
+//! (`database.json.journal`), rejoué à l'ouverture. Une entrée contient l'état
+//! complet de l'objet modifié et un numéro de séquence; la sauvegarde retient
+//! le dernier numéro qu'elle inclut, puis vide le journal.
+//!
+//! Un fichier d'un format plus ancien est migré à l'ouverture (voir [`migrations`]).
 
 use crate::{
     models::{MedicalReport, ReportID, UserData, UserID},
-    utils::input_validation::Username,
+    utils::{
+        crypto::{self, CryptoError, SecretKey, SALT_LEN},
+        input_validation::{AVSNumber, Username},
+        throttling::LoginAttempts,
+    },
 };
-use log::info;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use index::Index;
+use log::{info, warn};
+use migrations::{MigrationReport, SCHEMA_VERSION};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::{
     collections::HashMap,
-    fs::File,
-    io::{self, ErrorKind::NotFound},
-    path::PathBuf,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, ErrorKind::NotFound, Write},
+    path::{Path, PathBuf},
 };
 use thiserror::Error;
 
-// DO NOT MODIFY THIS FILE!!!
+mod index;
+pub mod migrations;
+pub mod sqlite;
 
-#[derive(Serialize, Deserialize, Default)]
+/// Début d'un fichier de base de données chiffré
+const MAGIC: &[u8; 8] = b"KARAKDB\0";
+/// Version du format chiffré: Argon2id (paramètres par défaut) et XChaCha20-Poly1305
+const FORMAT_VERSION: u8 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 1 + SALT_LEN;
+
+/// Nombre de versions précédentes du fichier conservées
+pub const GENERATIONS: usize = 3;
+
+/// Nombre d'entrées du journal après lequel la base est sauvegardée
+pub const COMPACTION_INTERVAL: u64 = 100;
+
+fn journal_path(path: &Path) -> PathBuf {
+    with_suffix(path, "journal")
+}
+
+/// Le chemin du fichier suivi d'une extension supplémentaire
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn generation_path(path: &Path, generation: usize) -> PathBuf {
+    with_suffix(path, &generation.to_string())
+}
+
+/// Assure que les renommages dans le dossier du fichier survivent à un crash
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    File::open(parent)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Database {
     #[serde(skip)]
     path: Option<PathBuf>,
+    #[serde(skip)]
+    encryption: Option<Encryption>,
+    /// Le fichier a été chargé depuis un format plus ancien
+    #[serde(skip)]
+    migrated: bool,
+    #[serde(skip)]
+    index: Index,
+    schema_version: u32,
     users: HashMap<UserID, UserData>,
     reports: HashMap<ReportID, MedicalReport>,
+    #[serde(default)]
+    login_attempts: HashMap<Username, LoginAttempts>,
+    /// Numéro de la dernière entrée du journal incluse dans la sauvegarde
+    #[serde(default)]
+    journal_seq: u64,
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self {
+            path: None,
+            encryption: None,
+            migrated: false,
+            index: Index::default(),
+            schema_version: SCHEMA_VERSION,
+            users: HashMap::new(),
+            reports: HashMap::new(),
+            login_attempts: HashMap::new(),
+            journal_seq: 0,
+        }
+    }
+}
+
+/// Une modification consignée dans le journal
+#[derive(Deserialize)]
+enum JournalEntry {
+    User(Box<UserData>),
+    Report(Box<MedicalReport>),
+    /// Un patient et l'ensemble de ses rapports, remplacés ensemble
+    Folder(Box<UserData>, Vec<MedicalReport>),
+    LoginAttempts(Username, Option<LoginAttempts>),
+}
+
+/// Une entrée du journal telle qu'elle est écrite, sans copier les données
+#[derive(Serialize)]
+enum JournalEntryRef<'a> {
+    User(&'a UserData),
+    Report(&'a MedicalReport),
+    Folder(&'a UserData, Vec<&'a MedicalReport>),
+    LoginAttempts(&'a Username, Option<&'a LoginAttempts>),
+}
+
+#[derive(Serialize, Deserialize)]
+struct JournalLine<E> {
+    seq: u64,
+    entry: E,
+}
+
+/// La clé de chiffrement du fichier, et le sel qui a servi à la dériver
+struct Encryption {
+    key: SecretKey,
+    salt: [u8; SALT_LEN],
+}
+
+impl Encryption {
+    fn derive(secret: &[u8], salt: [u8; SALT_LEN]) -> Result<Self, DBError> {
+        Ok(Self {
+            key: SecretKey::derive(secret, &salt)?,
+            salt,
+        })
+    }
+
+    fn header(&self) -> Vec<u8> {
+        let mut header = MAGIC.to_vec();
+        header.push(FORMAT_VERSION);
+        header.extend_from_slice(&self.salt);
+        header
+    }
+
+    /// Le contenu chiffré d'un fichier, précédé de l'en-tête
+    fn seal_file(&self, plaintext: &[u8]) -> Vec<u8> {
+        let mut bytes = self.header();
+        bytes.extend(crypto::seal(&self.key, plaintext, &self.header()));
+        bytes
+    }
 }
 
 #[derive(Debug, Error)]
@@ -30,84 +182,892 @@ pub enum DBError {
     InvalidUserID(UserID),
     #[error("User already exists: {username}")]
     UserAlreadyExists { username: Username },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("The database is encrypted, a key is required")]
+    MissingKey,
+    #[error("Cannot decrypt the database: {0}")]
+    Crypto(#[from] CryptoError),
+    #[error("The database is stored in clear text and must be migrated")]
+    PlaintextDatabase,
+    #[error("Unsupported database format version: {0}")]
+    UnsupportedVersion(u8),
+    #[error("Unsupported database schema version: {0}")]
+    UnsupportedSchema(u32),
+    #[error("Cannot migrate the database to schema version {version}: {reason}")]
+    MigrationFailed { version: u32, reason: String },
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+/// Un support de stockage pour les données de l'application.
+///
+/// Chaque modification est persistée par l'implémentation avant de retourner,
+/// et n'est appliquée en mémoire que si elle a pu l'être.
+/// Les données sont aussi gardées en mémoire, pour pouvoir en prêter des
+/// références.
+pub trait Storage {
+    fn get_user(&self, user: UserID) -> Result<&UserData, DBError>;
+
+    fn lookup_username(&self, name: &Username) -> Option<&UserData>;
+
+    /// Le patient dont le dossier porte ce numéro AVS
+    fn lookup_avs_number(&self, avs_number: &AVSNumber) -> Option<&UserData>;
+
+    /// Ajoute ou remplace un utilisateur
+    fn store_user(&mut self, data: UserData) -> Result<(), DBError>;
+
+    fn get_report(&self, report: ReportID) -> Option<&MedicalReport>;
+
+    /// Ajoute ou remplace un rapport
+    fn store_report(&mut self, report: MedicalReport) -> Result<(), DBError>;
+
+    fn list_reports(&self) -> Box<dyn Iterator<Item = &MedicalReport> + '_>;
+
+    /// Les rapports d'un patient
+    fn list_patient_reports(
+        &self,
+        patient: UserID,
+    ) -> Box<dyn Iterator<Item = &MedicalReport> + '_>;
+
+    /// Supprime tous les rapports d'un patient
+    fn remove_reports(&mut self, patient: UserID) -> Result<(), DBError>;
+
+    /// Les patients dont l'utilisateur est médecin traitant
+    fn get_patients(&self, doctor: UserID) -> Box<dyn Iterator<Item = UserID> + '_>;
+
+    /// Remplace en une seule opération un patient et l'ensemble de ses rapports
+    fn replace_folder(
+        &mut self,
+        patient: UserData,
+        reports: Vec<MedicalReport>,
+    ) -> Result<(), DBError>;
+
+    fn get_login_attempts(&self, name: &Username) -> Option<&LoginAttempts>;
+
+    /// Enregistre les tentatives de connexion pour un nom, ou les efface
+    fn store_login_attempts(
+        &mut self,
+        name: &Username,
+        attempts: Option<LoginAttempts>,
+    ) -> Result<(), DBError>;
+
+    /// Oublie les tentatives de connexion qui n'ont plus d'effet
+    fn prune_login_attempts(
+        &mut self,
+        expired: &dyn Fn(&LoginAttempts) -> bool,
+    ) -> Result<(), DBError>;
+
+    /// Écrit un état complet de la base, si le support en a besoin
+    fn save(&self) -> Result<(), DBError>;
+}
+
+impl dyn Storage {
+    /// Modifie une copie d'un utilisateur, puis l'enregistre
+    pub fn update_user<R>(
+        &mut self,
+        user: UserID,
+        f: impl FnOnce(&mut UserData) -> R,
+    ) -> Result<R, DBError> {
+        let mut data = self.get_user(user)?.clone();
+        let result = f(&mut data);
+        self.store_user(data)?;
+        Ok(result)
+    }
+
+    /// Modifie une copie d'un rapport, puis l'enregistre
+    pub fn update_report<R>(
+        &mut self,
+        report: ReportID,
+        f: impl FnOnce(&mut MedicalReport) -> R,
+    ) -> Result<Option<R>, DBError> {
+        let Some(report) = self.get_report(report) else {
+            return Ok(None);
+        };
+        let mut report = report.clone();
+        let result = f(&mut report);
+        self.store_report(report)?;
+        Ok(Some(result))
+    }
 }
 
 impl Database {
-    pub fn open(path: PathBuf) -> Result<Self, io::Error> {
-        match File::open(&path) {
-            // File successfuly opened
-            Ok(f) => {
-                let mut db: Self = serde_json::from_reader(f)?;
-                db.path = Some(path);
-                Ok(db)
+    /// Ouvre la base de données, en la créant si elle n'existe pas.
+    ///
+    /// Avec un secret, la base est chiffrée. Un fichier existant en clair
+    /// n'est pas ouvert silencieusement dans ce cas: l'erreur
+    /// [`DBError::PlaintextDatabase`] permet de proposer sa migration avec
+    /// [`Database::encrypt_legacy`].
+    ///
+    /// Si le fichier est illisible ou a disparu, la plus récente des versions
+    /// précédentes est chargée, et le fichier illisible est mis de côté
+    /// (`database.json.corrupt`).
+    pub fn open(path: PathBuf, secret: Option<&[u8]>) -> Result<Self, DBError> {
+        let primary = match Self::read(&path, secret) {
+            Ok(Some(db)) => return db.opened(path),
+            // Le problème vient du secret fourni ou de la version, pas du fichier
+            Err(
+                e @ (DBError::MissingKey
+                | DBError::PlaintextDatabase
+                | DBError::UnsupportedVersion(_)
+                | DBError::UnsupportedSchema(_)
+                | DBError::MigrationFailed { .. }),
+            ) => return Err(e),
+            other => other,
+        };
+
+        let recovered = match Self::recover(&path, secret) {
+            Ok(recovered) => recovered,
+            Err(e) => return Err(primary.err().unwrap_or(e)),
+        };
+        if let Some(db) = recovered {
+            if let Err(e) = &primary {
+                let corrupt = with_suffix(&path, "corrupt");
+                warn!("Unreadable DB file ({e}), moved to {}", corrupt.display());
+                fs::rename(&path, corrupt)?;
             }
+            return db.opened(path);
+        }
 
+        match primary {
+            Err(e) => Err(e),
             // Fichier non existant, on le crée
-            Err(not_found) if not_found.kind() == NotFound => {
+            Ok(_) => {
                 info!("DB file not found, creating new empty DB");
-                let mut new_db = Database::default();
-                new_db.path = Some(path);
+                let encryption = secret
+                    .map(|secret| Encryption::derive(secret, crypto::random_salt()))
+                    .transpose()?;
+                let new_db = Database {
+                    path: Some(path),
+                    encryption,
+                    ..Default::default()
+                };
 
                 // On vérifie la sauvegarde immédiatement pour diminuer le risque de perte de données
                 new_db.save()?;
                 Ok(new_db)
             }
+        }
+    }
+
+    /// Termine l'ouverture d'une base lue depuis `path`: rejoue le journal,
+    /// et sauvegarde immédiatement une base migrée
+    fn opened(mut self, path: PathBuf) -> Result<Self, DBError> {
+        self.path = Some(path);
+        self.replay_journal()?;
+        if self.migrated {
+            self.save()?;
+            self.migrated = false;
+        }
+        Ok(self)
+    }
+
+    /// Les migrations qu'appliquerait l'ouverture du fichier, sans le modifier
+    pub fn plan_migrations(
+        path: &Path,
+        secret: Option<&[u8]>,
+    ) -> Result<Vec<MigrationReport>, DBError> {
+        let (json, _) = Self::decrypt(&fs::read(path)?, secret)?;
+        let mut value: Value = serde_json::from_slice(&json).map_err(io::Error::from)?;
+        migrations::migrate(&mut value)
+    }
+
+    /// Lit un fichier de base de données, s'il existe
+    fn read(path: &Path, secret: Option<&[u8]>) -> Result<Option<Self>, DBError> {
+        match fs::read(path) {
+            Ok(bytes) => Self::decode(&bytes, secret).map(Some),
+            Err(not_found) if not_found.kind() == NotFound => Ok(None),
+            Err(other) => Err(other.into()),
+        }
+    }
+
+    /// Charge la plus récente des versions précédentes lisibles.
+    ///
+    /// Si des versions existent mais qu'aucune n'est lisible, l'erreur de la
+    /// plus ancienne est retournée plutôt que de créer une base vide.
+    fn recover(path: &Path, secret: Option<&[u8]>) -> Result<Option<Self>, DBError> {
+        let mut error = None;
+        for generation in 1..=GENERATIONS {
+            let backup = generation_path(path, generation);
+            match Self::read(&backup, secret) {
+                Ok(Some(db)) => {
+                    warn!("DB recovered from {}", backup.display());
+                    return Ok(Some(db));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("Unreadable DB backup {}: {e}", backup.display());
+                    error = Some(e);
+                }
+            }
+        }
+        error.map_or(Ok(None), Err)
+    }
 
-            // Autre erreur d'IO, on s'arrête
-            Err(other) => Err(other),
+    /// Chiffre une base de données existante stockée en clair
+    pub fn encrypt_legacy(path: PathBuf, secret: &[u8]) -> Result<Self, DBError> {
+        let mut db = Self::open(path, None)?;
+        if db.encryption.is_none() {
+            warn!("Encrypting legacy clear text database");
+            db.encryption = Some(Encryption::derive(secret, crypto::random_salt())?);
+            db.save()?;
+
+            // Les versions précédentes sont en clair
+            if let Some(path) = &db.path {
+                for generation in 1..=GENERATIONS {
+                    match fs::remove_file(generation_path(path, generation)) {
+                        Err(e) if e.kind() == NotFound => {}
+                        result => result?,
+                    }
+                }
+                db.encrypt_corrupt(path)?;
+            }
+        }
+        Ok(db)
+    }
+
+    /// Chiffre tel quel le fichier illisible mis de côté par [`Self::open`],
+    /// qui peut contenir des données en clair
+    fn encrypt_corrupt(&self, path: &Path) -> Result<(), DBError> {
+        let Some(encryption) = &self.encryption else {
+            return Ok(());
+        };
+        let corrupt = with_suffix(path, "corrupt");
+        let bytes = match fs::read(&corrupt) {
+            Err(e) if e.kind() == NotFound => return Ok(()),
+            result => result?,
+        };
+        if bytes.starts_with(MAGIC) {
+            return Ok(());
         }
+
+        warn!("Encrypting unreadable DB file {}", corrupt.display());
+        let temp = with_suffix(&corrupt, "tmp");
+        let mut file = File::create(&temp)?;
+        file.write_all(&encryption.seal_file(&bytes))?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&temp, &corrupt)?;
+        Ok(sync_parent_dir(path)?)
     }
 
+    fn decode(bytes: &[u8], secret: Option<&[u8]>) -> Result<Self, DBError> {
+        let (json, encryption) = Self::decrypt(bytes, secret)?;
+        let mut db = Self::from_json(&json)?;
+        db.encryption = encryption;
+        Ok(db)
+    }
+
+    /// Charge le JSON d'une sauvegarde, en migrant son format si nécessaire
+    fn from_json(json: &[u8]) -> Result<Self, DBError> {
+        let mut value: Value = serde_json::from_slice(json).map_err(io::Error::from)?;
+        let migrations = migrations::migrate(&mut value)?;
+        let mut db: Self = serde_json::from_value(value).map_err(io::Error::from)?;
+
+        for migration in &migrations {
+            info!(
+                "DB schema migrated to version {}: {} ({} changes)",
+                migration.version,
+                migration.description,
+                migration.changes.len()
+            );
+            for change in &migration.changes {
+                warn!("DB migration {}: {change}", migration.version);
+            }
+        }
+        db.migrated = !migrations.is_empty();
+        db.index = Index::new(&db.users, &db.reports);
+        Ok(db)
+    }
+
+    /// Le JSON contenu dans le fichier, et la clé qui a permis de le déchiffrer
+    fn decrypt(
+        bytes: &[u8],
+        secret: Option<&[u8]>,
+    ) -> Result<(Vec<u8>, Option<Encryption>), DBError> {
+        let Some(header) = bytes.strip_prefix(MAGIC) else {
+            if secret.is_some() {
+                return Err(DBError::PlaintextDatabase);
+            }
+            return Ok((bytes.to_vec(), None));
+        };
+
+        let secret = secret.ok_or(DBError::MissingKey)?;
+        match header.first() {
+            Some(&FORMAT_VERSION) if bytes.len() >= HEADER_LEN => {}
+            Some(&version) => return Err(DBError::UnsupportedVersion(version)),
+            None => return Err(CryptoError::Decryption.into()),
+        }
+
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(&bytes[MAGIC.len() + 1..HEADER_LEN]);
+        let encryption = Encryption::derive(secret, salt)?;
+
+        let json = crypto::open(&encryption.key, &bytes[HEADER_LEN..], &bytes[..HEADER_LEN])?;
+        Ok((json, Some(encryption)))
+    }
+
+    /// Le contenu du fichier, chiffré si la base a une clé
+    fn encode(&self) -> Result<Vec<u8>, io::Error> {
+        match &self.encryption {
+            Some(encryption) => Ok(encryption.seal_file(&serde_json::to_vec(self)?)),
+            None => Ok(serde_json::to_vec_pretty(self)?),
+        }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption.is_some()
+    }
+
+    /// Sauvegarde la base sans jamais laisser de fichier à moitié écrit:
+    /// le contenu est écrit et synchronisé dans un fichier temporaire, qui
+    /// remplace ensuite le fichier principal par un renommage atomique.
     pub fn save(&self) -> Result<(), io::Error> {
-        if let Some(path) = &self.path {
-            let file = File::create(path)?;
-            serde_json::to_writer_pretty(file, self)?;
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let bytes = self.encode()?;
+        let temp = with_suffix(path, "tmp");
+        let mut file = File::create(&temp)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        drop(file);
+
+        Self::rotate_generations(path)?;
+        fs::rename(&temp, path)?;
+        sync_parent_dir(path)?;
+
+        // Toutes les entrées du journal sont maintenant dans la sauvegarde
+        match fs::remove_file(journal_path(path)) {
+            Err(e) if e.kind() == NotFound => {}
+            result => result?,
+        }
+        sync_parent_dir(path)
+    }
+
+    /// Ajoute une entrée au journal et attend qu'elle soit sur le disque.
+    ///
+    /// Retourne le numéro de l'entrée, à confirmer avec [`Self::journal_written`].
+    fn write_journal(&self, entry: JournalEntryRef) -> Result<u64, DBError> {
+        let seq = self.journal_seq + 1;
+        let Some(path) = &self.path else {
+            return Ok(seq);
+        };
+
+        let json = serde_json::to_vec(&JournalLine { seq, entry }).map_err(io::Error::from)?;
+        let mut line = match &self.encryption {
+            Some(encryption) => {
+                BASE64.encode(crypto::seal(&encryption.key, &json, &encryption.header()))
+            }
+            None => String::from_utf8(json).expect("serde_json produces UTF-8"),
+        };
+        line.push('\n');
+
+        let mut journal = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(journal_path(path))?;
+        journal.write_all(line.as_bytes())?;
+        journal.sync_data()?;
+        Ok(seq)
+    }
+
+    /// Avance le numéro de séquence; la base est sauvegardée toutes les
+    /// [`COMPACTION_INTERVAL`] entrées
+    fn journal_written(&mut self, seq: u64) -> Result<(), DBError> {
+        self.journal_seq = seq;
+        if seq.is_multiple_of(COMPACTION_INTERVAL) {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Applique les entrées du journal plus récentes que la sauvegarde.
+    ///
+    /// Une ligne illisible (écriture interrompue par un crash) termine le journal.
+    fn replay_journal(&mut self) -> Result<(), DBError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let journal = match File::open(journal_path(path)) {
+            Ok(journal) => journal,
+            Err(not_found) if not_found.kind() == NotFound => return Ok(()),
+            Err(other) => return Err(other.into()),
+        };
+
+        let mut replayed = 0;
+        for (number, line) in BufReader::new(journal).lines().enumerate() {
+            let Some(JournalLine { seq, entry }) = self.decode_journal_line(&line?) else {
+                warn!("Journal truncated at line {}", number + 1);
+                break;
+            };
+            if seq <= self.journal_seq {
+                continue;
+            }
+
+            match entry {
+                JournalEntry::User(user) => self.index.put_user(&mut self.users, *user),
+                JournalEntry::Report(report) => self.index.put_report(&mut self.reports, *report),
+                JournalEntry::Folder(patient, reports) => self.put_folder(*patient, reports),
+                JournalEntry::LoginAttempts(name, Some(attempts)) => {
+                    self.login_attempts.insert(name, attempts);
+                }
+                JournalEntry::LoginAttempts(name, None) => {
+                    self.login_attempts.remove(&name);
+                }
+            }
+            self.journal_seq = seq;
+            replayed += 1;
+        }
+
+        if replayed > 0 {
+            info!("Replayed {replayed} journal entries");
         }
         Ok(())
     }
 
-    pub fn get_user(&self, user: UserID) -> Result<&UserData, DBError> {
+    fn decode_journal_line(&self, line: &str) -> Option<JournalLine<JournalEntry>> {
+        let json = match &self.encryption {
+            Some(encryption) => {
+                let sealed = BASE64.decode(line.trim()).ok()?;
+                crypto::open(&encryption.key, &sealed, &encryption.header()).ok()?
+            }
+            None => line.as_bytes().to_vec(),
+        };
+        serde_json::from_slice(&json).ok()
+    }
+
+    /// Remplace un patient et l'ensemble de ses rapports
+    fn put_folder(&mut self, patient: UserData, reports: Vec<MedicalReport>) {
+        self.index.remove_reports(&mut self.reports, patient.id);
+        for report in reports {
+            self.index.put_report(&mut self.reports, report);
+        }
+        self.index.put_user(&mut self.users, patient);
+    }
+
+    /// Décale les versions précédentes et y ajoute le fichier actuel,
+    /// qui reste en place jusqu'à son remplacement
+    fn rotate_generations(path: &Path) -> Result<(), io::Error> {
+        for generation in (1..GENERATIONS).rev() {
+            match fs::rename(
+                generation_path(path, generation),
+                generation_path(path, generation + 1),
+            ) {
+                Err(e) if e.kind() == NotFound => {}
+                result => result?,
+            }
+        }
+
+        let latest = generation_path(path, 1);
+        match fs::hard_link(path, &latest) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == NotFound => Ok(()),
+            // Système de fichiers sans liens physiques
+            Err(_) => fs::copy(path, &latest).map(drop),
+        }
+    }
+}
+
+impl Storage for Database {
+    fn get_user(&self, user: UserID) -> Result<&UserData, DBError> {
         self.users.get(&user).ok_or(DBError::InvalidUserID(user))
     }
 
-    pub fn get_user_mut(&mut self, user: UserID) -> Result<&mut UserData, DBError> {
-        self.users
-            .get_mut(&user)
-            .ok_or(DBError::InvalidUserID(user))
+    fn lookup_username(&self, name: &Username) -> Option<&UserData> {
+        self.index.lookup_username(&self.users, name)
     }
 
-    pub fn lookup_username(&self, name: &Username) -> Option<&UserData> {
-        self.users.values().find(|user| &user.username == name)
+    fn lookup_avs_number(&self, avs_number: &AVSNumber) -> Option<&UserData> {
+        self.index.lookup_avs_number(&self.users, avs_number)
     }
 
-    pub fn store_user(&mut self, data: UserData) {
-        self.users.insert(data.id, data);
+    fn store_user(&mut self, data: UserData) -> Result<(), DBError> {
+        let seq = self.write_journal(JournalEntryRef::User(&data))?;
+        self.index.put_user(&mut self.users, data);
+        self.journal_written(seq)
     }
 
-    pub fn get_report(&self, report: ReportID) -> Option<&MedicalReport> {
+    fn get_report(&self, report: ReportID) -> Option<&MedicalReport> {
         self.reports.get(&report)
     }
 
-    pub fn get_report_data_mut(&mut self, report: ReportID) -> Option<&mut String> {
-        Some(&mut self.reports.get_mut(&report)?.content)
+    fn store_report(&mut self, report: MedicalReport) -> Result<(), DBError> {
+        let seq = self.write_journal(JournalEntryRef::Report(&report))?;
+        self.index.put_report(&mut self.reports, report);
+        self.journal_written(seq)
+    }
+
+    fn list_reports(&self) -> Box<dyn Iterator<Item = &MedicalReport> + '_> {
+        Box::new(self.reports.values())
+    }
+
+    fn list_patient_reports(
+        &self,
+        patient: UserID,
+    ) -> Box<dyn Iterator<Item = &MedicalReport> + '_> {
+        Box::new(self.index.reports(&self.reports, patient))
+    }
+
+    fn remove_reports(&mut self, patient: UserID) -> Result<(), DBError> {
+        let seq =
+            self.write_journal(JournalEntryRef::Folder(self.get_user(patient)?, Vec::new()))?;
+        self.index.remove_reports(&mut self.reports, patient);
+        self.journal_written(seq)
+    }
+
+    fn get_patients(&self, doctor: UserID) -> Box<dyn Iterator<Item = UserID> + '_> {
+        Box::new(self.index.patients(doctor))
+    }
+
+    fn replace_folder(
+        &mut self,
+        patient: UserData,
+        reports: Vec<MedicalReport>,
+    ) -> Result<(), DBError> {
+        let seq =
+            self.write_journal(JournalEntryRef::Folder(&patient, reports.iter().collect()))?;
+        self.put_folder(patient, reports);
+        self.journal_written(seq)
+    }
+
+    fn get_login_attempts(&self, name: &Username) -> Option<&LoginAttempts> {
+        self.login_attempts.get(name)
+    }
+
+    fn store_login_attempts(
+        &mut self,
+        name: &Username,
+        attempts: Option<LoginAttempts>,
+    ) -> Result<(), DBError> {
+        let seq = self.write_journal(JournalEntryRef::LoginAttempts(name, attempts.as_ref()))?;
+        match attempts {
+            Some(attempts) => self.login_attempts.insert(name.clone(), attempts),
+            None => self.login_attempts.remove(name),
+        };
+        self.journal_written(seq)
     }
 
-    pub fn store_report(&mut self, report: MedicalReport) {
-        self.reports.insert(report.id, report);
+    fn prune_login_attempts(
+        &mut self,
+        expired: &dyn Fn(&LoginAttempts) -> bool,
+    ) -> Result<(), DBError> {
+        // Pas de journal: des entrées expirées rejouées n'ont aucun effet
+        self.login_attempts.retain(|_, attempts| !expired(attempts));
+        Ok(())
+    }
+
+    fn save(&self) -> Result<(), DBError> {
+        Ok(Database::save(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ReportContent;
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("karak-db-{}.json", UserID::new()))
+    }
+
+    /// Supprime le fichier et tout ce qui a été créé à côté
+    fn remove_files(path: &Path) {
+        let generations = (1..=GENERATIONS + 1).map(|generation| generation_path(path, generation));
+        let others = ["tmp", "corrupt", "journal"].map(|suffix| with_suffix(path, suffix));
+        for file in std::iter::once(path.to_owned())
+            .chain(generations)
+            .chain(others)
+        {
+            let _ = fs::remove_file(file);
+        }
     }
 
-    pub fn list_reports(&self) -> impl Iterator<Item = &MedicalReport> + '_ {
-        self.reports.values()
+    fn set_failures(db: &mut Database, failures: u32) {
+        let name = Username::new("patient".to_owned());
+        let attempts = LoginAttempts {
+            failures,
+            ..Default::default()
+        };
+        db.store_login_attempts(&name, Some(attempts)).unwrap();
     }
 
-    pub fn remove_reports(&mut self, patient: UserID) {
-        self.reports.retain(|_id, report| report.patient != patient);
+    fn failures(db: &Database) -> u32 {
+        db.get_login_attempts(&Username::new("patient".to_owned()))
+            .map_or(0, |attempts| attempts.failures)
     }
 
-    pub fn get_patients(&self, doctor: UserID) -> impl Iterator<Item = UserID> + '_ {
-        self.users
-            .values()
-            .filter(move |u| u.has_doctor(doctor))
-            .map(|u| u.id)
+    #[test]
+    fn test_encrypted_roundtrip() {
+        let path = temp_path();
+        let name = Username::new("patient".to_owned());
+
+        let mut db = Database::open(path.clone(), Some(b"passphrase")).unwrap();
+        set_failures(&mut db, 2);
+        db.save().unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        let reopened = Database::open(path.clone(), Some(b"passphrase"));
+        let wrong_key = Database::open(path.clone(), Some(b"wrong"));
+        let no_key = Database::open(path.clone(), None);
+        remove_files(&path);
+
+        assert!(bytes.starts_with(MAGIC));
+        assert!(!bytes.windows(7).any(|w| w == b"patient"));
+        assert_eq!(
+            reopened
+                .unwrap()
+                .get_login_attempts(&name)
+                .unwrap()
+                .failures,
+            2
+        );
+        assert!(matches!(wrong_key, Err(DBError::Crypto(_))));
+        assert!(matches!(no_key, Err(DBError::MissingKey)));
+    }
+
+    #[test]
+    fn test_legacy_migration() {
+        let path = temp_path();
+        let name = Username::new("patient".to_owned());
+
+        let mut db = Database::open(path.clone(), None).unwrap();
+        set_failures(&mut db, 1);
+        db.save().unwrap();
+        let corrupt = with_suffix(&path, "corrupt");
+        fs::write(&corrupt, br#"{"users": {"patient"#).unwrap();
+
+        let refused = Database::open(path.clone(), Some(b"passphrase"));
+        let migrated = Database::encrypt_legacy(path.clone(), b"passphrase").unwrap();
+        let reopened = Database::open(path.clone(), Some(b"passphrase"));
+        let plaintext_backup = generation_path(&path, 1).exists();
+        let corrupt = Database::decrypt(&fs::read(&corrupt).unwrap(), Some(b"passphrase"));
+        remove_files(&path);
+
+        assert!(matches!(refused, Err(DBError::PlaintextDatabase)));
+        assert!(!plaintext_backup);
+        // Le fichier illisible n'est plus en clair, mais reste récupérable
+        assert_eq!(corrupt.unwrap().0, br#"{"users": {"patient"#);
+        assert!(migrated.is_encrypted());
+        assert_eq!(
+            reopened
+                .unwrap()
+                .get_login_attempts(&name)
+                .unwrap()
+                .failures,
+            1
+        );
+    }
+
+    #[test]
+    fn test_save_keeps_generations() {
+        let path = temp_path();
+
+        let mut db = Database::open(path.clone(), None).unwrap();
+        for failures in 1..=5 {
+            set_failures(&mut db, failures);
+            db.save().unwrap();
+        }
+
+        let generations: Vec<u32> = (1..=GENERATIONS)
+            .map(|generation| {
+                failures(&Database::open(generation_path(&path, generation), None).unwrap())
+            })
+            .collect();
+        let extra = generation_path(&path, GENERATIONS + 1).exists();
+        let temp = with_suffix(&path, "tmp").exists();
+        let current = failures(&Database::open(path.clone(), None).unwrap());
+        remove_files(&path);
+
+        assert_eq!(current, 5);
+        assert_eq!(generations, [4, 3, 2]);
+        assert!(!extra);
+        assert!(!temp);
+    }
+
+    #[test]
+    fn test_open_falls_back_to_previous_generation() {
+        let path = temp_path();
+
+        let mut db = Database::open(path.clone(), Some(b"passphrase")).unwrap();
+        set_failures(&mut db, 1);
+        db.save().unwrap();
+        set_failures(&mut db, 2);
+        db.save().unwrap();
+
+        // Fichier tronqué par un crash pendant l'écriture
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+
+        let recovered = Database::open(path.clone(), Some(b"passphrase"));
+        let corrupt = with_suffix(&path, "corrupt").exists();
+        let wrong_key = Database::open(path.clone(), Some(b"wrong"));
+        remove_files(&path);
+
+        assert_eq!(failures(&recovered.unwrap()), 1);
+        assert!(corrupt);
+        assert!(matches!(wrong_key, Err(DBError::Crypto(_))));
+    }
+
+    #[test]
+    fn test_open_migrates_old_schema() {
+        let path = temp_path();
+        fs::write(&path, include_bytes!("../database.json.example")).unwrap();
+
+        let planned = Database::plan_migrations(&path, None).unwrap();
+        let untouched = fs::read_to_string(&path)
+            .unwrap()
+            .contains("medical_reports");
+        let db = Database::open(path.clone(), None).unwrap();
+        let saved: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        let backup = fs::read_to_string(generation_path(&path, 1)).unwrap();
+        let replanned = Database::plan_migrations(&path, None).unwrap();
+        remove_files(&path);
+
+        assert_eq!(planned.len(), SCHEMA_VERSION as usize);
+        assert!(untouched);
+        assert_eq!(db.list_reports().count(), 2);
+        assert_eq!(migrations::schema_version(&saved), SCHEMA_VERSION);
+        assert!(!saved.to_string().contains("medical_reports"));
+        assert!(backup.contains("medical_reports"));
+        assert!(replanned.is_empty());
+    }
+
+    #[test]
+    fn test_open_refuses_invalid_avs_number() {
+        let path = temp_path();
+        let mut old: Value =
+            serde_json::from_slice(include_bytes!("../database.json.example")).unwrap();
+        old["schema_version"] = 2.into();
+        old["users"]["3fa47071-25cd-4efb-bfcd-128f3344f2b2"]["medical_folder"]["personal_data"]
+            ["avs_number"] = "756.1234.5678.98".into();
+        let bytes = serde_json::to_vec(&old).unwrap();
+        fs::write(&path, &bytes).unwrap();
+
+        let opened = Database::open(path.clone(), None);
+        let untouched = fs::read(&path).unwrap() == bytes;
+        let set_aside = with_suffix(&path, "corrupt").exists();
+        remove_files(&path);
+
+        // Le fichier n'est ni remplacé par une version précédente ni mis de côté
+        match opened {
+            Err(DBError::MigrationFailed { version, reason }) => {
+                assert_eq!(version, 3);
+                assert!(reason.contains("patient"), "{reason}");
+            }
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+        assert!(untouched);
+        assert!(!set_aside);
+    }
+
+    fn report(patient: UserID, content: &str) -> MedicalReport {
+        MedicalReport {
+            id: ReportID::new(),
+            title: "Consultation".to_owned(),
+            author: patient,
+            patient,
+            content: ReportContent::Plain(content.to_owned()),
+            details: None,
+            attachments: Vec::new(),
+            revisions: Vec::new(),
+            provenance: Default::default(),
+            parent: None,
+            signature: None,
+            seal: None,
+        }
+    }
+
+    fn content(db: &Database, report: ReportID) -> &str {
+        match &db.get_report(report).unwrap().content {
+            ReportContent::Plain(text) => text,
+            ReportContent::Sealed(_) => panic!("reports are stored in clear in these tests"),
+        }
+    }
+
+    #[test]
+    fn test_journal_replay() {
+        let path = temp_path();
+        let report = report(UserID::new(), "Grippe");
+        let report_id = report.id;
+
+        let mut db = Database::open(path.clone(), Some(b"passphrase")).unwrap();
+        db.store_report(report).unwrap();
+        set_failures(&mut db, 3);
+        // Le processus est tué sans sauvegarde, au milieu d'une écriture
+        drop(db);
+        let journal = journal_path(&path);
+        let mut file = fs::OpenOptions::new().append(true).open(&journal).unwrap();
+        file.write_all(b"AAAA").unwrap();
+        drop(file);
+
+        let journal_bytes = fs::read(&journal).unwrap();
+        let journal_in_clear = journal_bytes.windows(6).any(|w| w == b"Grippe");
+        let mut db = Database::open(path.clone(), Some(b"passphrase")).unwrap();
+        let replayed = (content(&db, report_id).to_owned(), failures(&db));
+
+        // Une modification sauvegardée sans passer par le journal n'est pas
+        // écrasée par d'anciennes entrées, même si le journal n'a pas été vidé
+        db.reports.get_mut(&report_id).unwrap().content = ReportContent::Plain("Angine".to_owned());
+        db.save().unwrap();
+        let compacted = !journal.exists();
+        fs::write(&journal, journal_bytes).unwrap();
+        let reopened = Database::open(path.clone(), Some(b"passphrase")).unwrap();
+        remove_files(&path);
+
+        assert!(!journal_in_clear);
+        assert_eq!(replayed, ("Grippe".to_owned(), 3));
+        assert!(compacted);
+        assert_eq!(content(&reopened, report_id), "Angine");
+        assert_eq!(failures(&reopened), 3);
+    }
+
+    #[test]
+    fn test_journal_compaction() {
+        let path = temp_path();
+
+        let mut db = Database::open(path.clone(), None).unwrap();
+        for failures in 1..COMPACTION_INTERVAL {
+            set_failures(&mut db, failures as u32);
+        }
+        let before = journal_path(&path).exists();
+        set_failures(&mut db, COMPACTION_INTERVAL as u32 - 1);
+        let after = journal_path(&path).exists();
+        let snapshot = fs::read(&path).unwrap();
+        remove_files(&path);
+
+        assert!(before);
+        assert!(!after);
+        let snapshot: Database = serde_json::from_slice(&snapshot).unwrap();
+        assert_eq!(failures(&snapshot), COMPACTION_INTERVAL as u32 - 1);
+        assert_eq!(snapshot.journal_seq, COMPACTION_INTERVAL);
+    }
+
+    #[test]
+    fn test_failed_journal_write_changes_nothing() {
+        let path = temp_path();
+        let patient = UserID::new();
+        let report = report(patient, "Grippe");
+        let report_id = report.id;
+
+        let mut db = Database::open(path.clone(), None).unwrap();
+        // Le journal ne peut pas être ouvert en écriture
+        fs::create_dir(journal_path(&path)).unwrap();
+        let stored = db.store_report(report);
+        let attempts = LoginAttempts {
+            failures: 1,
+            ..Default::default()
+        };
+        let name = Username::new("patient".to_owned());
+        let attempted = db.store_login_attempts(&name, Some(attempts));
+        fs::remove_dir(journal_path(&path)).unwrap();
+        remove_files(&path);
+
+        assert!(stored.is_err());
+        assert!(attempted.is_err());
+        assert!(db.get_report(report_id).is_none());
+        assert!(db.list_patient_reports(patient).next().is_none());
+        assert_eq!(failures(&db), 0);
     }
 }
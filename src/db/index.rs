@@ -0,0 +1,283 @@
+//! Index secondaires des données gardées en mémoire
+//!
+//! Les utilisateurs et les rapports restent dans leurs tables, indexées par
+//! identifiant; l'index permet de retrouver un utilisateur par son nom, les
+//! patients d'un médecin et les rapports d'un patient sans tout parcourir.
+//! Toute modification des tables passe par l'index pour le garder cohérent.
+
+use crate::{
+    models::{MedicalReport, ReportID, UserData, UserID},
+    utils::input_validation::{AVSNumber, Username},
+};
+use log::error;
+use std::collections::{hash_map::Entry, BTreeSet, HashMap};
+
+pub type Users = HashMap<UserID, UserData>;
+pub type Reports = HashMap<ReportID, MedicalReport>;
+
+#[derive(Default)]
+pub struct Index {
+    usernames: HashMap<Username, UserID>,
+    avs_numbers: HashMap<AVSNumber, UserID>,
+    /// Les patients de chaque médecin traitant
+    patients: HashMap<UserID, BTreeSet<UserID>>,
+    /// Les rapports de chaque patient
+    reports: HashMap<UserID, BTreeSet<ReportID>>,
+}
+
+impl Index {
+    pub fn new(users: &Users, reports: &Reports) -> Self {
+        let mut index = Self::default();
+        users
+            .values()
+            .for_each(|user| index.insert_user(users, user));
+        reports
+            .values()
+            .for_each(|report| index.insert_report(report));
+        index
+    }
+
+    fn insert_user(&mut self, users: &Users, user: &UserData) {
+        self.claim_username(users, user);
+        if let Some(folder) = &user.medical_folder {
+            let avs_number = folder.personal_data.avs_number.clone();
+            self.avs_numbers.insert(avs_number, user.id);
+        }
+        for doctor in user.medical_folder.iter().flat_map(|f| &f.doctors) {
+            self.patients.entry(*doctor).or_default().insert(user.id);
+        }
+    }
+
+    /// Associe le nom à l'utilisateur, sauf s'il appartient à un compte plus
+    /// ancien: deux noms de même forme canonique désignent toujours le même
+    /// compte, quel que soit l'ordre de chargement
+    fn claim_username(&mut self, users: &Users, user: &UserData) {
+        let rank = |user: &UserData| (user.provenance.created_at, user.id);
+        let mut entry = match self.usernames.entry(user.username.clone()) {
+            Entry::Vacant(entry) => {
+                entry.insert(user.id);
+                return;
+            }
+            Entry::Occupied(entry) if *entry.get() == user.id => return,
+            Entry::Occupied(entry) => entry,
+        };
+        let (winner, loser) = match users.get(entry.get()) {
+            Some(holder) if rank(holder) <= rank(user) => (holder.id, user.id),
+            _ => (user.id, std::mem::replace(entry.get_mut(), user.id)),
+        };
+        error!(
+            "Username {} of user {loser} already belongs to user {winner}, ignored",
+            user.username
+        );
+    }
+
+    fn remove_user(&mut self, user: &UserData) {
+        if self.usernames.get(&user.username) == Some(&user.id) {
+            self.usernames.remove(&user.username);
+        }
+        if let Some(folder) = &user.medical_folder {
+            let avs_number = &folder.personal_data.avs_number;
+            if self.avs_numbers.get(avs_number) == Some(&user.id) {
+                self.avs_numbers.remove(avs_number);
+            }
+        }
+        for doctor in user.medical_folder.iter().flat_map(|f| &f.doctors) {
+            remove_from(&mut self.patients, doctor, &user.id);
+        }
+    }
+
+    fn insert_report(&mut self, report: &MedicalReport) {
+        self.reports
+            .entry(report.patient)
+            .or_default()
+            .insert(report.id);
+    }
+
+    fn remove_report(&mut self, report: &MedicalReport) {
+        remove_from(&mut self.reports, &report.patient, &report.id);
+    }
+
+    /// Ajoute ou remplace un utilisateur
+    pub fn put_user(&mut self, users: &mut Users, user: UserData) {
+        if let Some(old) = users.get(&user.id) {
+            self.remove_user(old);
+        }
+        self.insert_user(users, &user);
+        users.insert(user.id, user);
+    }
+
+    /// Ajoute ou remplace un rapport
+    pub fn put_report(&mut self, reports: &mut Reports, report: MedicalReport) {
+        if let Some(old) = reports.remove(&report.id) {
+            self.remove_report(&old);
+        }
+        self.insert_report(&report);
+        reports.insert(report.id, report);
+    }
+
+    /// Supprime tous les rapports d'un patient
+    pub fn remove_reports(&mut self, reports: &mut Reports, patient: UserID) {
+        for id in self.reports.remove(&patient).unwrap_or_default() {
+            reports.remove(&id);
+        }
+    }
+
+    pub fn lookup_username<'a>(&self, users: &'a Users, name: &Username) -> Option<&'a UserData> {
+        users.get(self.usernames.get(name)?)
+    }
+
+    pub fn lookup_avs_number<'a>(
+        &self,
+        users: &'a Users,
+        avs_number: &AVSNumber,
+    ) -> Option<&'a UserData> {
+        users.get(self.avs_numbers.get(avs_number)?)
+    }
+
+    pub fn patients(&self, doctor: UserID) -> impl Iterator<Item = UserID> + '_ {
+        self.patients.get(&doctor).into_iter().flatten().copied()
+    }
+
+    pub fn reports<'a>(
+        &'a self,
+        reports: &'a Reports,
+        patient: UserID,
+    ) -> impl Iterator<Item = &'a MedicalReport> + 'a {
+        self.reports
+            .get(&patient)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| reports.get(id))
+    }
+}
+
+fn remove_from<K: Eq + std::hash::Hash, V: Ord>(
+    map: &mut HashMap<K, BTreeSet<V>>,
+    key: &K,
+    value: &V,
+) {
+    if let Some(set) = map.get_mut(key) {
+        set.remove(value);
+        if set.is_empty() {
+            map.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{MedicalFolder, PersonalData, ReportContent, Role};
+    use crate::utils::password_utils::hash;
+
+    fn user(name: &str, doctors: &[UserID]) -> UserData {
+        let mut folder = MedicalFolder::new(PersonalData::new(
+            "756.1234.5678.97".to_owned().try_into().unwrap(),
+            crate::models::BloodType::O,
+        ));
+        folder.doctors.extend(doctors);
+        UserData {
+            id: UserID::new(),
+            role: Role::Patient,
+            username: Username::new(name.to_owned()),
+            password: hash("password"),
+            must_change_password: false,
+            second_factor: None,
+            keys: None,
+            signing_keys: None,
+            medical_folder: Some(folder),
+            provenance: Default::default(),
+        }
+    }
+
+    fn report(patient: UserID) -> MedicalReport {
+        MedicalReport {
+            id: ReportID::new(),
+            title: "Consultation".to_owned(),
+            author: patient,
+            patient,
+            content: ReportContent::Plain(String::new()),
+            details: None,
+            attachments: Vec::new(),
+            revisions: Vec::new(),
+            provenance: Default::default(),
+            parent: None,
+            signature: None,
+            seal: None,
+        }
+    }
+
+    #[test]
+    fn test_index_follows_updates() {
+        let (mut users, mut reports) = (Users::new(), Reports::new());
+        let (doctor, other) = (UserID::new(), UserID::new());
+        let mut alice = user("alice", &[doctor]);
+        let mut index = Index::new(&users, &reports);
+        index.put_user(&mut users, alice.clone());
+        index.put_report(&mut reports, report(alice.id));
+
+        assert_eq!(
+            index.lookup_username(&users, &alice.username).unwrap().id,
+            alice.id
+        );
+        assert_eq!(index.patients(doctor).collect::<Vec<_>>(), [alice.id]);
+        assert_eq!(index.reports(&reports, alice.id).count(), 1);
+        let avs_number = alice
+            .medical_folder
+            .as_ref()
+            .unwrap()
+            .personal_data
+            .avs_number
+            .clone();
+        assert_eq!(
+            index.lookup_avs_number(&users, &avs_number).unwrap().id,
+            alice.id
+        );
+
+        // Renommage et changement de médecin
+        alice.username = Username::new("alice2".to_owned());
+        let folder = alice.medical_folder.as_mut().unwrap();
+        folder.doctors.remove(&doctor);
+        folder.doctors.insert(other);
+        index.put_user(&mut users, alice.clone());
+
+        assert!(index
+            .lookup_username(&users, &Username::new("alice".to_owned()))
+            .is_none());
+        assert!(index.lookup_username(&users, &alice.username).is_some());
+        assert_eq!(index.patients(doctor).count(), 0);
+        assert_eq!(index.patients(other).collect::<Vec<_>>(), [alice.id]);
+
+        index.remove_reports(&mut reports, alice.id);
+        assert_eq!(index.reports(&reports, alice.id).count(), 0);
+        assert!(reports.is_empty());
+
+        // Reconstruit depuis les tables, l'index est identique
+        let rebuilt = Index::new(&users, &reports);
+        assert_eq!(rebuilt.usernames, index.usernames);
+        assert_eq!(rebuilt.avs_numbers, index.avs_numbers);
+        assert_eq!(rebuilt.patients, index.patients);
+        assert_eq!(rebuilt.reports, index.reports);
+    }
+
+    #[test]
+    fn test_username_collision() {
+        let mut older = user("alice", &[]);
+        older.provenance.created_at = Some(chrono::DateTime::UNIX_EPOCH);
+        let mut newer = user("ALICE", &[]);
+        newer.provenance.created_at = Some(chrono::Utc::now());
+
+        // Le compte le plus ancien garde le nom, dans quelque ordre qu'ils arrivent
+        for order in [[&older, &newer], [&newer, &older]] {
+            let (mut users, reports) = (Users::new(), Reports::new());
+            let mut index = Index::new(&users, &reports);
+            for user in order {
+                index.put_user(&mut users, user.clone());
+            }
+            let found = index.lookup_username(&users, &older.username).unwrap();
+            assert_eq!(found.id, older.id);
+            let rebuilt = Index::new(&users, &reports);
+            assert_eq!(rebuilt.usernames, index.usernames);
+        }
+    }
+}
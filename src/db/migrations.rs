@@ -0,0 +1,380 @@
+//! Migrations du format de la base de données JSON
+//!
+//! Chaque sauvegarde porte un `schema_version`; un fichier qui n'en a pas est
+//! en version 0. À l'ouverture, un fichier plus ancien est mis à jour étape
+//! par étape sur sa représentation JSON, avant d'être chargé.
+//!
+//! Les entrées du journal ne sont pas migrées: elles sont rejouées sur la
+//! base migrée, qui est aussitôt sauvegardée.
+
+use super::DBError;
+use crate::utils::input_validation::{canonical_username, AVSNumber};
+use chrono::DateTime;
+use serde_json::{Map, Value};
+use std::{collections::BTreeMap, io};
+
+/// Version du format écrite par cette version de l'application
+pub const SCHEMA_VERSION: u32 = 4;
+
+/// La base au format JSON, telle que la modifient les migrations
+type Db = Map<String, Value>;
+
+/// Une étape de migration, de `version - 1` à `version`
+struct Migration {
+    version: u32,
+    description: &'static str,
+    /// Modifie la base et décrit chaque changement effectué, ou explique
+    /// pourquoi le fichier ne peut pas être migré sans intervention
+    apply: fn(&mut Db) -> Result<Vec<String>, String>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "suppression de la liste de rapports des dossiers médicaux",
+        apply: |db| Ok(remove_folder_reports(db)),
+    },
+    Migration {
+        version: 2,
+        description: "noms d'utilisateur comparés sans tenir compte de la casse",
+        apply: |db| Ok(report_username_collisions(db)),
+    },
+    Migration {
+        version: 3,
+        description: "numéros AVS enregistrés sous leur forme à 13 chiffres",
+        apply: normalise_avs_numbers,
+    },
+    Migration {
+        version: 4,
+        description: "nouveaux champs des données personnelles",
+        apply: |db| Ok(add_personal_data_fields(db)),
+    },
+];
+
+/// Les changements effectués par une étape de migration
+#[derive(Debug)]
+pub struct MigrationReport {
+    pub version: u32,
+    pub description: &'static str,
+    pub changes: Vec<String>,
+}
+
+/// La version du format d'une base de données
+pub fn schema_version(db: &Value) -> u32 {
+    db.get("schema_version")
+        .and_then(Value::as_u64)
+        .map_or(0, |version| version as u32)
+}
+
+/// Met à jour une base de données vers [`SCHEMA_VERSION`]
+pub fn migrate(db: &mut Value) -> Result<Vec<MigrationReport>, DBError> {
+    let version = schema_version(db);
+    if version > SCHEMA_VERSION {
+        return Err(DBError::UnsupportedSchema(version));
+    }
+    let Some(db) = db.as_object_mut() else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "DB is not a JSON object").into());
+    };
+
+    MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > version)
+        .map(|migration| {
+            let changes = (migration.apply)(db).map_err(|reason| DBError::MigrationFailed {
+                version: migration.version,
+                reason,
+            })?;
+            db.insert("schema_version".to_owned(), migration.version.into());
+            Ok(MigrationReport {
+                version: migration.version,
+                description: migration.description,
+                changes,
+            })
+        })
+        .collect()
+}
+
+/// Les rapports sont stockés à part depuis longtemps; les dossiers contenaient
+/// encore un champ `medical_reports` ignoré au chargement
+fn remove_folder_reports(db: &mut Map<String, Value>) -> Vec<String> {
+    let Some(users) = db.get_mut("users").and_then(Value::as_object_mut) else {
+        return Vec::new();
+    };
+
+    users
+        .values_mut()
+        .filter_map(|user| {
+            let folder = user.get_mut("medical_folder")?.as_object_mut()?;
+            let reports = folder.remove("medical_reports")?;
+            let count = reports.as_array().map_or(0, Vec::len);
+            let username = user.get("username").and_then(Value::as_str).unwrap_or("?");
+            Some(format!(
+                "{username}: champ medical_folder.medical_reports supprimé ({count} entrées)"
+            ))
+        })
+        .collect()
+}
+
+/// Les noms d'utilisateur sont désormais uniques à la normalisation Unicode
+/// et à la casse près. Les comptes existants qui entrent en collision ne
+/// peuvent pas être renommés automatiquement: ils sont signalés, et seul le
+/// plus ancien (par date de création, puis par identifiant) reste accessible
+/// par son nom, comme le décide l'index à l'ouverture.
+fn report_username_collisions(db: &mut Map<String, Value>) -> Vec<String> {
+    let Some(users) = db.get("users").and_then(Value::as_object) else {
+        return Vec::new();
+    };
+
+    // Les comptes de chaque nom canonique, du plus ancien au plus récent
+    let mut names: BTreeMap<String, Vec<_>> = BTreeMap::new();
+    for (id, user) in users {
+        let Some(username) = user.get("username").and_then(Value::as_str) else {
+            continue;
+        };
+        let created_at = user
+            .get("created_at")
+            .and_then(Value::as_str)
+            .and_then(|date| DateTime::parse_from_rfc3339(date).ok());
+        names
+            .entry(canonical_username(username))
+            .or_default()
+            .push((created_at, id.as_str(), username));
+    }
+
+    names
+        .into_iter()
+        .filter(|(_, accounts)| accounts.len() > 1)
+        .map(|(canonical, mut accounts)| {
+            accounts.sort_unstable();
+            let kept = accounts[0].2;
+            let mut usernames: Vec<_> = accounts.iter().map(|account| account.2).collect();
+            usernames.sort_unstable();
+            format!(
+                "collision: {} ont le même nom canonique '{canonical}', seul {kept} reste accessible par ce nom",
+                usernames.join(", ")
+            )
+        })
+        .collect()
+}
+
+/// Les numéros AVS étaient enregistrés tels que saisis, avec ou sans points.
+/// Les doublons ainsi révélés sont signalés, masqués, sans être modifiés.
+///
+/// Un numéro invalide ne peut plus être chargé: le fichier n'est pas migré
+/// tant qu'il n'a pas été corrigé, et les comptes concernés sont nommés.
+fn normalise_avs_numbers(db: &mut Map<String, Value>) -> Result<Vec<String>, String> {
+    let Some(users) = db.get_mut("users").and_then(Value::as_object_mut) else {
+        return Ok(Vec::new());
+    };
+
+    let mut changes = Vec::new();
+    let mut invalid = Vec::new();
+    // Les patients de chaque numéro, et sa forme masquée
+    let mut numbers: BTreeMap<String, (String, Vec<String>)> = BTreeMap::new();
+    for user in users.values_mut() {
+        let username = user
+            .get("username")
+            .and_then(Value::as_str)
+            .unwrap_or("?")
+            .to_owned();
+        let Some(avs_number) = user
+            .pointer_mut("/medical_folder/personal_data/avs_number")
+            .filter(|value| value.is_string())
+        else {
+            continue;
+        };
+        let Ok(parsed) = AVSNumber::parse_lenient(avs_number.as_str().unwrap_or_default()) else {
+            invalid.push(username);
+            continue;
+        };
+
+        if avs_number.as_str() != Some(parsed.digits()) {
+            *avs_number = parsed.digits().into();
+            changes.push(format!(
+                "{username}: numéro AVS {} normalisé",
+                parsed.masked()
+            ));
+        }
+        numbers
+            .entry(parsed.digits().to_owned())
+            .or_insert_with(|| (parsed.masked(), Vec::new()))
+            .1
+            .push(username);
+    }
+
+    for (masked, mut usernames) in numbers.into_values() {
+        if usernames.len() > 1 {
+            usernames.sort_unstable();
+            changes.push(format!(
+                "doublon: {} ont le même numéro AVS {masked}",
+                usernames.join(", ")
+            ));
+        }
+    }
+    if !invalid.is_empty() {
+        invalid.sort_unstable();
+        return Err(format!(
+            "numéro AVS invalide pour {}, à corriger dans le fichier",
+            invalid.join(", ")
+        ));
+    }
+    Ok(changes)
+}
+
+/// Champs ajoutés aux données personnelles, vides dans les anciens dossiers
+const PERSONAL_DATA_FIELDS: &[&str] = &[
+    "rhesus",
+    "full_name",
+    "date_of_birth",
+    "sex",
+    "address",
+    "emergency_contact",
+    "insurance_number",
+];
+
+fn add_personal_data_fields(db: &mut Map<String, Value>) -> Vec<String> {
+    let Some(users) = db.get_mut("users").and_then(Value::as_object_mut) else {
+        return Vec::new();
+    };
+
+    users
+        .values_mut()
+        .filter_map(|user| {
+            let username = user
+                .get("username")
+                .and_then(Value::as_str)
+                .unwrap_or("?")
+                .to_owned();
+            let data = user
+                .pointer_mut("/medical_folder/personal_data")?
+                .as_object_mut()?;
+            let added = PERSONAL_DATA_FIELDS
+                .iter()
+                .filter(|&&field| {
+                    let missing = !data.contains_key(field);
+                    data.entry(field).or_insert(Value::Null);
+                    missing
+                })
+                .count();
+            (added > 0).then(|| format!("{username}: {added} champs vides ajoutés"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &[u8] = include_bytes!("../../database.json.example");
+
+    #[test]
+    fn test_migrate_example() {
+        let mut db: Value = serde_json::from_slice(EXAMPLE).unwrap();
+        assert_eq!(schema_version(&db), 0);
+
+        let reports = migrate(&mut db).unwrap();
+        assert_eq!(schema_version(&db), SCHEMA_VERSION);
+        assert_eq!(reports.len(), 4);
+        assert_eq!(
+            reports[0].changes,
+            ["patient: champ medical_folder.medical_reports supprimé (0 entrées)"]
+        );
+        assert!(reports[1].changes.is_empty());
+        assert_eq!(
+            reports[2].changes,
+            ["patient: numéro AVS 756.****.****.97 normalisé"]
+        );
+        assert_eq!(
+            db.pointer("/users/3fa47071-25cd-4efb-bfcd-128f3344f2b2/medical_folder/personal_data/avs_number"),
+            Some(&Value::from("7561234567897"))
+        );
+        assert!(!db.to_string().contains("medical_reports"));
+        assert_eq!(reports[3].changes, ["patient: 7 champs vides ajoutés"]);
+        assert_eq!(
+            db.pointer("/users/3fa47071-25cd-4efb-bfcd-128f3344f2b2/medical_folder/personal_data/full_name"),
+            Some(&Value::Null)
+        );
+
+        // Une base à jour n'est pas modifiée
+        let before = db.clone();
+        assert!(migrate(&mut db).unwrap().is_empty());
+        assert_eq!(db, before);
+    }
+
+    #[test]
+    fn test_username_collisions_reported() {
+        let mut db = serde_json::json!({
+            "schema_version": 1,
+            "users": {
+                "1": { "username": "Admin", "created_at": "2024-05-02T08:00:00Z" },
+                "2": { "username": "admin" },
+                "3": { "username": "ＡＤＭＩＮ" },
+                "4": { "username": "medecin1" },
+                "5": { "username": "Medecin2", "created_at": "2024-05-02T08:00:00.5Z" },
+                "6": { "username": "medecin2", "created_at": "2024-05-02T08:00:00Z" },
+            },
+        });
+
+        // Un compte sans date de création est antérieur aux autres
+        let reports = migrate(&mut db).unwrap();
+        assert_eq!(
+            reports[0].changes,
+            [
+                "collision: Admin, admin, ＡＤＭＩＮ ont le même nom canonique 'admin', seul admin reste accessible par ce nom",
+                "collision: Medecin2, medecin2 ont le même nom canonique 'medecin2', seul medecin2 reste accessible par ce nom",
+            ]
+        );
+        assert_eq!(db["users"]["3"]["username"], "ＡＤＭＩＮ");
+    }
+
+    #[test]
+    fn test_avs_duplicates_reported() {
+        let folder =
+            |avs_number: &str| serde_json::json!({ "personal_data": { "avs_number": avs_number } });
+        let mut db = serde_json::json!({
+            "schema_version": 2,
+            "users": {
+                "1": { "username": "alice", "medical_folder": folder("756.1234.5678.97") },
+                "2": { "username": "bob", "medical_folder": folder("7561234567897") },
+                "3": { "username": "carol", "medical_folder": folder("756.0905.7171.04") },
+                "4": { "username": "dave", "medical_folder": null },
+            },
+        });
+
+        let reports = migrate(&mut db).unwrap();
+        assert_eq!(
+            reports[0].changes,
+            [
+                "alice: numéro AVS 756.****.****.97 normalisé",
+                "carol: numéro AVS 756.****.****.04 normalisé",
+                "doublon: alice, bob ont le même numéro AVS 756.****.****.97",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_avs_number_rejected() {
+        let mut db = serde_json::json!({
+            "schema_version": 2,
+            "users": {
+                "1": { "username": "alice", "medical_folder": { "personal_data": { "avs_number": "756.1234.5678.98" } } },
+                "2": { "username": "bob", "medical_folder": { "personal_data": { "avs_number": "7561234567897" } } },
+            },
+        });
+
+        assert!(matches!(
+            migrate(&mut db),
+            Err(DBError::MigrationFailed { version: 3, reason })
+                if reason == "numéro AVS invalide pour alice, à corriger dans le fichier"
+        ));
+    }
+
+    #[test]
+    fn test_newer_schema_rejected() {
+        let mut db = serde_json::json!({ "schema_version": SCHEMA_VERSION + 1 });
+        assert!(matches!(
+            migrate(&mut db),
+            Err(DBError::UnsupportedSchema(version)) if version == SCHEMA_VERSION + 1
+        ));
+    }
+}
@@ -0,0 +1,378 @@
+//! Stockage dans une base SQLite embarquée
+//!
+//! Chaque utilisateur et chaque rapport est une ligne contenant sa
+//! représentation JSON, chiffrée comme le fichier JSON si la base a une clé.
+//! Les tentatives de connexion sont stockées ensemble dans la table `meta`.
+//!
+//! Toutes les lignes sont chargées à l'ouverture; chaque modification est
+//! écrite immédiatement, dans une transaction.
+
+use super::{index::Index, DBError, Encryption, Storage};
+use crate::{
+    models::{MedicalReport, ReportID, UserData, UserID},
+    utils::{
+        crypto::{self, CryptoError, SALT_LEN},
+        input_validation::{AVSNumber, Username},
+        throttling::LoginAttempts,
+    },
+};
+use log::info;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{collections::HashMap, io, path::Path};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS meta (key TEXT PRIMARY KEY, value BLOB NOT NULL);
+    CREATE TABLE IF NOT EXISTS users (id TEXT PRIMARY KEY, data BLOB NOT NULL);
+    CREATE TABLE IF NOT EXISTS reports (
+        id TEXT PRIMARY KEY,
+        patient TEXT NOT NULL,
+        data BLOB NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS reports_by_patient ON reports (patient);
+";
+
+/// Valeur chiffrée à l'ouverture pour vérifier la clé, même si la base est vide
+const KEY_CHECK: &[u8] = b"karak";
+
+pub struct SqliteDatabase {
+    conn: Connection,
+    encryption: Option<Encryption>,
+    users: HashMap<UserID, UserData>,
+    reports: HashMap<ReportID, MedicalReport>,
+    login_attempts: HashMap<Username, LoginAttempts>,
+    index: Index,
+}
+
+impl SqliteDatabase {
+    /// Ouvre la base SQLite, en la créant si elle n'existe pas.
+    ///
+    /// Comme pour le fichier JSON, une base existante en clair n'est pas
+    /// ouverte avec un secret ([`DBError::PlaintextDatabase`]).
+    pub fn open(path: &Path, secret: Option<&[u8]>) -> Result<Self, DBError> {
+        Self::load(Connection::open(path)?, secret)
+    }
+
+    /// Une base en mémoire, perdue à la fermeture
+    pub fn open_in_memory(secret: Option<&[u8]>) -> Result<Self, DBError> {
+        Self::load(Connection::open_in_memory()?, secret)
+    }
+
+    fn load(conn: Connection, secret: Option<&[u8]>) -> Result<Self, DBError> {
+        conn.execute_batch(SCHEMA)?;
+
+        let salt: Option<Vec<u8>> = conn
+            .query_row("SELECT value FROM meta WHERE key = 'salt'", [], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        let is_empty: bool =
+            conn.query_row("SELECT NOT EXISTS (SELECT 1 FROM users)", [], |row| {
+                row.get(0)
+            })?;
+
+        let encryption = match (salt, secret) {
+            (Some(_), None) => return Err(DBError::MissingKey),
+            (Some(salt), Some(secret)) => {
+                let salt: [u8; SALT_LEN] = salt
+                    .try_into()
+                    .map_err(|_| DBError::Crypto(CryptoError::Decryption))?;
+                let encryption = Encryption::derive(secret, salt)?;
+                let check: Vec<u8> = conn.query_row(
+                    "SELECT value FROM meta WHERE key = 'key_check'",
+                    [],
+                    |row| row.get(0),
+                )?;
+                crypto::open(&encryption.key, &check, b"key_check")?;
+                Some(encryption)
+            }
+            (None, Some(_)) if !is_empty => return Err(DBError::PlaintextDatabase),
+            (None, Some(secret)) => {
+                info!("Encrypting new SQLite database");
+                let encryption = Encryption::derive(secret, crypto::random_salt())?;
+                let check = crypto::seal(&encryption.key, KEY_CHECK, b"key_check");
+                conn.execute(
+                    "INSERT INTO meta (key, value) VALUES ('salt', ?1), ('key_check', ?2)",
+                    params![encryption.salt.as_slice(), check],
+                )?;
+                Some(encryption)
+            }
+            (None, None) => None,
+        };
+
+        let mut db = Self {
+            conn,
+            encryption,
+            users: HashMap::new(),
+            reports: HashMap::new(),
+            login_attempts: HashMap::new(),
+            index: Index::default(),
+        };
+
+        let users: Vec<UserData> = db.load_rows("SELECT id, data FROM users", "users")?;
+        db.users = users.into_iter().map(|user| (user.id, user)).collect();
+        let reports: Vec<MedicalReport> =
+            db.load_rows("SELECT id, data FROM reports", "reports")?;
+        db.reports = reports
+            .into_iter()
+            .map(|report| (report.id, report))
+            .collect();
+        db.index = Index::new(&db.users, &db.reports);
+
+        let attempts: Option<Vec<u8>> = db
+            .conn
+            .query_row(
+                "SELECT value FROM meta WHERE key = 'login_attempts'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(attempts) = attempts {
+            db.login_attempts = db.decode("meta", "login_attempts", &attempts)?;
+        }
+        Ok(db)
+    }
+
+    fn load_rows<T: DeserializeOwned>(&self, query: &str, table: &str) -> Result<Vec<T>, DBError> {
+        let mut statement = self.conn.prepare(query)?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.iter()
+            .map(|(id, data)| self.decode(table, id, data))
+            .collect()
+    }
+
+    /// Le contenu d'une ligne, lié à sa table et à sa clé s'il est chiffré
+    fn encode(&self, table: &str, id: &str, value: &impl Serialize) -> Result<Vec<u8>, DBError> {
+        let json = serde_json::to_vec(value).map_err(io::Error::from)?;
+        Ok(match &self.encryption {
+            Some(encryption) => crypto::seal(&encryption.key, &json, &row_aad(table, id)),
+            None => json,
+        })
+    }
+
+    fn decode<T: DeserializeOwned>(
+        &self,
+        table: &str,
+        id: &str,
+        data: &[u8],
+    ) -> Result<T, DBError> {
+        let json = match &self.encryption {
+            Some(encryption) => crypto::open(&encryption.key, data, &row_aad(table, id))?,
+            None => data.to_vec(),
+        };
+        Ok(serde_json::from_slice(&json).map_err(io::Error::from)?)
+    }
+
+    fn write_report(&self, conn: &Connection, report: &MedicalReport) -> Result<(), DBError> {
+        let id = report.id.to_string();
+        conn.execute(
+            "INSERT OR REPLACE INTO reports (id, patient, data) VALUES (?1, ?2, ?3)",
+            params![
+                id,
+                report.patient.to_string(),
+                self.encode("reports", &id, report)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn write_user(&self, conn: &Connection, user: &UserData) -> Result<(), DBError> {
+        let id = user.id.to_string();
+        conn.execute(
+            "INSERT OR REPLACE INTO users (id, data) VALUES (?1, ?2)",
+            params![id, self.encode("users", &id, user)?],
+        )?;
+        Ok(())
+    }
+
+    /// Écrit les tentatives de connexion, puis les garde en mémoire
+    fn write_login_attempts(
+        &mut self,
+        attempts: HashMap<Username, LoginAttempts>,
+    ) -> Result<(), DBError> {
+        let data = self.encode("meta", "login_attempts", &attempts)?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('login_attempts', ?1)",
+            params![data],
+        )?;
+        self.login_attempts = attempts;
+        Ok(())
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption.is_some()
+    }
+}
+
+fn row_aad(table: &str, id: &str) -> Vec<u8> {
+    format!("{table}:{id}").into_bytes()
+}
+
+impl Storage for SqliteDatabase {
+    fn get_user(&self, user: UserID) -> Result<&UserData, DBError> {
+        self.users.get(&user).ok_or(DBError::InvalidUserID(user))
+    }
+
+    fn lookup_username(&self, name: &Username) -> Option<&UserData> {
+        self.index.lookup_username(&self.users, name)
+    }
+
+    fn lookup_avs_number(&self, avs_number: &AVSNumber) -> Option<&UserData> {
+        self.index.lookup_avs_number(&self.users, avs_number)
+    }
+
+    fn store_user(&mut self, data: UserData) -> Result<(), DBError> {
+        self.write_user(&self.conn, &data)?;
+        self.index.put_user(&mut self.users, data);
+        Ok(())
+    }
+
+    fn get_report(&self, report: ReportID) -> Option<&MedicalReport> {
+        self.reports.get(&report)
+    }
+
+    fn store_report(&mut self, report: MedicalReport) -> Result<(), DBError> {
+        self.write_report(&self.conn, &report)?;
+        self.index.put_report(&mut self.reports, report);
+        Ok(())
+    }
+
+    fn list_reports(&self) -> Box<dyn Iterator<Item = &MedicalReport> + '_> {
+        Box::new(self.reports.values())
+    }
+
+    fn list_patient_reports(
+        &self,
+        patient: UserID,
+    ) -> Box<dyn Iterator<Item = &MedicalReport> + '_> {
+        Box::new(self.index.reports(&self.reports, patient))
+    }
+
+    fn remove_reports(&mut self, patient: UserID) -> Result<(), DBError> {
+        self.conn.execute(
+            "DELETE FROM reports WHERE patient = ?1",
+            params![patient.to_string()],
+        )?;
+        self.index.remove_reports(&mut self.reports, patient);
+        Ok(())
+    }
+
+    fn get_patients(&self, doctor: UserID) -> Box<dyn Iterator<Item = UserID> + '_> {
+        Box::new(self.index.patients(doctor))
+    }
+
+    fn replace_folder(
+        &mut self,
+        patient: UserData,
+        reports: Vec<MedicalReport>,
+    ) -> Result<(), DBError> {
+        let transaction = self.conn.unchecked_transaction()?;
+        transaction.execute(
+            "DELETE FROM reports WHERE patient = ?1",
+            params![patient.id.to_string()],
+        )?;
+        for report in &reports {
+            self.write_report(&transaction, report)?;
+        }
+        self.write_user(&transaction, &patient)?;
+        transaction.commit()?;
+
+        self.index.remove_reports(&mut self.reports, patient.id);
+        for report in reports {
+            self.index.put_report(&mut self.reports, report);
+        }
+        self.index.put_user(&mut self.users, patient);
+        Ok(())
+    }
+
+    fn get_login_attempts(&self, name: &Username) -> Option<&LoginAttempts> {
+        self.login_attempts.get(name)
+    }
+
+    fn store_login_attempts(
+        &mut self,
+        name: &Username,
+        attempts: Option<LoginAttempts>,
+    ) -> Result<(), DBError> {
+        let mut all = self.login_attempts.clone();
+        match attempts {
+            Some(attempts) => all.insert(name.clone(), attempts),
+            None => all.remove(name),
+        };
+        self.write_login_attempts(all)
+    }
+
+    fn prune_login_attempts(
+        &mut self,
+        expired: &dyn Fn(&LoginAttempts) -> bool,
+    ) -> Result<(), DBError> {
+        let mut all = self.login_attempts.clone();
+        all.retain(|_, attempts| !expired(attempts));
+        self.write_login_attempts(all)
+    }
+
+    /// Chaque modification est déjà écrite
+    fn save(&self) -> Result<(), DBError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ReportContent;
+
+    fn temp_path() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("karak-db-{}.sqlite", UserID::new()))
+    }
+
+    #[test]
+    fn test_encrypted_roundtrip() {
+        let path = temp_path();
+        let name = Username::new("patient".to_owned());
+        let patient = UserID::new();
+        let report = MedicalReport {
+            id: ReportID::new(),
+            title: "Consultation".to_owned(),
+            author: patient,
+            patient,
+            content: ReportContent::Plain("Grippe".to_owned()),
+            details: None,
+            attachments: Vec::new(),
+            revisions: Vec::new(),
+            provenance: Default::default(),
+            parent: None,
+            signature: None,
+            seal: None,
+        };
+        let report_id = report.id;
+
+        let mut db = SqliteDatabase::open(&path, Some(b"passphrase")).unwrap();
+        db.store_report(report).unwrap();
+        let attempts = LoginAttempts {
+            failures: 2,
+            ..Default::default()
+        };
+        db.store_login_attempts(&name, Some(attempts)).unwrap();
+        drop(db);
+
+        let bytes = std::fs::read(&path).unwrap();
+        let reopened = SqliteDatabase::open(&path, Some(b"passphrase"));
+        let wrong_key = SqliteDatabase::open(&path, Some(b"wrong")).err();
+        let no_key = SqliteDatabase::open(&path, None).err();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(!bytes.windows(6).any(|w| w == b"Grippe"));
+        let reopened = reopened.unwrap();
+        assert!(reopened.is_encrypted());
+        assert_eq!(reopened.get_login_attempts(&name).unwrap().failures, 2);
+        assert_eq!(reopened.list_reports().count(), 1);
+        assert!(reopened.get_report(report_id).is_some());
+        assert!(matches!(wrong_key, Some(DBError::Crypto(_))));
+        assert!(matches!(no_key, Some(DBError::MissingKey)));
+    }
+}
@@ -0,0 +1,277 @@
+//! Chiffrement des rapports médicaux par dossier.
+//!
+//! Chaque utilisateur possède une paire de clés X25519 dont la clé privée est
+//! chiffrée sous son mot de passe. Chaque dossier possède sa propre paire de
+//! clés: les rapports sont chiffrés pour la clé publique du dossier, et la clé
+//! privée du dossier est chiffrée pour chaque utilisateur qui doit pouvoir les
+//! lire. Sans copie de cette clé, un administrateur ne peut pas lire les
+//! rapports, même en accédant directement à la base de données.
+
+use crate::models::{Attachment, FolderKeys, KeyGrant, ReportContent, ReportID, UserID, UserKeys};
+use crate::utils::crypto::{
+    self, generate_keypair, open_box, random_salt, seal_to, Bytes, CryptoError, PublicKey,
+    SealedBox, SecretKey, StaticSecret, KEY_LEN, SALT_LEN,
+};
+use std::collections::BTreeMap;
+use zeroize::Zeroizing;
+
+const USER_KEY_AAD: &[u8] = b"karak-user-key";
+
+fn static_secret(bytes: &[u8]) -> Result<StaticSecret, CryptoError> {
+    let bytes: [u8; KEY_LEN] = bytes.try_into().map_err(|_| CryptoError::Decryption)?;
+    Ok(StaticSecret::from(bytes))
+}
+
+impl UserKeys {
+    /// Tire une nouvelle paire de clés, protégée par le mot de passe
+    pub fn generate(password: &str) -> Result<(Self, StaticSecret), CryptoError> {
+        let (secret, _) = generate_keypair();
+        let keys = Self::protect(&secret, password)?;
+        Ok((keys, secret))
+    }
+
+    /// Chiffre une clé privée existante sous un (nouveau) mot de passe
+    pub fn protect(secret: &StaticSecret, password: &str) -> Result<Self, CryptoError> {
+        let salt = random_salt();
+        let kek = SecretKey::derive(password.as_bytes(), &salt)?;
+
+        Ok(Self {
+            public_key: Bytes::from(&PublicKey::from(secret)),
+            sealed_private_key: Bytes(crypto::seal(&kek, secret.as_bytes(), USER_KEY_AAD)),
+            salt: Bytes(salt.to_vec()),
+        })
+    }
+
+    /// Déchiffre la clé privée avec le mot de passe
+    pub fn unlock(&self, password: &str) -> Result<StaticSecret, CryptoError> {
+        let salt: [u8; SALT_LEN] = self
+            .salt
+            .0
+            .as_slice()
+            .try_into()
+            .map_err(|_| CryptoError::Decryption)?;
+        let kek = SecretKey::derive(password.as_bytes(), &salt)?;
+        let bytes = Zeroizing::new(crypto::open(
+            &kek,
+            &self.sealed_private_key.0,
+            USER_KEY_AAD,
+        )?);
+
+        let secret = static_secret(&bytes)?;
+        if Some(PublicKey::from(&secret)) != self.public_key.public_key() {
+            return Err(CryptoError::Decryption);
+        }
+        Ok(secret)
+    }
+}
+
+/// Lie une copie de la clé d'un dossier au patient et à son destinataire
+fn grant_aad(patient: UserID, recipient: UserID) -> Vec<u8> {
+    format!("karak-folder-key:{patient}:{recipient}").into_bytes()
+}
+
+impl FolderKeys {
+    /// Tire une nouvelle paire de clés pour le dossier, sans aucune copie
+    pub fn generate() -> (Self, StaticSecret) {
+        let (secret, public) = generate_keypair();
+        let keys = Self {
+            public_key: Bytes::from(&public),
+            grants: BTreeMap::new(),
+            rotation_pending: false,
+        };
+        (keys, secret)
+    }
+
+    pub fn public_key(&self) -> Option<PublicKey> {
+        self.public_key.public_key()
+    }
+
+    /// Donne une copie de la clé privée du dossier à un utilisateur
+    pub fn grant(
+        &mut self,
+        patient: UserID,
+        user: UserID,
+        recipient: &PublicKey,
+        folder_secret: &StaticSecret,
+    ) {
+        let sealed_key = seal_to(
+            recipient,
+            folder_secret.as_bytes(),
+            &grant_aad(patient, user),
+        );
+        self.grants.insert(
+            user,
+            KeyGrant {
+                recipient: Bytes::from(recipient),
+                sealed_key,
+            },
+        );
+    }
+
+    /// Indique si l'utilisateur a une copie de la clé utilisable avec sa clé actuelle
+    pub fn has_grant(&self, user: UserID, recipient: &PublicKey) -> bool {
+        self.grants
+            .get(&user)
+            .is_some_and(|grant| grant.recipient.public_key().as_ref() == Some(recipient))
+    }
+
+    /// Retrouve la clé privée du dossier à partir de la copie d'un utilisateur
+    pub fn unwrap(
+        &self,
+        patient: UserID,
+        user: UserID,
+        user_secret: &StaticSecret,
+    ) -> Option<StaticSecret> {
+        let grant = self.grants.get(&user)?;
+        let bytes = Zeroizing::new(
+            open_box(user_secret, &grant.sealed_key, &grant_aad(patient, user)).ok()?,
+        );
+        let secret = static_secret(&bytes).ok()?;
+        (Some(PublicKey::from(&secret)) == self.public_key()).then_some(secret)
+    }
+}
+
+/// Lie le texte chiffré d'un rapport à son identifiant et à son patient
+fn report_aad(report: ReportID, patient: UserID) -> Vec<u8> {
+    format!("karak-report:{report}:{patient}").into_bytes()
+}
+
+impl ReportContent {
+    /// Chiffre le texte d'un rapport pour la clé publique du dossier
+    pub fn seal(folder: &PublicKey, report: ReportID, patient: UserID, text: &str) -> Self {
+        Self::Sealed(seal_to(
+            folder,
+            text.as_bytes(),
+            &report_aad(report, patient),
+        ))
+    }
+
+    pub fn is_sealed(&self) -> bool {
+        matches!(self, Self::Sealed(_))
+    }
+
+    /// Le texte du rapport, déchiffré avec la clé privée du dossier si nécessaire
+    pub fn open(
+        &self,
+        report: ReportID,
+        patient: UserID,
+        folder_secret: Option<&StaticSecret>,
+    ) -> Result<String, CryptoError> {
+        match self {
+            Self::Plain(text) => Ok(text.clone()),
+            Self::Sealed(sealed) => {
+                let secret = folder_secret.ok_or(CryptoError::Decryption)?;
+                let bytes = open_box(secret, sealed, &report_aad(report, patient))?;
+                String::from_utf8(bytes).map_err(|_| CryptoError::Decryption)
+            }
+        }
+    }
+}
+
+/// Lie une pièce jointe chiffrée, et sa clé, à son rapport et à son patient
+fn attachment_aad(kind: &str, report: ReportID, patient: UserID) -> Vec<u8> {
+    format!("karak-attachment-{kind}:{report}:{patient}").into_bytes()
+}
+
+impl Attachment {
+    /// Chiffre un fichier sous une nouvelle clé. Retourne le fichier chiffré,
+    /// et sa clé chiffrée pour la clé publique du dossier.
+    pub fn seal(
+        folder: &PublicKey,
+        report: ReportID,
+        patient: UserID,
+        bytes: &[u8],
+    ) -> (Vec<u8>, SealedBox) {
+        let key = SecretKey::generate();
+        let blob = crypto::seal(&key, bytes, &attachment_aad("blob", report, patient));
+        (blob, Self::seal_key(&key, folder, report, patient))
+    }
+
+    /// Chiffre la clé d'une pièce jointe pour la clé publique du dossier
+    pub fn seal_key(
+        key: &SecretKey,
+        folder: &PublicKey,
+        report: ReportID,
+        patient: UserID,
+    ) -> SealedBox {
+        seal_to(
+            folder,
+            key.as_bytes(),
+            &attachment_aad("key", report, patient),
+        )
+    }
+
+    /// La clé de la pièce jointe, déchiffrée avec la clé privée du dossier
+    pub fn open_key(
+        &self,
+        report: ReportID,
+        patient: UserID,
+        folder_secret: &StaticSecret,
+    ) -> Result<SecretKey, CryptoError> {
+        let bytes = Zeroizing::new(open_box(
+            folder_secret,
+            &self.sealed_key,
+            &attachment_aad("key", report, patient),
+        )?);
+        SecretKey::from_bytes(&bytes).ok_or(CryptoError::Decryption)
+    }
+
+    /// Déchiffre le fichier lu dans le magasin
+    pub fn open(
+        &self,
+        report: ReportID,
+        patient: UserID,
+        folder_secret: &StaticSecret,
+        blob: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        let key = self.open_key(report, patient, folder_secret)?;
+        crypto::open(&key, blob, &attachment_aad("blob", report, patient))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_keys() {
+        let (keys, secret) = UserKeys::generate("password").unwrap();
+
+        let unlocked = keys.unlock("password").unwrap();
+        assert_eq!(unlocked.as_bytes(), secret.as_bytes());
+        assert!(keys.unlock("wrong password").is_err());
+
+        let rekeyed = UserKeys::protect(&secret, "new password").unwrap();
+        assert_eq!(rekeyed.public_key, keys.public_key);
+        assert!(rekeyed.unlock("password").is_err());
+        assert!(rekeyed.unlock("new password").is_ok());
+    }
+
+    #[test]
+    fn test_folder_grants() {
+        let (patient, doctor, admin) = (UserID::new(), UserID::new(), UserID::new());
+        let (doctor_secret, doctor_public) = generate_keypair();
+        let (admin_secret, _) = generate_keypair();
+
+        let (mut keys, folder_secret) = FolderKeys::generate();
+        keys.grant(patient, doctor, &doctor_public, &folder_secret);
+        assert!(keys.has_grant(doctor, &doctor_public));
+
+        let report = ReportID::new();
+        let content = ReportContent::seal(&keys.public_key().unwrap(), report, patient, "texte");
+        assert!(content.is_sealed());
+
+        let unwrapped = keys.unwrap(patient, doctor, &doctor_secret).unwrap();
+        assert_eq!(
+            content.open(report, patient, Some(&unwrapped)).unwrap(),
+            "texte"
+        );
+
+        assert!(keys.unwrap(patient, admin, &admin_secret).is_none());
+        assert!(keys.unwrap(patient, doctor, &admin_secret).is_none());
+        assert!(content.open(report, patient, None).is_err());
+        assert!(content
+            .open(ReportID::new(), patient, Some(&unwrapped))
+            .is_err());
+    }
+}
@@ -1,5 +1,9 @@
+pub mod audit;
 pub mod authorization;
+pub mod blobs;
 pub mod db;
+pub mod envelope;
 pub mod models;
 pub mod services;
+pub mod signature;
 pub mod utils;
@@ -1,15 +1,60 @@
 use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
 use derive_more::Display;
-use inquire::{Confirm, Password, Select, Text};
+use inquire::{validator::Validation, Confirm, CustomType, Password, Select, Text};
+use karak::audit::AuditLog;
 use karak::authorization::Enforcer;
-use karak::db::Database;
+use karak::blobs::BlobStore;
+use karak::db::{sqlite::SqliteDatabase, DBError, Database, Storage};
 use karak::models::*;
-use karak::services::Service;
-use karak::utils::input_validation::{username_input_validation, AVSNumber};
+use karak::services::{LoginError, SecondFactorPolicy, Service, ServiceError};
+use karak::utils::input_validation::{
+    password_input, username_input_validation, AVSNumber, Icd10Code, InvalidInput, PasswordPolicy,
+    PasswordStrength,
+};
+use karak::utils::password_utils::{HashConfig, Hasher, Pepper};
+use karak::utils::username::{UsernamePolicy, DEFAULT_MAX_USERNAME_LEN, DEFAULT_MIN_USERNAME_LEN};
+use similar::{ChangeTag, TextDiff};
+use std::fs;
+use std::path::Path;
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
+use zeroize::Zeroizing;
 
 const DB_FILE: &str = "database.json";
+const SQLITE_FILE: &str = "database.sqlite";
+const AUDIT_FILE: &str = "audit.log";
+/// Dossier des pièces jointes, à côté de la base de données
+const BLOB_DIR: &str = "blobs";
+
+/// Variable d'environnement permettant d'ajuster le score zxcvbn minimal (0 à 4)
+const MIN_PASSWORD_SCORE_VAR: &str = "KARAK_MIN_PASSWORD_SCORE";
+
+/// Variables d'environnement permettant d'ajuster le coût d'Argon2id
+const ARGON2_MEMORY_VAR: &str = "KARAK_ARGON2_MEMORY_KIB";
+const ARGON2_ITERATIONS_VAR: &str = "KARAK_ARGON2_ITERATIONS";
+const ARGON2_PARALLELISM_VAR: &str = "KARAK_ARGON2_PARALLELISM";
+
+/// Variables d'environnement contenant le poivre des mots de passe, et son
+/// identifiant (1 à 8 caractères) à changer avec lui
+const PEPPER_VAR: &str = "KARAK_PEPPER";
+const PEPPER_ID_VAR: &str = "KARAK_PEPPER_ID";
+
+/// Variables d'environnement donnant le secret de chiffrement de la base de données:
+/// le chemin d'un fichier clé, ou directement une phrase de passe
+const DB_KEYFILE_VAR: &str = "KARAK_DB_KEYFILE";
+const DB_PASSPHRASE_VAR: &str = "KARAK_DB_PASSPHRASE";
+
+/// Variable d'environnement choisissant le stockage: `json` (par défaut) ou `sqlite`
+const DB_BACKEND_VAR: &str = "KARAK_DB_BACKEND";
+
+/// Variables d'environnement donnant les longueurs minimale et maximale
+/// des nouveaux noms d'utilisateur
+const USERNAME_MIN_LEN_VAR: &str = "KARAK_USERNAME_MIN_LEN";
+const USERNAME_MAX_LEN_VAR: &str = "KARAK_USERNAME_MAX_LEN";
+
+/// Variable d'environnement rendant le TOTP obligatoire pour les médecins et les admins
+const MANDATORY_2FA_VAR: &str = "KARAK_MANDATORY_2FA";
 
 // ---------------------------------- NE PAS MODIFIER -------------------------------------------
 
@@ -69,7 +114,22 @@ impl Menu for App {
         match choice {
             Choice::Register => {
                 let username = username_input_validation("Username à enregistrer: ")?;
-                self.service.register(username)?;
+                loop {
+                    let password = password_input("Entrez votre mot de passe : ")?;
+                    match self.service.register(username.clone(), &password, None) {
+                        Err(ServiceError::WeakPassword { feedback }) => {
+                            print_password_feedback(feedback)
+                        }
+                        Err(ServiceError::InvalidUsername(e)) => {
+                            eprintln!("[!] {e}");
+                            break;
+                        }
+                        result => {
+                            result?;
+                            break;
+                        }
+                    }
+                }
                 Ok(MENU_LOOP) // Retourne au menu principal après l'enregistrement
             }
             Choice::Login => {
@@ -79,7 +139,32 @@ impl Menu for App {
                     .with_display_mode(inquire::PasswordDisplayMode::Masked)
                     .prompt()?;
 
-                let user_id = self.service.login(&username, &password)?;
+                let user_id = match self.service.login(&username, &password) {
+                    Err(LoginError::SecondFactorRequired) => {
+                        let code = Text::new("Code d'authentification (ou code de récupération) :")
+                            .prompt()?;
+                        self.service.verify_second_factor(&code)
+                    }
+                    result => result,
+                }?;
+
+                if self.service.must_change_password() {
+                    eprintln!("[!] Votre mot de passe doit être changé avant de continuer.");
+                    if let Err(error) = change_password_loop(&mut self.service, &password) {
+                        self.service.logout();
+                        return Err(error);
+                    }
+                }
+
+                if self.service.must_enroll_second_factor() {
+                    eprintln!(
+                        "[!] L'authentification à deux facteurs est obligatoire pour votre rôle."
+                    );
+                    if let Err(error) = enroll_second_factor(&mut self.service) {
+                        self.service.logout();
+                        return Err(error);
+                    }
+                }
 
                 eprintln!("[*] Bienvenue, {}.", username);
                 UserMenu {
@@ -94,6 +179,23 @@ impl Menu for App {
     }
 }
 
+/// Un patient dans une liste, avec son numéro AVS masqué
+struct PatientChoice<'a>(&'a UserData);
+
+impl std::fmt::Display for PatientChoice<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.0.medical_folder {
+            Some(folder) => write!(
+                f,
+                "{} ({})",
+                self.0.username,
+                folder.personal_data.avs_number.masked()
+            ),
+            None => write!(f, "{}", self.0.username),
+        }
+    }
+}
+
 struct UserMenu<'srv> {
     service: &'srv mut Service,
     user_id: UserID,
@@ -103,7 +205,7 @@ impl Menu for UserMenu<'_> {
     fn enter(&mut self) -> Result<Option<()>> {
         #[derive(EnumIter, Display)]
         enum Choice {
-            #[display("Créer mon dossier médical")]
+            #[display("Créer ou modifier mon dossier médical")]
             SetPersonalData,
 
             #[display("Lire mon dossier médical")]
@@ -118,9 +220,30 @@ impl Menu for UserMenu<'_> {
             #[display("Écrire un rapport")]
             AddReport,
 
+            #[display("Compléter le dossier clinique d'un patient")]
+            AddClinicalEntry,
+
+            #[display("Voir qui a accédé à mon dossier")]
+            ReadOwnAuditTrail,
+
+            #[display("Consulter le journal d'accès d'un utilisateur")]
+            ReadAuditTrail,
+
             #[display("Administrer les Rôles")]
             UpdateRole,
 
+            #[display("Changer mon mot de passe")]
+            ChangePassword,
+
+            #[display("Activer l'authentification à deux facteurs")]
+            EnableSecondFactor,
+
+            #[display("Réinitialiser le mot de passe d'un utilisateur")]
+            ResetPassword,
+
+            #[display("Déverrouiller un compte")]
+            UnlockAccount,
+
             #[display("Supprimer toutes mes données")]
             WipeAccount,
 
@@ -134,24 +257,32 @@ impl Menu for UserMenu<'_> {
                 ReportsMenu {
                     service: self.service,
                     patient_id: self.user_id,
+                    kind: None,
                 }
                 .show()?;
             }
 
             Choice::SetPersonalData => {
-                let avs_number: AVSNumber =
-                    Text::new("Entrez votre numéro AVS:").prompt()?.try_into()?;
-                let blood_type =
-                    Select::new("Entrez votre groupe sanguin:", BloodType::iter().collect())
-                        .prompt()?;
+                let has_folder = self
+                    .service
+                    .get_data(self.user_id)?
+                    .medical_folder
+                    .is_some();
+                if !has_folder {
+                    let avs_number: AVSNumber =
+                        Text::new("Entrez votre numéro AVS:").prompt()?.try_into()?;
+                    let blood_type =
+                        Select::new("Entrez votre groupe sanguin:", BloodType::iter().collect())
+                            .prompt()?;
+                    self.service
+                        .update_data(self.user_id, PersonalData::new(avs_number, blood_type))?;
+                }
 
-                self.service.update_data(
-                    self.user_id,
-                    PersonalData {
-                        avs_number,
-                        blood_type,
-                    },
-                )?;
+                PersonalDataMenu {
+                    service: self.service,
+                    user_id: self.user_id,
+                }
+                .enter_loop();
             }
 
             Choice::AddDoctor => {
@@ -164,13 +295,18 @@ impl Menu for UserMenu<'_> {
             }
 
             Choice::CheckPatient => {
-                let patients: Vec<&UserData> = self.service.list_patients().collect();
+                let patients: Vec<PatientChoice> =
+                    self.service.list_patients().map(PatientChoice).collect();
 
-                let patient_id = Select::new("Choisissez un patient:", patients).prompt()?.id;
+                let patient_id = Select::new("Choisissez un patient:", patients)
+                    .prompt()?
+                    .0
+                    .id;
 
                 ReportsMenu {
                     service: self.service,
                     patient_id,
+                    kind: report_kind_filter()?,
                 }
                 .enter_loop()
             }
@@ -181,12 +317,37 @@ impl Menu for UserMenu<'_> {
                     .lookup_user(&username_input_validation("Username du patient:")?)
                     .ok_or(anyhow!("Patient inexistant"))?;
 
-                let title = Text::new("Entrez le titre du rapport:").prompt()?;
+                let kind = Select::new("Type de rapport:", ReportKind::iter().collect())
+                    .with_help_message("Échap pour un rapport en texte libre")
+                    .prompt_skippable()?;
+                let mut title = Text::new("Entrez le titre du rapport:");
+                let default_title = kind.map(|kind| kind.to_string());
+                if let Some(default_title) = &default_title {
+                    title = title.with_default(default_title);
+                }
+                let title = title.prompt()?;
+                let today = self.service.now().date_naive();
+                let details = kind
+                    .map(|kind| report_details_input(kind, today))
+                    .transpose()?;
 
                 let content = inquire::Editor::new("Enter the report:").prompt()?;
 
                 self.service
-                    .add_report(self.user_id, patient, title, content)?;
+                    .add_report(self.user_id, patient, title, content, details)?;
+            }
+
+            Choice::AddClinicalEntry => {
+                let patients: Vec<PatientChoice> =
+                    self.service.list_patients().map(PatientChoice).collect();
+                let patient = Select::new("Choisissez un patient:", patients)
+                    .prompt()?
+                    .0
+                    .id;
+
+                let today = self.service.now().date_naive();
+                let entry = clinical_entry_input(self.user_id, today)?;
+                self.service.add_clinical_entry(patient, entry)?;
             }
 
             Choice::WipeAccount => {
@@ -197,6 +358,19 @@ impl Menu for UserMenu<'_> {
                     }
             }
 
+            Choice::ReadOwnAuditTrail => show_audit_trail(self.service, self.user_id)?,
+
+            Choice::ReadAuditTrail => {
+                let username = username_input_validation("Username à consulter: ")?;
+
+                let user_id = self
+                    .service
+                    .lookup_user(&username)
+                    .ok_or(anyhow!("Utilisateur inconnu"))?;
+
+                show_audit_trail(self.service, user_id)?;
+            }
+
             Choice::UpdateRole => {
                 let username = username_input_validation("Username à administrer: ")?;
 
@@ -210,15 +384,413 @@ impl Menu for UserMenu<'_> {
                 self.service.update_role(user_id, role)?;
             }
 
+            Choice::ChangePassword => {
+                let old = Password::new("Mot de passe actuel : ")
+                    .without_confirmation()
+                    .with_display_mode(inquire::PasswordDisplayMode::Masked)
+                    .prompt()?;
+                change_password_loop(self.service, &old)?;
+                println!("Votre mot de passe a été changé");
+            }
+
+            Choice::EnableSecondFactor => enroll_second_factor(self.service)?,
+
+            Choice::ResetPassword => {
+                let username = username_input_validation("Username à réinitialiser: ")?;
+
+                let user_id = self
+                    .service
+                    .lookup_user(&username)
+                    .ok_or(anyhow!("Utilisateur inconnu"))?;
+
+                let password = password_input("Mot de passe temporaire : ")?;
+                self.service.reset_password(user_id, &password)?;
+                println!("L'utilisateur devra changer ce mot de passe à sa prochaine connexion");
+            }
+
+            Choice::UnlockAccount => {
+                let username = username_input_validation("Username à déverrouiller: ")?;
+
+                let user_id = self
+                    .service
+                    .lookup_user(&username)
+                    .ok_or(anyhow!("Utilisateur inconnu"))?;
+
+                self.service.unlock_account(user_id)?;
+                println!("Ce compte est déverrouillé");
+            }
+
             Choice::Logout => return Ok(MENU_EXIT),
         };
         Ok(MENU_LOOP)
     }
 }
 
+/// Modifie un à un les champs des données personnelles d'un dossier existant
+struct PersonalDataMenu<'srv> {
+    service: &'srv mut Service,
+    user_id: UserID,
+}
+
+impl Menu for PersonalDataMenu<'_> {
+    fn enter(&mut self) -> Result<MenuExit> {
+        #[derive(EnumIter, Display)]
+        enum Field {
+            #[display("Numéro AVS")]
+            AvsNumber,
+            #[display("Groupe sanguin")]
+            BloodType,
+            #[display("Facteur Rhésus")]
+            Rhesus,
+            #[display("Nom complet")]
+            FullName,
+            #[display("Date de naissance")]
+            DateOfBirth,
+            #[display("Sexe")]
+            Sex,
+            #[display("Adresse")]
+            Address,
+            #[display("Personne à prévenir en cas d'urgence")]
+            EmergencyContact,
+            #[display("Numéro d'assurance maladie")]
+            InsuranceNumber,
+            #[display("Retour")]
+            Back,
+        }
+
+        let Some(folder) = &self.service.get_data(self.user_id)?.medical_folder else {
+            return Ok(MENU_EXIT);
+        };
+        let mut data = folder.personal_data.clone();
+        print_personal_data(&data);
+
+        let Some(field) =
+            Select::new("Quel champ modifier ?", Field::iter().collect()).prompt_skippable()?
+        else {
+            return Ok(MENU_EXIT);
+        };
+        match field {
+            Field::AvsNumber => {
+                data.avs_number = Text::new("Numéro AVS:").prompt()?.try_into()?;
+            }
+            Field::BloodType => {
+                data.blood_type =
+                    Select::new("Groupe sanguin:", BloodType::iter().collect()).prompt()?;
+            }
+            Field::Rhesus => data.rhesus = optional_choice("Facteur Rhésus:")?,
+            Field::FullName => data.full_name = full_name_input("Prénom:", "Nom:")?,
+            Field::DateOfBirth => {
+                data.date_of_birth = optional_input("Date de naissance (JJ.MM.AAAA):")?;
+            }
+            Field::Sex => data.sex = optional_choice("Sexe:")?,
+            Field::Address => data.address = address_input()?,
+            Field::EmergencyContact => {
+                data.emergency_contact =
+                    match full_name_input("Prénom du contact:", "Nom du contact:")? {
+                        Some(name) => Some(EmergencyContact {
+                            name,
+                            phone: Text::new("Téléphone du contact:").prompt()?.try_into()?,
+                        }),
+                        None => None,
+                    };
+            }
+            Field::InsuranceNumber => {
+                data.insurance_number =
+                    optional_input("Numéro de carte d'assurance (20 chiffres):")?;
+            }
+            Field::Back => return Ok(MENU_EXIT),
+        }
+
+        self.service.update_data(self.user_id, data)?;
+        Ok(MENU_LOOP)
+    }
+}
+
+/// Demande un champ facultatif; une réponse vide l'efface
+fn optional_input<T: TryFrom<String, Error = InvalidInput>>(message: &str) -> Result<Option<T>> {
+    let value = Text::new(message)
+        .with_help_message("Laisser vide pour effacer")
+        .prompt()?;
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(value.try_into()?))
+}
+
+/// Choisit la valeur d'un champ facultatif; Échap l'efface
+fn optional_choice<T: IntoEnumIterator + std::fmt::Display>(message: &str) -> Result<Option<T>> {
+    Ok(Select::new(message, T::iter().collect())
+        .with_help_message("Échap pour effacer")
+        .prompt_skippable()?)
+}
+
+fn full_name_input(first: &str, last: &str) -> Result<Option<FullName>> {
+    let Some(first_name) = optional_input(first)? else {
+        return Ok(None);
+    };
+    Ok(Some(FullName {
+        first_name,
+        last_name: Text::new(last).prompt()?.try_into()?,
+    }))
+}
+
+fn address_input() -> Result<Option<Address>> {
+    let Some(street) = optional_input("Rue et numéro:")? else {
+        return Ok(None);
+    };
+    Ok(Some(Address {
+        street,
+        postal_code: Text::new("Code postal:").prompt()?.try_into()?,
+        city: Text::new("Localité:").prompt()?.try_into()?,
+        country: Text::new("Pays:")
+            .with_default("Suisse")
+            .prompt()?
+            .try_into()?,
+    }))
+}
+
+fn clinical_entry_input(author: UserID, today: NaiveDate) -> Result<ClinicalEntry> {
+    #[derive(EnumIter, Display)]
+    enum Kind {
+        #[display("Allergie")]
+        Allergy,
+        #[display("Traitement")]
+        Medication,
+        #[display("Diagnostic")]
+        Diagnosis,
+    }
+
+    Ok(
+        match Select::new("Type d'entrée:", Kind::iter().collect()).prompt()? {
+            Kind::Allergy => ClinicalEntry::Allergy(Allergy {
+                substance: Text::new("Substance:").prompt()?.try_into()?,
+                severity: Select::new("Gravité:", Severity::iter().collect()).prompt()?,
+            }),
+            Kind::Medication => ClinicalEntry::Medication(medication_input(today)?),
+            Kind::Diagnosis => ClinicalEntry::Diagnosis(Diagnosis {
+                code: Text::new("Code CIM-10:").prompt()?.try_into()?,
+                date: date_input("Date du diagnostic (AAAA-MM-JJ):", today)?,
+                author,
+            }),
+        },
+    )
+}
+
+/// Demande une date, aujourd'hui par défaut
+fn date_input(message: &str, today: NaiveDate) -> Result<NaiveDate> {
+    Ok(CustomType::<NaiveDate>::new(message)
+        .with_default(today)
+        .prompt()?)
+}
+
+fn medication_input(today: NaiveDate) -> Result<Medication> {
+    Ok(Medication {
+        drug: Text::new("Médicament:").prompt()?.try_into()?,
+        dose: Text::new("Posologie:").prompt()?.try_into()?,
+        start: date_input("Début du traitement (AAAA-MM-JJ):", today)?,
+        end: CustomType::<NaiveDate>::new("Fin du traitement (AAAA-MM-JJ):")
+            .with_help_message("Échap si le traitement n'a pas de fin prévue")
+            .prompt_skippable()?,
+    })
+}
+
+/// Demande des codes CIM-10 séparés par des virgules
+fn diagnoses_input(message: &str) -> Result<Vec<Icd10Code>> {
+    let codes = Text::new(message)
+        .with_help_message("Codes CIM-10 séparés par des virgules")
+        .prompt()?;
+    Ok(codes
+        .split(',')
+        .filter(|code| !code.trim().is_empty())
+        .map(|code| code.to_owned().try_into())
+        .collect::<Result<_, _>>()?)
+}
+
+/// Répète une saisie tant que l'utilisateur veut ajouter des entrées
+fn list_input<T>(message: &str, input: impl Fn() -> Result<T>) -> Result<Vec<T>> {
+    let mut entries = vec![input()?];
+    while Confirm::new(message).with_default(false).prompt()? {
+        entries.push(input()?);
+    }
+    Ok(entries)
+}
+
+fn lab_value_input() -> Result<LabValue> {
+    Ok(LabValue {
+        analysis: Text::new("Analyse:").prompt()?.try_into()?,
+        value: Text::new("Valeur:").prompt()?.try_into()?,
+        unit: Text::new("Unité:").prompt()?.try_into()?,
+        low: optional_input("Valeur de référence minimale:")?,
+        high: optional_input("Valeur de référence maximale:")?,
+    })
+}
+
+/// Demande les champs structurés d'un rapport du type donné
+fn report_details_input(kind: ReportKind, today: NaiveDate) -> Result<ReportDetails> {
+    Ok(match kind {
+        ReportKind::ConsultationNote => ReportDetails::ConsultationNote {
+            reason: Text::new("Motif de consultation:").prompt()?.try_into()?,
+            diagnoses: diagnoses_input("Diagnostics:")?,
+        },
+        ReportKind::LabResult => ReportDetails::LabResult {
+            sampled_on: date_input("Date du prélèvement (AAAA-MM-JJ):", today)?,
+            results: list_input("Ajouter une autre analyse ?", lab_value_input)?,
+        },
+        ReportKind::Prescription => ReportDetails::Prescription {
+            medications: list_input("Ajouter un autre médicament ?", || medication_input(today))?,
+            valid_until: date_input("Ordonnance valable jusqu'au (AAAA-MM-JJ):", today)?,
+        },
+        ReportKind::DischargeLetter => ReportDetails::DischargeLetter {
+            admitted: date_input("Date d'admission (AAAA-MM-JJ):", today)?,
+            discharged: date_input("Date de sortie (AAAA-MM-JJ):", today)?,
+            diagnoses: diagnoses_input("Diagnostics de sortie:")?,
+        },
+        ReportKind::ImagingResult => ReportDetails::ImagingResult {
+            performed_on: date_input("Date de l'examen (AAAA-MM-JJ):", today)?,
+            modality: Select::new("Technique:", ImagingModality::iter().collect()).prompt()?,
+            region: Text::new("Région examinée:").prompt()?.try_into()?,
+        },
+    })
+}
+
+/// Choisit le type des rapports à afficher; Échap les affiche tous
+fn report_kind_filter() -> Result<Option<ReportKind>> {
+    Ok(
+        Select::new("Type de rapports à afficher:", ReportKind::iter().collect())
+            .with_help_message("Échap pour afficher tous les rapports")
+            .prompt_skippable()?,
+    )
+}
+
+fn print_report_details(details: &ReportDetails) {
+    let date = |date: &NaiveDate| date.format("%d.%m.%Y").to_string();
+    let codes = |codes: &[Icd10Code]| match codes {
+        [] => "aucun".to_owned(),
+        codes => codes
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", "),
+    };
+    println!("Type: {}", details.kind());
+    match details {
+        ReportDetails::ConsultationNote { reason, diagnoses } => {
+            println!("Motif: {reason}\nDiagnostics: {}", codes(diagnoses));
+        }
+        ReportDetails::LabResult {
+            sampled_on,
+            results,
+        } => {
+            println!("Prélèvement du {}:", date(sampled_on));
+            for result in results {
+                println!("  - {result}");
+            }
+        }
+        ReportDetails::Prescription {
+            medications,
+            valid_until,
+        } => {
+            println!("Valable jusqu'au {}:", date(valid_until));
+            for medication in medications {
+                println!("  - {medication}");
+            }
+        }
+        ReportDetails::DischargeLetter {
+            admitted,
+            discharged,
+            diagnoses,
+        } => println!(
+            "Séjour du {} au {}\nDiagnostics: {}",
+            date(admitted),
+            date(discharged),
+            codes(diagnoses)
+        ),
+        ReportDetails::ImagingResult {
+            performed_on,
+            modality,
+            region,
+        } => println!("{modality}, {region}, le {}", date(performed_on)),
+    }
+}
+
+fn print_clinical_record(record: &ClinicalRecord, today: NaiveDate) {
+    let sections: [(&str, Vec<String>); 3] = [
+        (
+            "Allergies",
+            record.allergies.iter().map(ToString::to_string).collect(),
+        ),
+        (
+            "Traitements en cours",
+            record
+                .medications
+                .iter()
+                .filter(|medication| medication.is_current(today))
+                .map(ToString::to_string)
+                .collect(),
+        ),
+        (
+            "Diagnostics",
+            record.diagnoses.iter().map(ToString::to_string).collect(),
+        ),
+    ];
+    for (title, entries) in sections {
+        if entries.is_empty() {
+            println!("{title}: aucun");
+        } else {
+            println!("{title}:\n  - {}", entries.join("\n  - "));
+        }
+    }
+}
+
+fn print_provenance(provenance: &Provenance) {
+    let date = |date: Option<DateTime<Utc>>| {
+        date.map(|date| date.format("%d.%m.%Y %H:%M").to_string())
+            .unwrap_or_else(|| "inconnue".to_owned())
+    };
+    let by = provenance
+        .updated_by
+        .map(|editor| format!(" par {editor}"))
+        .unwrap_or_default();
+    println!(
+        "Création: {}\nDernière modification: {}{by}",
+        date(provenance.created_at),
+        date(provenance.updated_at)
+    );
+}
+
+fn print_personal_data(data: &PersonalData) {
+    println!(
+        "Numéro AVS: {}\nGroupe sanguin: {}",
+        data.avs_number,
+        data.blood_group()
+    );
+    let fields: [(&str, Option<String>); 6] = [
+        ("Nom", data.full_name.as_ref().map(ToString::to_string)),
+        (
+            "Date de naissance",
+            data.date_of_birth.map(|date| date.to_string()),
+        ),
+        ("Sexe", data.sex.map(|sex| sex.to_string())),
+        ("Adresse", data.address.as_ref().map(ToString::to_string)),
+        (
+            "En cas d'urgence",
+            data.emergency_contact.as_ref().map(ToString::to_string),
+        ),
+        (
+            "Assurance maladie",
+            data.insurance_number.as_ref().map(ToString::to_string),
+        ),
+    ];
+    for (label, value) in fields {
+        println!("{label}: {}", value.as_deref().unwrap_or("-"));
+    }
+}
+
 struct ReportsMenu<'srv> {
     service: &'srv mut Service,
     patient_id: UserID,
+    /// N'affiche que les rapports de ce type
+    kind: Option<ReportKind>,
 }
 
 impl ReportsMenu<'_> {
@@ -228,6 +800,7 @@ impl ReportsMenu<'_> {
                 role,
                 username,
                 medical_folder,
+                provenance,
                 ..
             } = user;
             let has_data = if medical_folder.is_some() {
@@ -236,25 +809,32 @@ impl ReportsMenu<'_> {
                 "non"
             };
             println!("User: {username}\nRole: {role}\nDossier électronique: {has_data}");
+            print_provenance(provenance);
 
             if let Some(folder) = medical_folder {
-                let PersonalData {
-                    avs_number,
-                    blood_type,
-                } = &folder.personal_data;
-                println!("Numéro AVS: {avs_number}\nGroupe sanguin: {blood_type}");
+                print_personal_data(&folder.personal_data);
+            }
+            if let Ok(record) = self.service.clinical_record(self.patient_id) {
+                print_clinical_record(record, self.service.now().date_naive());
             }
         } else {
             println!("[!] L'accès à ce dossier est restreint")
         }
 
-        Ok(self.enter_loop())
+        self.kind = report_kind_filter()?;
+        self.enter_loop();
+        Ok(())
     }
 }
 
 impl Menu for ReportsMenu<'_> {
     fn enter(&mut self) -> Result<Option<()>> {
-        let reports: Vec<&MedicalReport> = self.service.list_reports(self.patient_id).collect();
+        let mut reports: Vec<&MedicalReport> = self
+            .service
+            .list_reports(self.patient_id, self.kind)
+            .collect();
+        // Les rapports antérieurs aux dates sont considérés comme les plus anciens
+        reports.sort_by_key(|report| report.provenance.created_at);
 
         if reports.is_empty() {
             println!("[*] Il n'y a pas de rapports dans ce dossier");
@@ -266,19 +846,449 @@ impl Menu for ReportsMenu<'_> {
             return Ok(MENU_EXIT);
         };
 
+        let content = self
+            .service
+            .report_content(report)
+            .unwrap_or_else(|e| format!("[!] Contenu illisible: {e}"));
         println!(
-            "\n[{}]\nTitre: {}\nAuteur: {}\n\n{}\n===============",
-            report.id, report.title, report.author, report.content
+            "\n[{}]\nTitre: {}\nAuteur: {}\nRévisions: {}",
+            report.id,
+            report.title,
+            report.author,
+            report.revisions.len(),
         );
+        print_provenance(&report.provenance);
+        if let Some(parent) = report.parent {
+            println!("Addendum au rapport: {parent}");
+        }
+        if let Some(details) = &report.details {
+            print_report_details(details);
+        }
+        if report.is_final() {
+            println!("Statut: définitif");
+        }
+        for attachment in &report.attachments {
+            println!("Pièce jointe: {attachment}");
+        }
+        println!("\n{content}\n===============");
+
+        #[derive(EnumIter, Display)]
+        enum Action {
+            #[display("Modifier ce rapport")]
+            Edit,
+            #[display("Consulter l'historique")]
+            History,
+            #[display("Signer et rendre définitif")]
+            Seal,
+            #[display("Ajouter un addendum")]
+            AddAddendum,
+            #[display("Vérifier les signatures")]
+            Verify,
+            #[display("Joindre un fichier")]
+            AddAttachment,
+            #[display("Enregistrer une pièce jointe")]
+            SaveAttachment,
+            #[display("Retour")]
+            Back,
+        }
+
+        let report_id = report.id;
+        match Select::new("Que voulez-vous faire ?", Action::iter().collect()).prompt_skippable()? {
+            Some(Action::Edit) => {
+                let content = inquire::Editor::new("Nouveau texte du rapport:")
+                    .with_predefined_text(&content)
+                    .prompt()?;
+                let reason = Text::new("Motif de la modification:")
+                    .prompt()?
+                    .try_into()?;
+                self.service.update_report(report_id, content, reason)?;
+            }
+            Some(Action::History) => show_report_history(self.service, report)?,
+            Some(Action::Seal) => {
+                self.service.seal_report(report_id)?;
+                println!("[*] Le rapport est désormais définitif");
+            }
+            Some(Action::AddAddendum) => {
+                let content = inquire::Editor::new("Texte de l'addendum:").prompt()?;
+                self.service.add_addendum(report_id, content)?;
+            }
+            Some(Action::Verify) => {
+                let verification = self.service.verify_report(report)?;
+                println!(
+                    "Version originale: {}\nModifications: {}",
+                    verification.original, verification.revisions
+                );
+                match verification.seal {
+                    Some((sealed_at, status)) => println!(
+                        "Version définitive du {}: {status}",
+                        sealed_at.format("%d.%m.%Y %H:%M")
+                    ),
+                    None => println!("Le rapport n'est pas définitif"),
+                }
+            }
+            Some(Action::AddAttachment) => {
+                let path = Text::new("Chemin du fichier:").prompt()?;
+                let path = Path::new(path.trim());
+                if fs::metadata(path)?.len() > MAX_ATTACHMENT_SIZE as u64 {
+                    return Err(ServiceError::AttachmentTooLarge.into());
+                }
+                let file_name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let name = Text::new("Nom de la pièce jointe:")
+                    .with_default(&file_name)
+                    .prompt()?
+                    .try_into()?;
+                self.service
+                    .add_attachment(report_id, name, &fs::read(path)?)?;
+            }
+            Some(Action::SaveAttachment) => {
+                if report.attachments.is_empty() {
+                    println!("[*] Ce rapport n'a pas de pièce jointe");
+                    return Ok(MENU_LOOP);
+                }
+                let attachments = report.attachments.iter().collect();
+                let Some(attachment) =
+                    Select::new("Choisissez une pièce jointe:", attachments).prompt_skippable()?
+                else {
+                    return Ok(MENU_LOOP);
+                };
+                let bytes = self.service.attachment_content(report, &attachment.blob)?;
+                let path = Text::new("Enregistrer sous:")
+                    .with_default(&attachment.name.to_string())
+                    .prompt()?;
+                fs::write(path.trim(), bytes)?;
+                println!("[*] Pièce jointe enregistrée");
+            }
+            Some(Action::Back) | None => {}
+        }
 
         Ok(MENU_LOOP)
     }
 }
 
+/// Parcourt les versions d'un rapport, chacune comparée à la précédente
+fn show_report_history(service: &Service, report: &MedicalReport) -> Result<()> {
+    let versions = service.report_history(report)?;
+    let labels: Vec<String> = versions
+        .iter()
+        .enumerate()
+        .map(|(number, version)| match version.revision {
+            Some(revision) => format!(
+                "Version {number}, le {} par {}: {}",
+                revision.timestamp.format("%d.%m.%Y %H:%M"),
+                revision.editor,
+                revision.reason
+            ),
+            None => format!("Version {number}, originale par {}", report.author),
+        })
+        .collect();
+
+    while let Some(label) =
+        Select::new("Choisissez une version:", labels.clone()).prompt_skippable()?
+    {
+        let number = labels.iter().position(|l| *l == label).unwrap_or_default();
+        let text = &versions[number].text;
+        match number.checked_sub(1) {
+            Some(previous) => print_diff(&versions[previous].text, text),
+            None => println!("{text}"),
+        }
+        println!("===============");
+    }
+    Ok(())
+}
+
+/// Affiche les lignes retirées (`-`) et ajoutées (`+`) d'un texte à l'autre
+fn print_diff(old: &str, new: &str) {
+    let diff = TextDiff::from_lines(old, new);
+    for change in diff.iter_all_changes() {
+        let sign = match change.tag() {
+            ChangeTag::Delete => "-",
+            ChangeTag::Insert => "+",
+            ChangeTag::Equal => " ",
+        };
+        print!("{sign} {change}");
+        if change.missing_newline() {
+            println!();
+        }
+    }
+}
+
+/// Affiche les accès au dossier d'un utilisateur sur une période choisie
+fn show_audit_trail(service: &Service, user_id: UserID) -> Result<()> {
+    let now = service.now();
+    let since = move |days: u32| {
+        TimeDelta::try_days(days.into()).and_then(|period| now.checked_sub_signed(period))
+    };
+    let days = CustomType::<u32>::new("Nombre de jours à afficher:")
+        .with_help_message("Échap pour tout afficher")
+        .with_validator(move |&days: &u32| {
+            Ok(match since(days) {
+                Some(_) => Validation::Valid,
+                None => Validation::Invalid("Période trop longue".into()),
+            })
+        })
+        .prompt_skippable()?;
+    let since = days.and_then(since);
+
+    let records = service.audit_trail(user_id, since)?;
+    if records.is_empty() {
+        println!("[*] Aucun accès enregistré sur cette période");
+    }
+    for record in records {
+        let report = record
+            .target
+            .report
+            .map(|report| format!(" (rapport {report})"))
+            .unwrap_or_default();
+        println!(
+            "{} {} {}{} : {}",
+            record.timestamp.format("%d.%m.%Y %H:%M:%S"),
+            record.subject,
+            record.action,
+            report,
+            record.decision
+        );
+    }
+    Ok(())
+}
+
+/// Affiche pourquoi un mot de passe a été refusé
+fn print_password_feedback(feedback: PasswordStrength) {
+    eprintln!("Mot de passe trop faible ({}/4).", feedback.score);
+    eprintln!(
+        "Temps estimé pour le casser: {}",
+        feedback.crack_time_text()
+    );
+    if let Some(warning) = feedback.warning_text() {
+        eprintln!("Attention: {warning}");
+    }
+    for suggestion in feedback.suggestion_texts() {
+        eprintln!(" - {suggestion}");
+    }
+}
+
+/// Active le TOTP pour l'utilisateur connecté et affiche ses codes de récupération
+fn enroll_second_factor(service: &mut Service) -> Result<()> {
+    let uri = service.begin_totp_enrolment()?;
+    println!("Ajoutez ce compte dans votre application d'authentification:\n{uri}");
+
+    loop {
+        let code = Text::new("Code affiché par l'application :").prompt()?;
+        match service.confirm_totp_enrolment(&code) {
+            Err(ServiceError::InvalidSecondFactor) => eprintln!("Code invalide, réessayez."),
+            result => {
+                println!(
+                    "Conservez ces codes de récupération en lieu sûr, ils ne seront plus affichés:"
+                );
+                for code in result? {
+                    println!("  {code}");
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Demande un nouveau mot de passe jusqu'à ce qu'il soit accepté
+fn change_password_loop(service: &mut Service, old: &str) -> Result<()> {
+    loop {
+        let new = password_input("Nouveau mot de passe : ")?;
+        match service.change_password(old, &new) {
+            Err(ServiceError::WeakPassword { feedback }) => print_password_feedback(feedback),
+            Err(error @ ServiceError::SamePassword) => eprintln!("[!] {error}"),
+            result => return Ok(result?),
+        }
+    }
+}
+
+/// Lit un paramètre numérique dans l'environnement, avec une valeur par défaut
+fn env_or<T: std::str::FromStr>(var: &str, default: T) -> Result<T> {
+    match std::env::var(var) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| anyhow!("{var} doit être un nombre entier")),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Construit la configuration de hachage à partir de l'environnement
+fn hash_config() -> Result<HashConfig> {
+    let default = HashConfig::default();
+    Ok(HashConfig {
+        memory_kib: env_or(ARGON2_MEMORY_VAR, default.memory_kib)?,
+        iterations: env_or(ARGON2_ITERATIONS_VAR, default.iterations)?,
+        parallelism: env_or(ARGON2_PARALLELISM_VAR, default.parallelism)?,
+        pepper: pepper()?,
+    })
+}
+
+/// Lit le poivre et son identifiant dans l'environnement
+fn pepper() -> Result<Option<Pepper>> {
+    let Ok(secret) = std::env::var(PEPPER_VAR) else {
+        return Ok(None);
+    };
+    let id = std::env::var(PEPPER_ID_VAR)
+        .map_err(|_| anyhow!("{PEPPER_ID_VAR} est requis avec {PEPPER_VAR}"))?;
+    Pepper::new(id.as_bytes(), secret.into_bytes())
+        .map(Some)
+        .ok_or_else(|| anyhow!("{PEPPER_ID_VAR} doit compter de 1 à 8 caractères"))
+}
+
+/// Commandes d'administration, utilisables sans passer par les menus
+fn run_command(args: &[String]) -> Result<()> {
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["audit", "verify"] => {
+            let count = AuditLog::verify(AUDIT_FILE.as_ref())?;
+            println!("[*] Journal d'audit intègre ({count} entrées)");
+            Ok(())
+        }
+        ["db", "migrate"] => migrate_database(false),
+        ["db", "migrate", "--dry-run"] => migrate_database(true),
+        _ => Err(anyhow!(
+            "Commande inconnue. Commandes disponibles:\n  karak audit verify\n  karak db migrate [--dry-run]"
+        )),
+    }
+}
+
+/// Affiche les migrations nécessaires à la base de données JSON, et les
+/// applique sauf en simulation
+fn migrate_database(dry_run: bool) -> Result<()> {
+    let path = Path::new(DB_FILE);
+    let mut secret = Some(database_secret(path)?);
+    let migrations = match Database::plan_migrations(path, secret.as_deref().map(Vec::as_slice)) {
+        // Une base en clair est migrée sans être chiffrée
+        Err(DBError::PlaintextDatabase) => {
+            secret = None;
+            Database::plan_migrations(path, None)?
+        }
+        result => result?,
+    };
+
+    if migrations.is_empty() {
+        println!("[*] La base de données est à jour");
+        return Ok(());
+    }
+    for migration in &migrations {
+        println!(
+            "[*] Version {}: {}",
+            migration.version, migration.description
+        );
+        for change in &migration.changes {
+            println!("    - {change}");
+        }
+    }
+
+    if dry_run {
+        println!("[*] Simulation: aucune modification enregistrée");
+    } else {
+        Database::open(path.into(), secret.as_deref().map(Vec::as_slice))?;
+        println!("[*] Base de données migrée");
+    }
+    Ok(())
+}
+
+/// Obtient le secret de chiffrement de la base de données, depuis
+/// l'environnement ou en le demandant à l'opérateur
+fn database_secret(path: &Path) -> Result<Zeroizing<Vec<u8>>> {
+    if let Ok(keyfile) = std::env::var(DB_KEYFILE_VAR) {
+        return Ok(Zeroizing::new(std::fs::read(keyfile)?));
+    }
+    if let Ok(passphrase) = std::env::var(DB_PASSPHRASE_VAR) {
+        return Ok(Zeroizing::new(passphrase.into_bytes()));
+    }
+
+    let mut prompt = Password::new("Phrase de passe de la base de données :")
+        .with_display_mode(inquire::PasswordDisplayMode::Masked);
+    if path.exists() {
+        prompt = prompt.without_confirmation();
+    }
+    Ok(Zeroizing::new(prompt.prompt()?.into_bytes()))
+}
+
+/// Ouvre la base de données chiffrée avec le stockage choisi
+fn open_storage() -> Result<Box<dyn Storage>> {
+    match std::env::var(DB_BACKEND_VAR).as_deref() {
+        Err(_) | Ok("json") => Ok(Box::new(open_database()?)),
+        Ok("sqlite") => {
+            let secret = database_secret(Path::new(SQLITE_FILE))?;
+            Ok(Box::new(SqliteDatabase::open(
+                Path::new(SQLITE_FILE),
+                Some(&secret),
+            )?))
+        }
+        Ok(other) => Err(anyhow!(
+            "{DB_BACKEND_VAR}: stockage inconnu '{other}' (json ou sqlite)"
+        )),
+    }
+}
+
+/// Ouvre la base de données JSON chiffrée, en proposant de chiffrer une
+/// ancienne base stockée en clair
+fn open_database() -> Result<Database> {
+    let secret = database_secret(Path::new(DB_FILE))?;
+
+    match Database::open(DB_FILE.into(), Some(&secret)) {
+        Err(DBError::PlaintextDatabase) => {
+            let migrate =
+                Confirm::new("La base de données n'est pas chiffrée. La chiffrer maintenant ?")
+                    .with_default(true)
+                    .prompt()?;
+            if migrate {
+                Ok(Database::encrypt_legacy(DB_FILE.into(), &secret)?)
+            } else {
+                eprintln!("[!] La base de données reste stockée en clair.");
+                Ok(Database::open(DB_FILE.into(), None)?)
+            }
+        }
+        result => Ok(result?),
+    }
+}
+
 fn main() -> anyhow::Result<()> {
     simple_logging::log_to_file("./karak.log", log::LevelFilter::Info)?;
 
-    let db = Database::open(DB_FILE.into())?;
-    let enforcer = Enforcer::load()?;
-    App::new(Service::new(db, enforcer)).start()
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        return run_command(&args);
+    }
+
+    let db = open_storage()?;
+    let enforcer = Enforcer::load()?.with_audit_log(AuditLog::open(AUDIT_FILE.into())?);
+
+    let password_policy = match std::env::var(MIN_PASSWORD_SCORE_VAR) {
+        Ok(score) => score
+            .parse()
+            .map_err(|_| InvalidInput)
+            .and_then(PasswordPolicy::with_min_score)
+            .map_err(|_| anyhow!("{MIN_PASSWORD_SCORE_VAR} doit être compris entre 0 et 4"))?,
+        Err(_) => PasswordPolicy::default(),
+    };
+
+    let hasher =
+        Hasher::new(hash_config()?).map_err(|e| anyhow!("Paramètres Argon2 invalides: {e}"))?;
+
+    let username_policy = UsernamePolicy::with_length(
+        env_or(USERNAME_MIN_LEN_VAR, DEFAULT_MIN_USERNAME_LEN)?,
+        env_or(USERNAME_MAX_LEN_VAR, DEFAULT_MAX_USERNAME_LEN)?,
+    )
+    .map_err(|e| anyhow!("{USERNAME_MIN_LEN_VAR}/{USERNAME_MAX_LEN_VAR}: {e}"))?;
+
+    let second_factor_policy = SecondFactorPolicy {
+        mandatory_for_staff: std::env::var(MANDATORY_2FA_VAR).is_ok_and(|value| value == "1"),
+    };
+
+    let service = Service::new(db, enforcer)
+        .with_password_policy(password_policy)
+        .with_username_policy(username_policy)
+        .with_hasher(hasher)
+        .with_second_factor_policy(second_factor_policy)
+        .with_blob_store(BlobStore::open(BLOB_DIR.into())?);
+    App::new(service).start()
 }
@@ -1,14 +1,24 @@
 //! Modèle de données
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use chrono::{DateTime, NaiveDate, Utc};
 
 use derive_more::Display;
 use serde::{Deserialize, Serialize};
 use strum_macros::EnumIter;
+use thiserror::Error;
 use uuid::Uuid;
 
-use crate::utils::input_validation::{AVSNumber, Username};
+use crate::blobs::BlobHash;
+use crate::utils::crypto::{Bytes, SealedBox};
+use crate::utils::input_validation::{
+    AVSNumber, DateOfBirth, Icd10Code, InsuranceNumber, Measurement, PersonName, PhoneNumber,
+    PostalCode, TextLine, Username,
+};
 use crate::utils::password_utils::PWHash;
+use crate::utils::totp::TotpSecret;
 
 /// Role d'un utilisateur: Médecin, Patient ou Admin
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, Hash, EnumIter, Display)]
@@ -27,6 +37,26 @@ pub enum BloodType {
     O,
 }
 
+/// Le facteur Rhésus d'un groupe sanguin
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Hash, EnumIter, Display)]
+pub enum Rhesus {
+    #[display("+")]
+    Positive,
+    #[display("-")]
+    Negative,
+}
+
+/// Le sexe d'un patient
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Hash, EnumIter, Display)]
+pub enum Sex {
+    #[display("Féminin")]
+    Female,
+    #[display("Masculin")]
+    Male,
+    #[display("Autre")]
+    Other,
+}
+
 /// Un identifiant unique d'utilisateur.
 #[derive(
     Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord, Display,
@@ -39,6 +69,12 @@ impl UserID {
     }
 }
 
+impl Default for UserID {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Un identifiant unique de rapport médical
 #[derive(
     Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord, Display,
@@ -51,6 +87,12 @@ impl ReportID {
     }
 }
 
+impl Default for ReportID {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Les données associées à un utilisateur.
 ///
 /// Un utilisateur peut être un médecin ou un simple patient.
@@ -58,17 +100,37 @@ impl ReportID {
 ///
 /// Indépendamment de son rôle, un utilisateur peut avoir
 /// un dossier médical, ou pas.
-#[derive(Debug, Serialize, Deserialize, Hash, Display)]
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, Display)]
 #[display("{username}")]
 pub struct UserData {
     pub id: UserID,
     pub role: Role,
     pub username: Username,
     pub password: PWHash,
+    /// L'utilisateur doit changer son mot de passe à la prochaine connexion
+    #[serde(default)]
+    pub must_change_password: bool,
+    #[serde(default)]
+    pub second_factor: Option<SecondFactor>,
+    /// Absentes tant qu'un compte antérieur au chiffrement ne s'est pas reconnecté
+    #[serde(default)]
+    pub keys: Option<UserKeys>,
+    /// Clés de signature des rapports, pour les médecins et les admins
+    #[serde(default)]
+    pub signing_keys: Option<SigningKeys>,
     pub medical_folder: Option<MedicalFolder>,
+    #[serde(flatten)]
+    pub provenance: Provenance,
 }
 
 impl UserData {
+    /// Indique si un second facteur doit être présenté à la connexion
+    pub fn has_second_factor(&self) -> bool {
+        self.second_factor
+            .as_ref()
+            .is_some_and(|second_factor| second_factor.confirmed)
+    }
+
     pub fn has_doctor(&self, doctor: UserID) -> bool {
         self.medical_folder
             .as_ref()
@@ -77,32 +139,475 @@ impl UserData {
     }
 }
 
+/// Second facteur d'authentification d'un utilisateur (TOTP)
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+pub struct SecondFactor {
+    pub secret: TotpSecret,
+    /// Le secret n'est utilisé qu'une fois qu'un premier code a été vérifié
+    pub confirmed: bool,
+    /// Dernier pas de temps accepté, pour refuser qu'un code soit rejoué
+    pub last_used_step: Option<u64>,
+    /// Codes de récupération à usage unique, hachés
+    pub recovery_codes: Vec<PWHash>,
+}
+
+/// La paire de clés X25519 d'un utilisateur.
+///
+/// La clé privée est chiffrée sous une clé dérivée de son mot de passe.
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+pub struct UserKeys {
+    pub public_key: Bytes,
+    pub sealed_private_key: Bytes,
+    pub salt: Bytes,
+}
+
+/// La paire de clés Ed25519 avec laquelle un médecin signe ses rapports.
+///
+/// La clé privée est chiffrée pour la clé publique X25519 de l'utilisateur.
+/// Elle est renouvelée si celle-ci change; les anciennes clés publiques
+/// sont conservées pour vérifier les rapports signés avant.
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+pub struct SigningKeys {
+    pub public_key: Bytes,
+    pub sealed_private_key: SealedBox,
+    /// La clé publique X25519 pour laquelle la clé privée est chiffrée
+    pub recipient: Bytes,
+    #[serde(default)]
+    pub retired: Vec<Bytes>,
+}
+
 /// Le contenu d'un rapport médical
-#[derive(Debug, Serialize, Deserialize, Hash, Display)]
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, Display)]
 #[display("{title}")]
 pub struct MedicalReport {
     pub id: ReportID,
     pub title: String,
     pub author: UserID,
     pub patient: UserID,
-    pub content: String,
+    /// Le texte actuel du rapport
+    pub content: ReportContent,
+    /// Les champs propres au type du rapport, aucun pour un rapport en
+    /// texte libre. Comme le titre, ils ne sont pas chiffrés.
+    #[serde(default)]
+    pub details: Option<ReportDetails>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    /// Les modifications successives, de la plus ancienne à la plus récente
+    #[serde(default)]
+    pub revisions: Vec<Revision>,
+    #[serde(flatten)]
+    pub provenance: Provenance,
+    /// Le rapport complété par cet addendum
+    #[serde(default)]
+    pub parent: Option<ReportID>,
+    /// Signature de la version originale par l'auteur
+    #[serde(default)]
+    pub signature: Option<ReportSignature>,
+    /// Présent une fois le rapport définitif: il ne peut plus être modifié,
+    /// seulement complété par des addenda
+    #[serde(default)]
+    pub seal: Option<ReportSeal>,
+}
+
+impl MedicalReport {
+    pub fn is_final(&self) -> bool {
+        self.seal.is_some()
+    }
+
+    pub fn kind(&self) -> Option<ReportKind> {
+        self.details.as_ref().map(ReportDetails::kind)
+    }
+}
+
+/// Le type d'un rapport médical
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, EnumIter, Display)]
+pub enum ReportKind {
+    #[display("Note de consultation")]
+    ConsultationNote,
+    #[display("Résultat de laboratoire")]
+    LabResult,
+    #[display("Ordonnance")]
+    Prescription,
+    #[display("Lettre de sortie")]
+    DischargeLetter,
+    #[display("Résultat d'imagerie")]
+    ImagingResult,
+}
+
+/// Les champs structurés d'un rapport, selon son type. Le texte du rapport
+/// en est le commentaire ou la conclusion.
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+pub enum ReportDetails {
+    ConsultationNote {
+        reason: TextLine,
+        diagnoses: Vec<Icd10Code>,
+    },
+    LabResult {
+        sampled_on: NaiveDate,
+        results: Vec<LabValue>,
+    },
+    Prescription {
+        medications: Vec<Medication>,
+        valid_until: NaiveDate,
+    },
+    DischargeLetter {
+        admitted: NaiveDate,
+        discharged: NaiveDate,
+        diagnoses: Vec<Icd10Code>,
+    },
+    ImagingResult {
+        performed_on: NaiveDate,
+        modality: ImagingModality,
+        region: TextLine,
+    },
+}
+
+impl ReportDetails {
+    pub fn kind(&self) -> ReportKind {
+        match self {
+            Self::ConsultationNote { .. } => ReportKind::ConsultationNote,
+            Self::LabResult { .. } => ReportKind::LabResult,
+            Self::Prescription { .. } => ReportKind::Prescription,
+            Self::DischargeLetter { .. } => ReportKind::DischargeLetter,
+            Self::ImagingResult { .. } => ReportKind::ImagingResult,
+        }
+    }
+
+    /// Vérifie la cohérence des champs entre eux et avec la date du jour
+    pub fn validate(&self, today: NaiveDate) -> Result<(), InvalidDetails> {
+        let past = |date: &NaiveDate| {
+            (*date <= today)
+                .then_some(())
+                .ok_or(InvalidDetails::FutureDate)
+        };
+        match self {
+            Self::ConsultationNote { .. } => Ok(()),
+            Self::LabResult {
+                sampled_on,
+                results,
+            } => {
+                past(sampled_on)?;
+                if results.is_empty() {
+                    return Err(InvalidDetails::Empty);
+                }
+                if results.iter().any(LabValue::has_inverted_range) {
+                    return Err(InvalidDetails::InvertedRange);
+                }
+                Ok(())
+            }
+            Self::Prescription {
+                medications,
+                valid_until,
+            } => {
+                if medications.is_empty() {
+                    return Err(InvalidDetails::Empty);
+                }
+                if *valid_until < today {
+                    return Err(InvalidDetails::Expired);
+                }
+                if medications.iter().any(Medication::ends_before_start) {
+                    return Err(InvalidDetails::EndBeforeStart);
+                }
+                Ok(())
+            }
+            Self::DischargeLetter {
+                admitted,
+                discharged,
+                diagnoses,
+            } => {
+                past(discharged)?;
+                if discharged < admitted {
+                    return Err(InvalidDetails::EndBeforeStart);
+                }
+                if diagnoses.is_empty() {
+                    return Err(InvalidDetails::Empty);
+                }
+                Ok(())
+            }
+            Self::ImagingResult { performed_on, .. } => past(performed_on),
+        }
+    }
+}
+
+/// Une incohérence dans les champs structurés d'un rapport ou dans une
+/// entrée du dossier clinique
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum InvalidDetails {
+    #[error("une date est dans le futur")]
+    FutureDate,
+    #[error("une date de fin précède la date de début")]
+    EndBeforeStart,
+    #[error("la date de validité est déjà passée")]
+    Expired,
+    #[error("au moins une entrée est requise")]
+    Empty,
+    #[error("une valeur de référence minimale dépasse la maximale")]
+    InvertedRange,
+}
+
+/// Une analyse de laboratoire, et ses valeurs de référence si elles sont connues
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+pub struct LabValue {
+    pub analysis: TextLine,
+    pub value: Measurement,
+    pub unit: TextLine,
+    pub low: Option<Measurement>,
+    pub high: Option<Measurement>,
+}
+
+impl LabValue {
+    fn has_inverted_range(&self) -> bool {
+        match (&self.low, &self.high) {
+            (Some(low), Some(high)) => low.value() > high.value(),
+            _ => false,
+        }
+    }
+
+    /// Indique si la valeur sort des valeurs de référence
+    pub fn is_abnormal(&self) -> bool {
+        let value = self.value.value();
+        self.low.as_ref().is_some_and(|low| value < low.value())
+            || self.high.as_ref().is_some_and(|high| value > high.value())
+    }
+}
+
+impl fmt::Display for LabValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} {}", self.analysis, self.value, self.unit)?;
+        match (&self.low, &self.high) {
+            (Some(low), Some(high)) => write!(f, " ({low} - {high})")?,
+            (Some(low), None) => write!(f, " (> {low})")?,
+            (None, Some(high)) => write!(f, " (< {high})")?,
+            (None, None) => {}
+        }
+        if self.is_abnormal() {
+            write!(f, " [!]")?;
+        }
+        Ok(())
+    }
+}
+
+/// La technique d'un examen d'imagerie
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Hash, EnumIter, Display)]
+pub enum ImagingModality {
+    #[display("Radiographie")]
+    Radiography,
+    #[display("Échographie")]
+    Ultrasound,
+    #[display("Scanner")]
+    ComputedTomography,
+    #[display("IRM")]
+    MagneticResonance,
+    #[display("Autre")]
+    Other,
+}
+
+/// Taille maximale d'une pièce jointe, en octets
+pub const MAX_ATTACHMENT_SIZE: usize = 20 * 1024 * 1024;
+
+/// Un fichier joint à un rapport. Son contenu est chiffré sous une clé
+/// qui lui est propre, elle-même chiffrée pour la clé du dossier.
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, Display)]
+#[display("{name} ({mime_type}, {size} octets)")]
+pub struct Attachment {
+    pub name: TextLine,
+    pub mime_type: MimeType,
+    /// Taille du fichier d'origine, en octets
+    pub size: usize,
+    /// L'empreinte du fichier chiffré dans le magasin
+    pub blob: BlobHash,
+    pub sealed_key: SealedBox,
+}
+
+/// Les types de fichiers acceptés en pièce jointe
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Display)]
+pub enum MimeType {
+    #[serde(rename = "application/pdf")]
+    #[display("application/pdf")]
+    Pdf,
+    #[serde(rename = "image/jpeg")]
+    #[display("image/jpeg")]
+    Jpeg,
+    #[serde(rename = "image/png")]
+    #[display("image/png")]
+    Png,
+    #[serde(rename = "application/dicom")]
+    #[display("application/dicom")]
+    Dicom,
+}
+
+impl MimeType {
+    /// Reconnaît le type d'un fichier à ses premiers octets, sans se fier à
+    /// son nom
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(b"%PDF-") {
+            Some(Self::Pdf)
+        } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(Self::Jpeg)
+        } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Self::Png)
+        } else if bytes.get(128..132) == Some(b"DICM") {
+            Some(Self::Dicom)
+        } else {
+            None
+        }
+    }
+}
+
+/// Une signature Ed25519 et la clé publique qui permet de la vérifier
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+pub struct ReportSignature {
+    pub public_key: Bytes,
+    pub signature: Bytes,
+}
+
+/// La signature par l'auteur de la version définitive d'un rapport
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+pub struct ReportSeal {
+    pub sealed_at: DateTime<Utc>,
+    pub signature: ReportSignature,
+}
+
+/// Dates de création et de dernière modification, et auteur de celle-ci.
+///
+/// Inconnues pour les données enregistrées avant leur introduction.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Hash)]
+pub struct Provenance {
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub updated_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub updated_by: Option<UserID>,
+}
+
+impl Provenance {
+    pub fn new(at: DateTime<Utc>, by: UserID) -> Self {
+        Self {
+            created_at: Some(at),
+            updated_at: Some(at),
+            updated_by: Some(by),
+        }
+    }
+
+    /// Enregistre une modification
+    pub fn touch(&mut self, at: DateTime<Utc>, by: UserID) {
+        self.updated_at = Some(at);
+        self.updated_by = Some(by);
+    }
+}
+
+/// Une modification d'un rapport, qui ne peut plus être changée.
+///
+/// Le texte remplacé est conservé, chiffré comme le rapport: la version `n`
+/// d'un rapport est le texte remplacé par la modification `n`, la dernière
+/// version est son contenu actuel.
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+pub struct Revision {
+    pub previous: ReportContent,
+    pub editor: UserID,
+    pub timestamp: DateTime<Utc>,
+    pub reason: TextLine,
+}
+
+/// Le texte d'un rapport, chiffré pour la clé du dossier du patient.
+///
+/// Les rapports écrits avant le chiffrement sont en clair jusqu'à ce que
+/// le dossier reçoive ses clés.
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+#[serde(untagged)]
+pub enum ReportContent {
+    Plain(String),
+    Sealed(SealedBox),
 }
 
 /// Les données personnelles d'un patient
-#[derive(Debug, Serialize, Deserialize, Hash)]
+///
+/// Seuls le numéro AVS et le groupe ABO sont obligatoires; les autres champs
+/// sont vides dans les dossiers créés avant leur introduction.
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
 pub struct PersonalData {
     pub avs_number: AVSNumber,
     pub blood_type: BloodType,
+    #[serde(default)]
+    pub rhesus: Option<Rhesus>,
+    #[serde(default)]
+    pub full_name: Option<FullName>,
+    #[serde(default)]
+    pub date_of_birth: Option<DateOfBirth>,
+    #[serde(default)]
+    pub sex: Option<Sex>,
+    #[serde(default)]
+    pub address: Option<Address>,
+    #[serde(default)]
+    pub emergency_contact: Option<EmergencyContact>,
+    #[serde(default)]
+    pub insurance_number: Option<InsuranceNumber>,
+}
+
+impl PersonalData {
+    pub fn new(avs_number: AVSNumber, blood_type: BloodType) -> Self {
+        Self {
+            avs_number,
+            blood_type,
+            rhesus: None,
+            full_name: None,
+            date_of_birth: None,
+            sex: None,
+            address: None,
+            emergency_contact: None,
+            insurance_number: None,
+        }
+    }
+
+    /// Le groupe sanguin complet, `A+` ou `O-`, ou seulement `A` si le
+    /// facteur Rhésus n'est pas connu
+    pub fn blood_group(&self) -> String {
+        match self.rhesus {
+            Some(rhesus) => format!("{}{rhesus}", self.blood_type),
+            None => self.blood_type.to_string(),
+        }
+    }
+}
+
+/// Le prénom et le nom d'une personne
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, Display)]
+#[display("{first_name} {last_name}")]
+pub struct FullName {
+    pub first_name: PersonName,
+    pub last_name: PersonName,
+}
+
+/// Une adresse postale
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, Display)]
+#[display("{street}, {postal_code} {city}, {country}")]
+pub struct Address {
+    pub street: TextLine,
+    pub postal_code: PostalCode,
+    pub city: TextLine,
+    pub country: TextLine,
+}
+
+/// La personne à prévenir en cas d'urgence
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, Display)]
+#[display("{name} ({phone})")]
+pub struct EmergencyContact {
+    pub name: FullName,
+    pub phone: PhoneNumber,
 }
 
 /// Un dossier médical pour un patient donné.
 /// Contient des données personnelles génériques,
 /// une liste de rapports, et une liste
 /// de médecins traitants autorisés.
-#[derive(Debug, Serialize, Deserialize, Hash)]
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
 pub struct MedicalFolder {
     pub personal_data: PersonalData,
     pub doctors: BTreeSet<UserID>,
+    #[serde(default)]
+    pub keys: Option<FolderKeys>,
+    #[serde(default)]
+    pub clinical: ClinicalRecord,
 }
 
 impl MedicalFolder {
@@ -110,6 +615,116 @@ impl MedicalFolder {
         Self {
             personal_data,
             doctors: BTreeSet::default(),
+            keys: None,
+            clinical: ClinicalRecord::default(),
         }
     }
 }
+
+/// Les informations cliniques structurées d'un dossier, complétées par les
+/// médecins traitants
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Hash)]
+pub struct ClinicalRecord {
+    pub allergies: Vec<Allergy>,
+    pub medications: Vec<Medication>,
+    pub diagnoses: Vec<Diagnosis>,
+}
+
+/// Une entrée à ajouter au dossier clinique
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+pub enum ClinicalEntry {
+    Allergy(Allergy),
+    Medication(Medication),
+    Diagnosis(Diagnosis),
+}
+
+impl ClinicalEntry {
+    /// Le type d'entrée, tel qu'il est journalisé
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Allergy(_) => "allergy",
+            Self::Medication(_) => "medication",
+            Self::Diagnosis(_) => "diagnosis",
+        }
+    }
+
+    /// Vérifie les dates de l'entrée, comme celles des rapports structurés
+    pub fn validate(&self, today: NaiveDate) -> Result<(), InvalidDetails> {
+        match self {
+            Self::Medication(medication) if medication.ends_before_start() => {
+                Err(InvalidDetails::EndBeforeStart)
+            }
+            Self::Diagnosis(diagnosis) if diagnosis.date > today => Err(InvalidDetails::FutureDate),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// La gravité d'une allergie
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Hash, EnumIter, Display)]
+pub enum Severity {
+    #[display("légère")]
+    Mild,
+    #[display("modérée")]
+    Moderate,
+    #[display("sévère")]
+    Severe,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, Display)]
+#[display("{substance} ({severity})")]
+pub struct Allergy {
+    pub substance: TextLine,
+    pub severity: Severity,
+}
+
+/// Un traitement, en cours tant qu'il n'a pas de date de fin passée
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, Display)]
+#[display("{drug}, {dose}, depuis le {}", start.format("%d.%m.%Y"))]
+pub struct Medication {
+    pub drug: TextLine,
+    pub dose: TextLine,
+    pub start: NaiveDate,
+    pub end: Option<NaiveDate>,
+}
+
+impl Medication {
+    pub fn is_current(&self, today: NaiveDate) -> bool {
+        self.start <= today && self.end.is_none_or(|end| today <= end)
+    }
+
+    pub fn ends_before_start(&self) -> bool {
+        self.end.is_some_and(|end| end < self.start)
+    }
+}
+
+/// Un diagnostic codé selon la CIM-10, posé par un médecin
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, Display)]
+#[display("{code}, le {}", date.format("%d.%m.%Y"))]
+pub struct Diagnosis {
+    pub code: Icd10Code,
+    pub date: NaiveDate,
+    pub author: UserID,
+}
+
+/// La paire de clés d'un dossier médical.
+///
+/// Les rapports sont chiffrés pour la clé publique du dossier, que tout
+/// auteur autorisé peut utiliser. La clé privée n'est accessible qu'aux
+/// utilisateurs qui en ont reçu une copie: le patient et ses médecins traitants.
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+pub struct FolderKeys {
+    pub public_key: Bytes,
+    pub grants: BTreeMap<UserID, KeyGrant>,
+    /// Un médecin a été retiré sans que la clé puisse être renouvelée
+    #[serde(default)]
+    pub rotation_pending: bool,
+}
+
+/// La clé privée d'un dossier, chiffrée pour la clé publique d'un utilisateur
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+pub struct KeyGrant {
+    /// La clé publique du destinataire au moment de la copie
+    pub recipient: Bytes,
+    pub sealed_key: SealedBox,
+}
@@ -1,17 +1,54 @@
 //! API d'accès au dossier, et point d'entrée unique pour le contrôle d'accès.
 //!
+use crate::audit::{AuditError, AuditFilter, AuditRecord, AuditTarget, Decision};
 use crate::authorization::{AccessDenied, Context, Enforcer};
-use crate::db::{DBError, Database};
-use crate::models::{MedicalFolder, MedicalReport, PersonalData, ReportID, Role, UserData, UserID};
-use crate::utils::input_validation::{password_input_validation, Username};
-use crate::utils::password_utils::{hash, verify};
-use log::info;
+use crate::blobs::{BlobHash, BlobStore};
+use crate::db::{DBError, Storage};
+use crate::models::{
+    Attachment, ClinicalEntry, ClinicalRecord, FolderKeys, InvalidDetails, MedicalFolder,
+    MedicalReport, MimeType, PersonalData, Provenance, ReportContent, ReportDetails, ReportID,
+    ReportKind, ReportSeal, ReportSignature, Revision, Role, SecondFactor, SigningKeys, UserData,
+    UserID, UserKeys, MAX_ATTACHMENT_SIZE,
+};
+use crate::signature::{report_message, SignatureStatus};
+use crate::utils::clock::{Clock, SystemClock};
+use crate::utils::crypto::{CryptoError, PublicKey, StaticSecret};
+use crate::utils::input_validation::{
+    password_validation, PasswordPolicy, PasswordStrength, TextLine, Username,
+};
+use crate::utils::password_utils::Hasher;
+use crate::utils::throttling::LockoutPolicy;
+use crate::utils::totp::{generate_recovery_codes, TotpSecret};
+use crate::utils::username::{UsernameError, UsernamePolicy};
+use chrono::{DateTime, Utc};
+use ed25519_dalek::SigningKey;
+use log::{info, warn};
+use std::io;
 use thiserror::Error;
 
+/// Règles d'utilisation du second facteur d'authentification
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SecondFactorPolicy {
+    /// Les médecins et les admins doivent activer le TOTP avant d'accéder aux dossiers
+    pub mandatory_for_staff: bool,
+}
+
 pub struct Service {
     user: Option<UserID>,
-    db: Database,
+    /// Utilisateur dont le mot de passe a été vérifié, en attente du second facteur
+    pending_second_factor: Option<UserID>,
+    /// Clé privée de l'utilisateur, déchiffrée avec son mot de passe à la connexion
+    session_key: Option<StaticSecret>,
+    db: Box<dyn Storage>,
+    /// Les pièces jointes des rapports, chiffrées
+    blobs: BlobStore,
     enforcer: Enforcer,
+    password_policy: PasswordPolicy,
+    username_policy: UsernamePolicy,
+    hasher: Hasher,
+    lockout_policy: LockoutPolicy,
+    second_factor_policy: SecondFactorPolicy,
+    clock: Box<dyn Clock>,
 }
 
 #[derive(Debug, Error)]
@@ -22,91 +59,636 @@ pub enum ServiceError {
     #[error("Utilisateur déja inscrit")]
     UserAlreadyExists,
 
+    #[error(transparent)]
+    InvalidUsername(#[from] UsernameError),
+
     #[error(transparent)]
     DBError(#[from] DBError),
 
     #[error("Pas de dossier pour ce patient")]
     NotAPatient,
 
+    #[error("Ce numéro AVS est déjà celui d'un autre patient")]
+    AvsNumberInUse,
+
     #[error("Rapport inexistant")]
     NoSuchReport,
+
+    #[error("Mot de passe actuel incorrect")]
+    WrongPassword,
+
+    #[error("Le nouveau mot de passe doit être différent de l'actuel")]
+    SamePassword,
+
+    #[error("Code d'authentification invalide")]
+    InvalidSecondFactor,
+
+    #[error("L'authentification à deux facteurs est déjà activée")]
+    SecondFactorAlreadyEnabled,
+
+    #[error("Mot de passe trop faible: {feedback}")]
+    WeakPassword { feedback: PasswordStrength },
+
+    #[error("Journal d'audit indisponible: {0}")]
+    Audit(#[from] AuditError),
+
+    #[error("Vous n'avez pas reçu la clé de ce dossier")]
+    NoKeyGrant,
+
+    #[error("Seul l'auteur d'un rapport, connecté avec sa clé de signature, peut le signer")]
+    NoSigningKey,
+
+    #[error("Ce rapport est définitif et ne peut plus être modifié")]
+    ReportSealed,
+
+    #[error("Seul un rapport définitif peut recevoir un addendum")]
+    ReportNotFinal,
+
+    #[error("Rapport invalide: {0}")]
+    InvalidReport(#[from] InvalidDetails),
+
+    #[error("Entrée invalide: {0}")]
+    InvalidEntry(InvalidDetails),
+
+    #[error("Pièce jointe trop volumineuse: {} Mo au maximum", MAX_ATTACHMENT_SIZE >> 20)]
+    AttachmentTooLarge,
+
+    #[error("Type de fichier non accepté: seuls les PDF, JPEG, PNG et DICOM peuvent être joints")]
+    UnsupportedAttachment,
+
+    #[error("Pièce jointe inexistante")]
+    NoSuchAttachment,
+
+    #[error("Ce dossier n'a pas encore de clé: le patient doit d'abord se connecter")]
+    FolderNotEncrypted,
+
+    #[error("Pièce jointe illisible: {0}")]
+    Blob(#[from] io::Error),
+
+    #[error(transparent)]
+    Crypto(#[from] CryptoError),
 }
 
 #[derive(Debug, Error)]
 pub enum LoginError {
     #[error("Mauvais mot de passe ou utilisateur inconnu")]
     InvalidCredentials,
+
+    #[error(
+        "Trop de tentatives de connexion, réessayez après le {}",
+        until.format("%d.%m.%Y à %H:%M:%S UTC")
+    )]
+    Locked { until: DateTime<Utc> },
+
+    #[error("Code d'authentification requis")]
+    SecondFactorRequired,
+}
+
+/// Une version d'un rapport, déchiffrée
+#[derive(Debug)]
+pub struct ReportVersion<'a> {
+    pub text: String,
+    /// La modification qui a produit cette version, aucune pour l'originale
+    pub revision: Option<&'a Revision>,
+}
+
+/// Le résultat de la vérification des signatures d'un rapport
+#[derive(Debug)]
+pub struct ReportVerification {
+    /// Signature de la version originale
+    pub original: SignatureStatus,
+    /// Nombre de modifications depuis la version originale
+    pub revisions: usize,
+    /// Date et signature de la version définitive
+    pub seal: Option<(DateTime<Utc>, SignatureStatus)>,
+}
+
+/// Les écritures faites pendant une connexion n'empêchent pas de se connecter
+/// si elles échouent, mais sont signalées
+fn warn_unsaved(username: &Username, result: Result<(), DBError>) {
+    if let Err(e) = result {
+        warn!("Connexion de {username} non enregistrée: {e}");
+    }
 }
 
 impl Service {
-    pub fn new(db: Database, enforcer: Enforcer) -> Self {
+    pub fn new(db: Box<dyn Storage>, enforcer: Enforcer) -> Self {
         Self {
             db,
+            blobs: BlobStore::default(),
             user: None,
+            pending_second_factor: None,
+            session_key: None,
             enforcer,
+            password_policy: PasswordPolicy::default(),
+            username_policy: UsernamePolicy::default(),
+            hasher: Hasher::default(),
+            lockout_policy: LockoutPolicy::default(),
+            second_factor_policy: SecondFactorPolicy::default(),
+            clock: Box::new(SystemClock),
         }
     }
 
-    pub fn save(&self) -> Result<(), std::io::Error> {
-        self.db.save()
+    /// Remplace les règles d'utilisation du second facteur
+    pub fn with_second_factor_policy(mut self, policy: SecondFactorPolicy) -> Self {
+        self.second_factor_policy = policy;
+        self
+    }
+
+    /// Remplace les règles de limitation des tentatives de connexion
+    pub fn with_lockout_policy(mut self, policy: LockoutPolicy) -> Self {
+        self.lockout_policy = policy;
+        self
+    }
+
+    /// Remplace les paramètres de hachage des mots de passe. Les hachés
+    /// existants plus faibles seront mis à jour à la prochaine connexion.
+    pub fn with_hasher(mut self, hasher: Hasher) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    /// Remplace les exigences de robustesse des mots de passe
+    pub fn with_password_policy(mut self, policy: PasswordPolicy) -> Self {
+        self.password_policy = policy;
+        self
+    }
+
+    /// Remplace les règles de choix des noms d'utilisateur
+    pub fn with_username_policy(mut self, policy: UsernamePolicy) -> Self {
+        self.username_policy = policy;
+        self
+    }
+
+    /// Remplace l'horloge qui date les connexions et les modifications
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
     }
 
-    /// Enregistre un nouvel utilisateur (Patient ou Docteur) dans la base de données.
-    pub fn register(&mut self, username: Username) -> Result<UserID, ServiceError> {
-        let password = password_input_validation(username.as_ref());
-        let password = hash(&password);
+    /// Remplace le magasin des pièces jointes, en mémoire par défaut
+    pub fn with_blob_store(mut self, blobs: BlobStore) -> Self {
+        self.blobs = blobs;
+        self
+    }
 
+    pub fn save(&self) -> Result<(), DBError> {
+        self.db.save()
+    }
+
+    /// Enregistre un nouvel utilisateur dans la base de données.
+    ///
+    /// Par défaut, le compte est créé avec le rôle `Patient`. Un autre rôle
+    /// ne peut être demandé que par un utilisateur connecté ayant le droit
+    /// de changer les rôles.
+    pub fn register(
+        &mut self,
+        username: Username,
+        password: &str,
+        role: Option<Role>,
+    ) -> Result<UserID, ServiceError> {
         if self.db.lookup_username(&username).is_some() {
             return Err(ServiceError::UserAlreadyExists);
         }
+        self.username_policy.validate(&username)?;
 
+        password_validation(password, username.as_ref(), &self.password_policy)
+            .map_err(|feedback| ServiceError::WeakPassword { feedback })?;
+
+        let (keys, _) = UserKeys::generate(password)?;
         let new_uid = UserID::new();
         let new_user = UserData {
             id: new_uid,
             role: Role::Patient,
             username,
-            password,
+            password: self.hasher.hash(password),
+            must_change_password: false,
+            second_factor: None,
+            keys: Some(keys),
+            signing_keys: None,
             medical_folder: None,
+            provenance: Provenance::new(self.clock.now(), self.user.unwrap_or(new_uid)),
         };
 
+        let role = role.unwrap_or(Role::Patient);
+        if !matches!(role, Role::Patient) {
+            self.enforce()?.update_role(&new_user, role)?;
+        }
+        let new_user = UserData { role, ..new_user };
+
+        self.record_change(
+            "register",
+            AuditTarget::user(new_uid),
+            format!("username: {}, role: {role}", new_user.username),
+        )?;
         info!(
             "Compte créé avec succès pour l'utilisateur {}",
             &new_user.username
         );
-        self.db.store_user(new_user);
+        self.db.store_user(new_user)?;
+        self.refresh_signing_keys(new_uid)?;
         Ok(new_uid)
     }
 
+    /// L'utilisateur connecté, auteur d'une modification
+    fn editor(&self) -> Result<UserID, ServiceError> {
+        self.user.ok_or(ServiceError::AccessDenied(AccessDenied))
+    }
+
+    /// Consigne une modification des données dans le journal d'audit,
+    /// juste avant qu'elle ne soit appliquée.
+    ///
+    /// Sans utilisateur connecté (inscription), l'auteur est l'utilisateur concerné.
+    fn record_change(
+        &self,
+        action: &str,
+        target: AuditTarget,
+        details: impl Into<String>,
+    ) -> Result<(), ServiceError> {
+        let subject = self
+            .user
+            .or(target.user)
+            .ok_or(ServiceError::AccessDenied(AccessDenied))?;
+        let record = AuditRecord::new(
+            self.clock.now(),
+            subject,
+            action,
+            target,
+            Decision::Applied,
+            details.into(),
+        );
+        Ok(self.enforcer.audit_log().append(record)?)
+    }
+
     /// Obtient les données courantes de l'utilisateur connecté
     fn get_subject(&self) -> Option<&UserData> {
         self.db.get_user(self.user?).ok()
     }
 
-    /// Crée un contexte d'autorisation ayant l'utilisateur connecté comme sujet
+    /// Crée un contexte d'autorisation ayant l'utilisateur connecté comme sujet.
+    /// Tant qu'il doit changer son mot de passe, seuls le changement et la
+    /// déconnexion, qui n'en ont pas besoin, lui sont permis.
     fn enforce(&self) -> Result<Context<'_>, ServiceError> {
         let subject = self
             .get_subject()
+            .filter(|subject| !self.requires_second_factor_enrolment(subject))
+            .filter(|subject| !subject.must_change_password)
             .ok_or(ServiceError::AccessDenied(AccessDenied))?;
 
-        Ok(self.enforcer.with_subject(subject))
+        Ok(self.enforcer.with_subject(subject, self.clock.now()))
     }
 
     /// Vérifie si le mot de passe est correct, et si oui, enregistre
     /// L'utilisateur comme utilisateur courant.
+    ///
+    /// Les échecs répétés imposent un délai croissant puis un verrouillage
+    /// temporaire, appliqués de la même manière aux noms inconnus.
+    ///
+    /// Si le haché stocké a été produit avec des paramètres plus faibles
+    /// que ceux du service, il est remplacé de manière transparente.
     pub fn login(&mut self, username: &Username, password: &str) -> Result<UserID, LoginError> {
+        let now = self.clock.now();
+        let policy = self.lockout_policy;
+        if let Some(until) = self
+            .db
+            .get_login_attempts(username)
+            .and_then(|attempts| attempts.is_blocked(&policy, now))
+        {
+            return Err(LoginError::Locked { until });
+        }
+
         let user = self.db.lookup_username(username);
         let hash = user.as_ref().map(|u| &u.password);
-        if !verify(password, hash) {
+        if !self.hasher.verify(password, hash) {
+            let result = self
+                .db
+                .prune_login_attempts(&|attempts| attempts.is_expired(&policy, now));
+            warn_unsaved(username, result);
+
+            let mut attempts = self
+                .db
+                .get_login_attempts(username)
+                .cloned()
+                .unwrap_or_default();
+            attempts.record_failure(now);
+            if attempts.failures >= policy.max_attempts {
+                warn!("Connexion verrouillée pour le nom d'utilisateur {username}");
+            }
+            let result = self.db.store_login_attempts(username, Some(attempts));
+            warn_unsaved(username, result);
             return Err(LoginError::InvalidCredentials);
         }
         let user = user.unwrap();
-        self.user = Some(user.id);
-        Ok(user.id)
+        let user_id = user.id;
+        let has_second_factor = user.has_second_factor();
+
+        if self.hasher.needs_rehash(&user.password) {
+            let new_hash = self.hasher.hash(password);
+            let result = self
+                .db
+                .update_user(user_id, |user| user.password = new_hash);
+            if result.is_ok() {
+                info!("Haché du mot de passe mis à jour pour l'utilisateur {username}");
+            }
+            warn_unsaved(username, result);
+        }
+
+        self.user = None;
+        self.session_key = self.unlock_keys(user_id, password);
+        let result = self.refresh_signing_keys(user_id);
+        warn_unsaved(username, result);
+        if has_second_factor {
+            self.pending_second_factor = Some(user_id);
+            return Err(LoginError::SecondFactorRequired);
+        }
+
+        let result = self.db.store_login_attempts(username, None);
+        warn_unsaved(username, result);
+        self.user = Some(user_id);
+        self.refresh_accessible_folders();
+        Ok(user_id)
+    }
+
+    /// Déchiffre la clé privée d'un utilisateur dont le mot de passe vient
+    /// d'être vérifié. Les comptes antérieurs au chiffrement reçoivent leur
+    /// paire de clés à cette occasion.
+    fn unlock_keys(&mut self, user_id: UserID, password: &str) -> Option<StaticSecret> {
+        let user = self.db.get_user(user_id).ok()?;
+        if let Some(keys) = &user.keys {
+            return keys
+                .unlock(password)
+                .inspect_err(|e| warn!("Clé privée de {} inutilisable: {e}", user.username))
+                .ok();
+        }
+
+        let username = user.username.clone();
+        let (keys, secret) = UserKeys::generate(password)
+            .inspect_err(|e| warn!("Génération de clés impossible: {e}"))
+            .ok()?;
+        let result = self.db.update_user(user_id, |user| user.keys = Some(keys));
+        if result.is_err() {
+            warn_unsaved(&username, result);
+            return None;
+        }
+        info!("Paire de clés créée pour l'utilisateur {username}");
+        Some(secret)
+    }
+
+    /// Termine une connexion en attente du second facteur, avec un code
+    /// TOTP ou un code de récupération.
+    ///
+    /// Les codes invalides comptent comme des échecs de connexion.
+    pub fn verify_second_factor(&mut self, code: &str) -> Result<UserID, LoginError> {
+        let now = self.clock.now();
+        let user_id = self
+            .pending_second_factor
+            .ok_or(LoginError::InvalidCredentials)?;
+        let user = self
+            .db
+            .get_user(user_id)
+            .map_err(|_| LoginError::InvalidCredentials)?;
+        let username = user.username.clone();
+
+        if let Some(until) = self
+            .db
+            .get_login_attempts(&username)
+            .and_then(|attempts| attempts.is_blocked(&self.lockout_policy, now))
+        {
+            self.pending_second_factor = None;
+            self.session_key = None;
+            return Err(LoginError::Locked { until });
+        }
+
+        let Some(second_factor) = &user.second_factor else {
+            return Err(LoginError::InvalidCredentials);
+        };
+
+        // Code TOTP, qui ne peut pas être rejoué
+        let totp_step = second_factor
+            .secret
+            .verify(code, now.timestamp().max(0) as u64)
+            .filter(|step| second_factor.last_used_step.is_none_or(|last| *step > last));
+
+        // Sinon, code de récupération, qui est consommé
+        let recovery_code = match totp_step {
+            Some(_) => None,
+            None => second_factor
+                .recovery_codes
+                .iter()
+                .position(|hash| self.hasher.verify(code.trim(), Some(hash))),
+        };
+
+        if totp_step.is_none() && recovery_code.is_none() {
+            let mut attempts = self
+                .db
+                .get_login_attempts(&username)
+                .cloned()
+                .unwrap_or_default();
+            attempts.record_failure(now);
+            let result = self.db.store_login_attempts(&username, Some(attempts));
+            warn_unsaved(&username, result);
+            return Err(LoginError::InvalidCredentials);
+        }
+
+        let result = self.db.update_user(user_id, |user| {
+            if let Some(second_factor) = &mut user.second_factor {
+                if let Some(step) = totp_step {
+                    second_factor.last_used_step = Some(step);
+                }
+                if let Some(index) = recovery_code {
+                    second_factor.recovery_codes.remove(index);
+                }
+            }
+        });
+        if recovery_code.is_some() {
+            info!("Code de récupération utilisé par l'utilisateur {username}");
+        }
+        warn_unsaved(&username, result);
+
+        let result = self.db.store_login_attempts(&username, None);
+        warn_unsaved(&username, result);
+        self.pending_second_factor = None;
+        self.user = Some(user_id);
+        self.refresh_accessible_folders();
+        Ok(user_id)
+    }
+
+    /// Indique si un utilisateur doit activer le second facteur avant d'accéder
+    /// aux dossiers
+    fn requires_second_factor_enrolment(&self, user: &UserData) -> bool {
+        self.second_factor_policy.mandatory_for_staff
+            && matches!(user.role, Role::Doctor | Role::Admin)
+            && !user.has_second_factor()
+    }
+
+    /// L'heure du service, celle qu'il consigne et avec laquelle il valide les dates
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    /// Indique si l'utilisateur connecté doit activer le second facteur
+    pub fn must_enroll_second_factor(&self) -> bool {
+        self.get_subject()
+            .is_some_and(|user| self.requires_second_factor_enrolment(user))
+    }
+
+    /// Commence l'activation du TOTP pour l'utilisateur connecté.
+    ///
+    /// Retourne l'URI de configuration à saisir dans une application
+    /// d'authentification. Le TOTP n'est actif qu'une fois confirmé.
+    pub fn begin_totp_enrolment(&mut self) -> Result<String, ServiceError> {
+        let user_id = self.user.ok_or(ServiceError::AccessDenied(AccessDenied))?;
+        let mut user = self.db.get_user(user_id)?.clone();
+        if user.has_second_factor() {
+            return Err(ServiceError::SecondFactorAlreadyEnabled);
+        }
+
+        let secret = TotpSecret::generate();
+        let uri = secret.provisioning_uri(user.username.as_ref());
+        user.second_factor = Some(SecondFactor {
+            secret,
+            confirmed: false,
+            last_used_step: None,
+            recovery_codes: Vec::new(),
+        });
+        self.db.store_user(user)?;
+        Ok(uri)
+    }
+
+    /// Confirme l'activation du TOTP avec un premier code valide.
+    ///
+    /// Retourne les codes de récupération, qui ne seront plus jamais affichés.
+    pub fn confirm_totp_enrolment(&mut self, code: &str) -> Result<Vec<String>, ServiceError> {
+        let user_id = self.user.ok_or(ServiceError::AccessDenied(AccessDenied))?;
+        let now = self.clock.now();
+
+        let recovery_codes = generate_recovery_codes();
+        let hashes = recovery_codes
+            .iter()
+            .map(|code| self.hasher.hash(code))
+            .collect();
+
+        let mut user = self.db.get_user(user_id)?.clone();
+        let second_factor = match &mut user.second_factor {
+            Some(second_factor) if !second_factor.confirmed => second_factor,
+            Some(_) => return Err(ServiceError::SecondFactorAlreadyEnabled),
+            None => return Err(ServiceError::InvalidSecondFactor),
+        };
+        let step = second_factor
+            .secret
+            .verify(code, now.timestamp().max(0) as u64)
+            .ok_or(ServiceError::InvalidSecondFactor)?;
+
+        second_factor.confirmed = true;
+        second_factor.last_used_step = Some(step);
+        second_factor.recovery_codes = hashes;
+        user.provenance.touch(now, user_id);
+        info!("TOTP activé pour l'utilisateur {}", user.username);
+
+        self.record_change("enable-second-factor", AuditTarget::user(user_id), "TOTP")?;
+        self.db.store_user(user)?;
+        Ok(recovery_codes)
+    }
+
+    /// Indique si l'utilisateur connecté doit changer son mot de passe
+    /// avant de continuer
+    pub fn must_change_password(&self) -> bool {
+        self.get_subject()
+            .map(|user| user.must_change_password)
+            .unwrap_or(false)
+    }
+
+    /// Change le mot de passe de l'utilisateur connecté, après avoir
+    /// vérifié son mot de passe actuel.
+    pub fn change_password(&mut self, old: &str, new: &str) -> Result<(), ServiceError> {
+        let user = self
+            .get_subject()
+            .ok_or(ServiceError::AccessDenied(AccessDenied))?;
+
+        if !self.hasher.verify(old, Some(&user.password)) {
+            return Err(ServiceError::WrongPassword);
+        }
+        // Garder un mot de passe temporaire lèverait l'obligation de le changer
+        if self.hasher.verify(new, Some(&user.password)) {
+            return Err(ServiceError::SamePassword);
+        }
+        password_validation(new, user.username.as_ref(), &self.password_policy)
+            .map_err(|feedback| ServiceError::WeakPassword { feedback })?;
+
+        // La clé privée est conservée, seule la clé qui la protège change
+        let keys = match &user.keys {
+            Some(keys) => UserKeys::protect(&keys.unlock(old)?, new)?,
+            None => UserKeys::generate(new)?.0,
+        };
+
+        self.record_change("change-password", AuditTarget::user(user.id), "")?;
+        let mut user = user.clone();
+        user.password = self.hasher.hash(new);
+        user.keys = Some(keys);
+        user.must_change_password = false;
+        user.provenance.touch(self.clock.now(), user.id);
+
+        info!("Mot de passe changé pour l'utilisateur {}", user.username);
+        Ok(self.db.store_user(user)?)
+    }
+
+    /// Remplace le mot de passe d'un utilisateur par un mot de passe temporaire,
+    /// qu'il devra changer à sa prochaine connexion.
+    ///
+    /// L'ancienne clé privée de l'utilisateur ne peut pas être récupérée sans
+    /// son mot de passe: il reçoit une nouvelle paire de clés, et retrouvera
+    /// l'accès aux dossiers quand un autre détenteur de leur clé les consultera.
+    pub fn reset_password(
+        &mut self,
+        user_id: UserID,
+        temporary_password: &str,
+    ) -> Result<(), ServiceError> {
+        let ctx = self.enforce()?;
+        let target = self.db.get_user(user_id)?;
+        ctx.reset_password(target)?;
+
+        password_validation(
+            temporary_password,
+            target.username.as_ref(),
+            &self.password_policy,
+        )
+        .map_err(|feedback| ServiceError::WeakPassword { feedback })?;
+
+        let (keys, _) = UserKeys::generate(temporary_password)?;
+
+        self.record_change("reset-password", AuditTarget::user(user_id), "")?;
+        let mut user = target.clone();
+        user.password = self.hasher.hash(temporary_password);
+        user.keys = Some(keys);
+        user.must_change_password = true;
+        user.provenance.touch(self.clock.now(), self.editor()?);
+
+        info!(
+            "Mot de passe réinitialisé pour l'utilisateur {}",
+            user.username
+        );
+        self.db.store_user(user)?;
+        Ok(self.refresh_signing_keys(user_id)?)
+    }
+
+    /// Lève le verrouillage du compte d'un utilisateur
+    pub fn unlock_account(&mut self, user_id: UserID) -> Result<(), ServiceError> {
+        let ctx = self.enforce()?;
+        let target = self.db.get_user(user_id)?;
+        ctx.unlock_account(target)?;
+
+        let username = target.username.clone();
+        self.record_change("unlock-account", AuditTarget::user(user_id), "")?;
+        self.db.store_login_attempts(&username, None)?;
+        info!("Compte de l'utilisateur {username} déverrouillé");
+        Ok(())
     }
 
     /// Ferme la session
     pub fn logout(&mut self) {
-        self.user = None
+        self.user = None;
+        self.pending_second_factor = None;
+        self.session_key = None;
     }
 
     /// Cherche un ID utilisateur par nom d'utilisateur
@@ -117,25 +699,102 @@ impl Service {
     /// Change le role d'un utilisateur
     pub fn update_role(&mut self, user_id: UserID, new_role: Role) -> Result<(), ServiceError> {
         let ctx = self.enforce()?;
-        ctx.update_role(self.db.get_user(user_id)?,new_role)?;
-        
+        ctx.update_role(self.db.get_user(user_id)?, new_role)?;
 
-        // Récupère l'utilisateur cible et met à jour son rôle
-        let user = self.db.get_user_mut(user_id)?;
-        user.role = new_role;
+        self.record_change(
+            "update-role",
+            AuditTarget::user(user_id),
+            format!("role: {new_role}"),
+        )?;
 
-        Ok(())
+        // Récupère l'utilisateur cible et met à jour son rôle
+        let (now, editor) = (self.clock.now(), self.editor()?);
+        self.db.update_user(user_id, |user| {
+            user.role = new_role;
+            user.provenance.touch(now, editor);
+        })?;
+        Ok(self.refresh_signing_keys(user_id)?)
     }
 
     /// Récupère les données d'un utilisateur
     pub fn get_data(&self, user_id: UserID) -> Result<&UserData, ServiceError> {
-        self.enforce()?;
+        let ctx = self.enforce()?;
 
         let user_data = self.db.get_user(user_id)?;
-        
+        ctx.read_data(user_data)?;
+
         Ok(user_data)
     }
 
+    /// Les allergies, traitements et diagnostics du dossier d'un patient
+    pub fn clinical_record(&self, patient: UserID) -> Result<&ClinicalRecord, ServiceError> {
+        let ctx = self.enforce()?;
+        let patient = self.db.get_user(patient)?;
+        ctx.read_clinical_data(patient)?;
+
+        let folder = patient.medical_folder.as_ref();
+        Ok(&folder.ok_or(ServiceError::NotAPatient)?.clinical)
+    }
+
+    /// Ajoute une entrée au dossier clinique d'un patient. L'auteur d'un
+    /// diagnostic est l'utilisateur connecté.
+    pub fn add_clinical_entry(
+        &mut self,
+        patient: UserID,
+        mut entry: ClinicalEntry,
+    ) -> Result<(), ServiceError> {
+        let ctx = self.enforce()?;
+        let patient_data = self.db.get_user(patient)?;
+        if patient_data.medical_folder.is_none() {
+            return Err(ServiceError::NotAPatient);
+        }
+        if let (ClinicalEntry::Diagnosis(diagnosis), Some(author)) = (&mut entry, self.user) {
+            diagnosis.author = author;
+        }
+        ctx.add_clinical_data(patient_data, &entry)?;
+        let now = self.clock.now();
+        entry
+            .validate(now.date_naive())
+            .map_err(ServiceError::InvalidEntry)?;
+
+        self.record_change(
+            "add-clinical-data",
+            AuditTarget::user(patient),
+            entry.kind(),
+        )?;
+        let editor = self.editor()?;
+        self.db.update_user(patient, |user| {
+            user.provenance.touch(now, editor);
+            let Some(folder) = &mut user.medical_folder else {
+                return;
+            };
+            let clinical = &mut folder.clinical;
+            match entry {
+                ClinicalEntry::Allergy(allergy) => clinical.allergies.push(allergy),
+                ClinicalEntry::Medication(medication) => clinical.medications.push(medication),
+                ClinicalEntry::Diagnosis(diagnosis) => clinical.diagnoses.push(diagnosis),
+            }
+        })?;
+        Ok(())
+    }
+
+    /// Consulte le journal des accès au compte et au dossier d'un utilisateur,
+    /// éventuellement à partir d'une date donnée
+    pub fn audit_trail(
+        &self,
+        user_id: UserID,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<Vec<AuditRecord>, ServiceError> {
+        let ctx = self.enforce()?;
+        ctx.read_audit(self.db.get_user(user_id)?)?;
+
+        Ok(self.enforcer.audit_log().query(&AuditFilter {
+            user: Some(user_id),
+            since,
+            ..Default::default()
+        }))
+    }
+
     /// Change les données personnelles d'un utilisateur. Si le dossier médical
     /// n'existait pas, il est créé pour l'occasion.
     pub fn update_data(
@@ -146,13 +805,30 @@ impl Service {
         let ctx = self.enforce()?;
         let user = self.db.get_user(user_id)?;
         ctx.update_data(user)?;
-        
-        let folder = &mut self.db.get_user_mut(user_id)?.medical_folder;
 
-        if let Some(folder) = folder {
-            folder.personal_data = personal_data;
-        } else {
-            *folder = Some(MedicalFolder::new(personal_data));
+        match self.db.lookup_avs_number(&personal_data.avs_number) {
+            Some(other) if other.id != user_id => return Err(ServiceError::AvsNumberInUse),
+            _ => {}
+        }
+
+        self.record_change("update-data", AuditTarget::user(user_id), "")?;
+        let (now, editor) = (self.clock.now(), self.editor()?);
+        let created = self.db.update_user(user_id, |user| {
+            user.provenance.touch(now, editor);
+            match &mut user.medical_folder {
+                Some(folder) => {
+                    folder.personal_data = personal_data;
+                    false
+                }
+                folder => {
+                    *folder = Some(MedicalFolder::new(personal_data));
+                    true
+                }
+            }
+        })?;
+
+        if created {
+            self.refresh_folder_keys(user_id)?;
         }
         Ok(())
     }
@@ -163,59 +839,330 @@ impl Service {
     pub fn delete_data(&mut self, patient: UserID) -> Result<(), ServiceError> {
         let ctx = self.enforce()?;
         let data = self.db.get_user(patient)?;
-        
+
         ctx.delete_data(data)?;
-        
-        self.db.get_user_mut(patient)?.medical_folder = None;
-        self.db.remove_reports(patient);
+
+        self.record_change("delete-data", AuditTarget::user(patient), "")?;
+        let blobs: Vec<BlobHash> = self
+            .db
+            .list_patient_reports(patient)
+            .flat_map(|report| &report.attachments)
+            .map(|attachment| attachment.blob.clone())
+            .collect();
+        let mut data = data.clone();
+        data.medical_folder = None;
+        data.provenance.touch(self.clock.now(), self.editor()?);
+        self.db.replace_folder(data, Vec::new())?;
+
+        // Les fichiers ne sont supprimés qu'une fois les rapports effacés
+        for blob in &blobs {
+            self.blobs.remove(blob)?;
+        }
         Ok(())
     }
 
-    /// Ecrire un nouveau rapport médical
+    /// Ecrire un nouveau rapport médical, signé par son auteur. Ses champs
+    /// structurés, s'il en a, déterminent son type.
     pub fn add_report(
         &mut self,
         author: UserID,
         patient: UserID,
         title: String,
         content: String,
+        details: Option<ReportDetails>,
     ) -> Result<(), ServiceError> {
+        self.write_report(author, patient, title, content, details, None)?;
+        Ok(())
+    }
+
+    /// Complète un rapport définitif. L'addendum est écrit par l'utilisateur
+    /// connecté, et définitif dès sa création.
+    pub fn add_addendum(
+        &mut self,
+        parent: ReportID,
+        content: String,
+    ) -> Result<ReportID, ServiceError> {
+        let parent = self
+            .db
+            .get_report(parent)
+            .ok_or(ServiceError::NoSuchReport)?;
+
+        // Seul un utilisateur qui peut lire le rapport apprend s'il est définitif
+        self.enforce()?
+            .read_report(parent, self.db.get_user(parent.patient)?)?;
+        if !parent.is_final() {
+            return Err(ServiceError::ReportNotFinal);
+        }
+
+        let title = format!("Addendum au rapport: {}", parent.title);
+        let (patient, parent) = (parent.patient, Some(parent.id));
+        self.write_report(self.editor()?, patient, title, content, None, parent)
+    }
+
+    fn write_report(
+        &mut self,
+        author: UserID,
+        patient: UserID,
+        title: String,
+        text: String,
+        details: Option<ReportDetails>,
+        parent: Option<ReportID>,
+    ) -> Result<ReportID, ServiceError> {
         // Vérifier d'abord si le patient existe et a un dossier médical
         let patient_data = self.db.get_user(patient)?;
         if patient_data.medical_folder.is_none() {
             return Err(ServiceError::NotAPatient);
         }
 
-        // Créer le rapport
-        let report = MedicalReport {
-            id: ReportID::new(),
+        // Créer le rapport, chiffré pour le dossier: le texte n'est jamais
+        // enregistré en clair, même avant la première connexion du patient
+        let folder =
+            Self::folder_public_key(patient_data).ok_or(ServiceError::FolderNotEncrypted)?;
+        let id = ReportID::new();
+        let content = ReportContent::seal(&folder, id, patient, &text);
+        let now = self.clock.now();
+        let mut report = MedicalReport {
+            id,
             title,
             author,
             patient,
             content,
+            details,
+            attachments: Vec::new(),
+            revisions: Vec::new(),
+            provenance: Provenance::new(now, author),
+            parent,
+            signature: None,
+            seal: None,
         };
 
         let ctx = self.enforce()?;
         ctx.add_report(patient_data, &report)?;
-        
-        self.db.store_report(report);
+        if let Some(details) = &report.details {
+            details.validate(now.date_naive())?;
+        }
+
+        let key = self.signing_key(author).ok_or(ServiceError::NoSigningKey)?;
+        report.signature = Some(ReportSignature::sign(
+            &key,
+            &report_message(&report, &text, None),
+        ));
+        // Un addendum ne peut plus être modifié
+        if parent.is_some() {
+            report.seal = Some(ReportSeal {
+                sealed_at: now,
+                signature: ReportSignature::sign(&key, &report_message(&report, &text, Some(now))),
+            });
+        }
+
+        let details = match (parent, report.kind()) {
+            (Some(parent), _) => format!("title: {}, addendum to: {parent}", report.title),
+            (None, Some(kind)) => format!("title: {}, kind: {kind:?}", report.title),
+            (None, None) => format!("title: {}", report.title),
+        };
+        self.record_change("add-report", AuditTarget::report(patient, id), details)?;
+        self.db.store_report(report)?;
+
+        Ok(id)
+    }
 
+    /// Rend un rapport définitif: son auteur en signe la version actuelle,
+    /// qui ne pourra plus être modifiée
+    pub fn seal_report(&mut self, report_id: ReportID) -> Result<(), ServiceError> {
+        let report = self
+            .db
+            .get_report(report_id)
+            .ok_or(ServiceError::NoSuchReport)?;
+
+        self.enforce()?.seal_report(report)?;
+        if report.is_final() {
+            return Err(ServiceError::ReportSealed);
+        }
+        let key = self
+            .signing_key(report.author)
+            .ok_or(ServiceError::NoSigningKey)?;
+        let text = report
+            .content
+            .open(
+                report.id,
+                report.patient,
+                self.folder_secret(report.patient).as_ref(),
+            )
+            .map_err(|_| ServiceError::NoKeyGrant)?;
+
+        let now = self.clock.now();
+        let seal = ReportSeal {
+            sealed_at: now,
+            signature: ReportSignature::sign(&key, &report_message(report, &text, Some(now))),
+        };
+        let (patient, author) = (report.patient, report.author);
+        self.record_change("seal-report", AuditTarget::report(patient, report_id), "")?;
+        self.db.update_report(report_id, |report| {
+            report.seal = Some(seal);
+            report.provenance.touch(now, author);
+        })?;
         Ok(())
     }
 
-    pub fn list_reports(&self, user_id: UserID) -> impl Iterator<Item = &MedicalReport> + '_ {
+    /// Vérifie les signatures d'un rapport: celle de sa version originale
+    /// et, s'il est définitif, celle de sa version actuelle
+    pub fn verify_report(
+        &self,
+        report: &MedicalReport,
+    ) -> Result<ReportVerification, ServiceError> {
+        let ctx = self.enforce()?;
+        ctx.read_report(report, self.db.get_user(report.patient)?)?;
+
+        let folder_secret = self.folder_secret(report.patient);
+        let open = |content: &ReportContent| {
+            content
+                .open(report.id, report.patient, folder_secret.as_ref())
+                .map_err(|_| ServiceError::NoKeyGrant)
+        };
+        let author = self.db.get_user(report.author).ok();
+        let author_keys = author.and_then(|author| author.signing_keys.as_ref());
+
+        let original = report
+            .revisions
+            .first()
+            .map_or(&report.content, |revision| &revision.previous);
+        let original = match &report.signature {
+            Some(signature) => {
+                signature.check(&report_message(report, &open(original)?, None), author_keys)
+            }
+            None => SignatureStatus::Unsigned,
+        };
+        let seal = match &report.seal {
+            Some(seal) => {
+                let message = report_message(report, &open(&report.content)?, Some(seal.sealed_at));
+                Some((seal.sealed_at, seal.signature.check(&message, author_keys)))
+            }
+            None => None,
+        };
+
+        Ok(ReportVerification {
+            original,
+            revisions: report.revisions.len(),
+            seal,
+        })
+    }
+
+    /// Joint un fichier à un rapport qui n'est pas encore définitif. Son type
+    /// est reconnu à son contenu.
+    pub fn add_attachment(
+        &mut self,
+        report_id: ReportID,
+        name: TextLine,
+        bytes: &[u8],
+    ) -> Result<(), ServiceError> {
+        let report = self
+            .db
+            .get_report(report_id)
+            .ok_or(ServiceError::NoSuchReport)?;
+
+        self.enforce()?.update_report(report)?;
+        if report.is_final() {
+            return Err(ServiceError::ReportSealed);
+        }
+        if bytes.len() > MAX_ATTACHMENT_SIZE {
+            return Err(ServiceError::AttachmentTooLarge);
+        }
+        let mime_type = MimeType::detect(bytes).ok_or(ServiceError::UnsupportedAttachment)?;
+        let patient = report.patient;
+        let folder = Self::folder_public_key(self.db.get_user(patient)?)
+            .ok_or(ServiceError::FolderNotEncrypted)?;
+
+        let (blob, sealed_key) = Attachment::seal(&folder, report_id, patient, bytes);
+        let attachment = Attachment {
+            name,
+            mime_type,
+            size: bytes.len(),
+            blob: BlobHash::of(&blob),
+            sealed_key,
+        };
+        self.record_change(
+            "add-attachment",
+            AuditTarget::report(patient, report_id),
+            format!(
+                "name: {}, type: {mime_type}, size: {}",
+                attachment.name, attachment.size
+            ),
+        )?;
+        self.blobs.put(&blob)?;
+        let (now, editor) = (self.clock.now(), self.editor()?);
+        self.db.update_report(report_id, |report| {
+            report.attachments.push(attachment);
+            report.provenance.touch(now, editor);
+        })?;
+        Ok(())
+    }
+
+    /// Le contenu d'une pièce jointe, lisible par qui peut lire le rapport
+    pub fn attachment_content(
+        &self,
+        report: &MedicalReport,
+        blob: &BlobHash,
+    ) -> Result<Vec<u8>, ServiceError> {
+        let ctx = self.enforce()?;
+        ctx.read_report(report, self.db.get_user(report.patient)?)?;
+
+        let attachment = report
+            .attachments
+            .iter()
+            .find(|attachment| attachment.blob == *blob)
+            .ok_or(ServiceError::NoSuchAttachment)?;
+        let folder_secret = self
+            .folder_secret(report.patient)
+            .ok_or(ServiceError::NoKeyGrant)?;
+        let sealed = self.blobs.get(blob)?;
+        attachment
+            .open(report.id, report.patient, &folder_secret, &sealed)
+            .map_err(|_| ServiceError::NoKeyGrant)
+    }
+
+    /// Les rapports d'un patient que l'utilisateur connecté peut lire,
+    /// éventuellement d'un seul type. Les addenda suivent le type du rapport
+    /// qu'ils complètent.
+    pub fn list_reports(
+        &self,
+        user_id: UserID,
+        kind: Option<ReportKind>,
+    ) -> impl Iterator<Item = &MedicalReport> + '_ {
         self.enforce().ok().into_iter().flat_map(move |ctx| {
-            self.db
-                .list_reports()
-                .filter(move |report| report.patient == user_id)
-                .filter(move |report|{
-                    let Ok(patient) = self.db.get_user(report.patient) else {
-                        return false
-                    };
-                    ctx.read_report(report,patient).is_ok()
-                })
+            self.db.list_patient_reports(user_id).filter(move |report| {
+                if kind.is_some_and(|kind| self.report_kind(report) != Some(kind)) {
+                    return false;
+                }
+                let Ok(patient) = self.db.get_user(report.patient) else {
+                    return false;
+                };
+                ctx.read_report(report, patient).is_ok()
+            })
         })
     }
 
+    /// Le type d'un rapport; un addendum, sans champs structurés, a celui du
+    /// rapport d'origine
+    fn report_kind<'a>(&'a self, mut report: &'a MedicalReport) -> Option<ReportKind> {
+        while let Some(parent) = report.parent.and_then(|parent| self.db.get_report(parent)) {
+            report = parent;
+        }
+        report.kind()
+    }
+
+    /// Le texte d'un rapport, déchiffré avec la copie de la clé du dossier
+    /// reçue par l'utilisateur connecté
+    pub fn report_content(&self, report: &MedicalReport) -> Result<String, ServiceError> {
+        let ctx = self.enforce()?;
+        ctx.read_report(report, self.db.get_user(report.patient)?)?;
+
+        let folder_secret = self.folder_secret(report.patient);
+        report
+            .content
+            .open(report.id, report.patient, folder_secret.as_ref())
+            .map_err(|_| ServiceError::NoKeyGrant)
+    }
+
     pub fn list_patients(&self) -> impl Iterator<Item = &UserData> + '_ {
         self.user
             .iter()
@@ -229,49 +1176,1480 @@ impl Service {
         doctor_id: UserID,
     ) -> Result<(), ServiceError> {
         let ctx = self.enforce()?;
-        
+
         ctx.add_doctor(self.db.get_user(patient_id)?, self.db.get_user(doctor_id)?)?;
-        
-        let patient = self.db.get_user_mut(patient_id)?;
-        patient
-            .medical_folder
-            .as_mut()
-            .map(|f| f.doctors.insert(doctor_id));
-        Ok(())
+
+        self.record_change("add-doctor", AuditTarget::doctor(patient_id, doctor_id), "")?;
+        let (now, editor) = (self.clock.now(), self.editor()?);
+        self.db.update_user(patient_id, |patient| {
+            patient.provenance.touch(now, editor);
+            patient
+                .medical_folder
+                .as_mut()
+                .map(|f| f.doctors.insert(doctor_id))
+        })?;
+
+        // Donne au médecin une copie de la clé du dossier
+        self.refresh_folder_keys(patient_id)
     }
 
+    /// Retire un médecin traitant et révoque sa copie de la clé du dossier.
+    ///
+    /// Le médecin a pu conserver la clé: elle est renouvelée et les rapports
+    /// chiffrés à nouveau, dès maintenant si l'utilisateur connecté la détient,
+    /// sinon à la prochaine consultation du dossier par un de ses détenteurs.
     pub fn remove_doctor(
         &mut self,
         patient_id: UserID,
         doctor_id: UserID,
     ) -> Result<(), ServiceError> {
         let ctx = self.enforce()?;
-        
+
         ctx.remove_doctor(self.db.get_user(patient_id)?, self.db.get_user(doctor_id)?)?;
-        
-        let patient = self.db.get_user_mut(patient_id)?;
-        patient
-            .medical_folder
-            .as_mut()
-            .map(|f| f.doctors.remove(&doctor_id));
-        Ok(())
+
+        self.record_change(
+            "remove-doctor",
+            AuditTarget::doctor(patient_id, doctor_id),
+            "",
+        )?;
+        let (now, editor) = (self.clock.now(), self.editor()?);
+        self.db.update_user(patient_id, |patient| {
+            patient.provenance.touch(now, editor);
+            if let Some(folder) = &mut patient.medical_folder {
+                folder.doctors.remove(&doctor_id);
+                if let Some(keys) = &mut folder.keys {
+                    keys.grants.remove(&doctor_id);
+                    keys.rotation_pending = true;
+                }
+            }
+        })?;
+        self.refresh_folder_keys(patient_id)
     }
 
+    /// Modifie le texte d'un rapport. Le texte remplacé est conservé dans
+    /// une nouvelle révision, avec l'auteur, la date et le motif du changement.
     pub fn update_report(
         &mut self,
         report_id: ReportID,
         content: String,
+        reason: TextLine,
     ) -> Result<(), ServiceError> {
-        
         let report = self
             .db
             .get_report(report_id)
             .ok_or(ServiceError::NoSuchReport)?;
 
         self.enforce()?.update_report(report)?;
-        *self.db.get_report_data_mut(report_id).unwrap() = content;
+        if report.is_final() {
+            return Err(ServiceError::ReportSealed);
+        }
+        let (now, editor) = (self.clock.now(), self.editor()?);
+        let patient = report.patient;
+        let number = report.revisions.len() + 1;
+        let folder = Self::folder_public_key(self.db.get_user(patient)?)
+            .ok_or(ServiceError::FolderNotEncrypted)?;
+        let content = ReportContent::seal(&folder, report_id, patient, &content);
+
+        self.record_change(
+            "update-report",
+            AuditTarget::report(patient, report_id),
+            format!("revision: {number}"),
+        )?;
+        self.db.update_report(report_id, |report| {
+            let previous = std::mem::replace(&mut report.content, content);
+            report.revisions.push(Revision {
+                previous,
+                editor,
+                timestamp: now,
+                reason,
+            });
+            report.provenance.touch(now, editor);
+        })?;
+        Ok(())
+    }
+
+    /// Toutes les versions d'un rapport, de l'originale à l'actuelle
+    pub fn report_history<'a>(
+        &'a self,
+        report: &'a MedicalReport,
+    ) -> Result<Vec<ReportVersion<'a>>, ServiceError> {
+        let ctx = self.enforce()?;
+        ctx.read_report_history(report, self.db.get_user(report.patient)?)?;
+
+        let folder_secret = self.folder_secret(report.patient);
+        let open = |content: &ReportContent| {
+            content
+                .open(report.id, report.patient, folder_secret.as_ref())
+                .map_err(|_| ServiceError::NoKeyGrant)
+        };
+
+        let mut revision = None;
+        let mut versions = Vec::with_capacity(report.revisions.len() + 1);
+        for next in &report.revisions {
+            versions.push(ReportVersion {
+                text: open(&next.previous)?,
+                revision,
+            });
+            revision = Some(next);
+        }
+        versions.push(ReportVersion {
+            text: open(&report.content)?,
+            revision,
+        });
+        Ok(versions)
+    }
+
+    /// La clé de signature de l'utilisateur connecté, s'il est l'auteur donné
+    fn signing_key(&self, author: UserID) -> Option<SigningKey> {
+        let session_key = self.session_key.as_ref()?;
+        let user = self.get_subject().filter(|user| user.id == author)?;
+        user.signing_keys.as_ref()?.unlock(author, session_key)
+    }
+
+    /// Donne aux médecins et aux admins une paire de clés de signature, et la
+    /// renouvelle si leur clé X25519 a changé depuis
+    fn refresh_signing_keys(&mut self, user_id: UserID) -> Result<(), DBError> {
+        let user = self.db.get_user(user_id)?;
+        if !matches!(user.role, Role::Doctor | Role::Admin) {
+            return Ok(());
+        }
+        let Some(public) = user
+            .keys
+            .as_ref()
+            .and_then(|keys| keys.public_key.public_key())
+        else {
+            return Ok(());
+        };
+        if user
+            .signing_keys
+            .as_ref()
+            .is_some_and(|keys| keys.is_sealed_for(&public))
+        {
+            return Ok(());
+        }
+
+        let username = user.username.clone();
+        self.db
+            .update_user(user_id, |user| match &mut user.signing_keys {
+                Some(keys) => keys.renew(user_id, &public),
+                keys => *keys = Some(SigningKeys::generate(user_id, &public)),
+            })?;
+        info!("Clés de signature créées pour l'utilisateur {username}");
+        Ok(())
+    }
+
+    /// La clé publique du dossier d'un patient, s'il a déjà ses clés
+    fn folder_public_key(patient: &UserData) -> Option<PublicKey> {
+        patient.medical_folder.as_ref()?.keys.as_ref()?.public_key()
+    }
+
+    /// La clé privée du dossier d'un patient, si l'utilisateur connecté
+    /// en a reçu une copie
+    fn folder_secret(&self, patient: UserID) -> Option<StaticSecret> {
+        let user = self.user?;
+        let session_key = self.session_key.as_ref()?;
+        let folder = self.db.get_user(patient).ok()?.medical_folder.as_ref()?;
+        folder.keys.as_ref()?.unwrap(patient, user, session_key)
+    }
+
+    /// Met à jour les clés des dossiers auxquels l'utilisateur connecté a accès:
+    /// le sien et ceux de ses patients
+    fn refresh_accessible_folders(&mut self) {
+        let Some(user) = self.user else {
+            return;
+        };
+        let patients: Vec<UserID> = std::iter::once(user)
+            .chain(self.db.get_patients(user))
+            .collect();
+        for patient in patients {
+            if let Err(e) = self.refresh_folder_keys(patient) {
+                warn!("Clés du dossier de {patient} non mises à jour: {e}");
+            }
+        }
+    }
+
+    /// Met à jour les clés du dossier d'un patient:
+    ///
+    /// - un dossier antérieur au chiffrement reçoit ses clés dès que le patient
+    ///   a les siennes, et ses rapports sont chiffrés;
+    /// - si l'utilisateur connecté détient la clé du dossier, il en donne une
+    ///   copie au patient et aux médecins traitants qui n'en ont pas d'utilisable,
+    ///   et la renouvelle si un médecin a été retiré entre-temps.
+    fn refresh_folder_keys(&mut self, patient_id: UserID) -> Result<(), ServiceError> {
+        let patient = self.db.get_user(patient_id)?;
+        let Some(folder) = &patient.medical_folder else {
+            return Ok(());
+        };
+
+        let recipients: Vec<(UserID, PublicKey)> = std::iter::once(patient_id)
+            .chain(folder.doctors.iter().copied())
+            .filter_map(|id| {
+                let keys = self.db.get_user(id).ok()?.keys.as_ref()?;
+                Some((id, keys.public_key.public_key()?))
+            })
+            .collect();
+
+        let folder_secret = match &folder.keys {
+            None if patient.keys.is_none() => return Ok(()),
+            None => None,
+            Some(keys) => match self.folder_secret(patient_id) {
+                Some(secret) if keys.rotation_pending => Some(secret),
+                Some(secret) => {
+                    let missing: Vec<_> = recipients
+                        .into_iter()
+                        .filter(|(id, public)| !keys.has_grant(*id, public))
+                        .collect();
+                    if !missing.is_empty() {
+                        self.db.update_user(patient_id, |patient| {
+                            let keys = patient
+                                .medical_folder
+                                .as_mut()
+                                .and_then(|folder| folder.keys.as_mut());
+                            if let Some(keys) = keys {
+                                for (id, public) in &missing {
+                                    keys.grant(patient_id, *id, public, &secret);
+                                }
+                            }
+                        })?;
+                    }
+                    return Ok(());
+                }
+                None => return Ok(()),
+            },
+        };
+
+        self.rotate_folder_keys(patient_id, folder_secret.as_ref(), &recipients)
+    }
+
+    /// Remplace la paire de clés du dossier d'un patient, chiffre à nouveau
+    /// ses rapports et en donne une copie à chaque destinataire
+    fn rotate_folder_keys(
+        &mut self,
+        patient: UserID,
+        old_secret: Option<&StaticSecret>,
+        recipients: &[(UserID, PublicKey)],
+    ) -> Result<(), ServiceError> {
+        // Tout déchiffrer avant de modifier quoi que ce soit, révisions comprises
+        let reports = self
+            .db
+            .list_patient_reports(patient)
+            .map(|report| {
+                let open = |content: &ReportContent| content.open(report.id, patient, old_secret);
+                let text = open(&report.content)?;
+                let previous = report
+                    .revisions
+                    .iter()
+                    .map(|revision| open(&revision.previous))
+                    .collect::<Result<Vec<_>, _>>()?;
+                // Les fichiers joints ne changent pas: seules leurs clés sont
+                // chiffrées à nouveau
+                let attachment_keys = report
+                    .attachments
+                    .iter()
+                    .map(|attachment| {
+                        let secret = old_secret.ok_or(CryptoError::Decryption)?;
+                        attachment.open_key(report.id, patient, secret)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok((report.clone(), text, previous, attachment_keys))
+            })
+            .collect::<Result<Vec<_>, CryptoError>>()?;
+
+        let (mut keys, secret) = FolderKeys::generate();
+        for (id, public) in recipients {
+            keys.grant(patient, *id, public, &secret);
+        }
+        let public = keys.public_key().expect("generated keys are valid");
+
+        self.record_change(
+            "rotate-folder-key",
+            AuditTarget::user(patient),
+            format!("reports: {}", reports.len()),
+        )?;
+        let reports = reports
+            .into_iter()
+            .map(|(mut report, text, previous, attachment_keys)| {
+                report.content = ReportContent::seal(&public, report.id, patient, &text);
+                for (revision, text) in report.revisions.iter_mut().zip(previous) {
+                    revision.previous = ReportContent::seal(&public, report.id, patient, &text);
+                }
+                for (attachment, key) in report.attachments.iter_mut().zip(attachment_keys) {
+                    attachment.sealed_key = Attachment::seal_key(&key, &public, report.id, patient);
+                }
+                report
+            })
+            .collect();
+        let mut data = self.db.get_user(patient)?.clone();
+        if let Some(folder) = &mut data.medical_folder {
+            folder.keys = Some(keys);
+        }
+        self.db.replace_folder(data, reports)?;
+        info!("Nouvelle clé pour le dossier de {patient}");
         Ok(())
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::Decision;
+    use crate::db::{sqlite::SqliteDatabase, Database};
+    use crate::models::{
+        Allergy, BloodType, Diagnosis, LabValue, Medication, Rhesus, Severity, Sex,
+    };
+    use crate::utils::clock::ManualClock;
+    use crate::utils::input_validation::AVSNumber;
+    use crate::utils::input_validation::{Icd10Code, Measurement, TextLine};
+    use crate::utils::password_utils::{HashConfig, Pepper};
+    use chrono::{NaiveDate, TimeDelta};
+
+    const STRONG_PASSWORD: &str = "Str0ngP@ssw0rd!";
+
+    /// Chaque test est exécuté avec les deux implémentations du stockage
+    macro_rules! storage_tests {
+        ($($test:ident),* $(,)?) => {
+            mod json {
+                use super::*;
+
+                fn service() -> Service {
+                    Service::new(Box::new(Database::default()), Enforcer::load().unwrap())
+                }
+
+                $(#[test]
+                fn $test() {
+                    super::$test(service)
+                })*
+            }
+
+            mod sqlite {
+                use super::*;
+
+                fn service() -> Service {
+                    let db = SqliteDatabase::open_in_memory(None).unwrap();
+                    Service::new(Box::new(db), Enforcer::load().unwrap())
+                }
+
+                $(#[test]
+                fn $test() {
+                    super::$test(service)
+                })*
+            }
+        };
+    }
+
+    storage_tests!(
+        test_register_and_login,
+        test_register_rejects_weak_password,
+        test_register_uses_password_policy,
+        test_change_password,
+        test_reset_password,
+        test_login_rehashes_weak_hash,
+        test_login_lockout,
+        test_second_factor_login,
+        test_second_factor_mandatory_for_staff,
+        test_audit_trail,
+        test_register_rejects_duplicate_username,
+        test_register_with_role_requires_admin,
+        test_reports_encrypted_per_folder,
+        test_keys_survive_password_change,
+        test_legacy_folder_encrypted_at_login,
+        test_usernames_compared_canonically,
+        test_register_uses_username_policy,
+        test_avs_number_unique,
+        test_personal_data_fields,
+        test_clinical_entries,
+        test_report_revisions,
+        test_provenance,
+        test_report_signing,
+        test_report_kinds,
+        test_attachments,
+    );
+
+    fn username(name: &str) -> Username {
+        Username::new(name.to_owned())
+    }
+
+    fn test_register_and_login(service: fn() -> Service) {
+        let mut service = service();
+        let id = service
+            .register(username("alice"), STRONG_PASSWORD, None)
+            .unwrap();
+
+        assert!(matches!(
+            service.db.get_user(id).unwrap().role,
+            Role::Patient
+        ));
+        assert_eq!(
+            service.login(&username("alice"), STRONG_PASSWORD).unwrap(),
+            id
+        );
+    }
+
+    fn test_register_rejects_weak_password(service: fn() -> Service) {
+        let mut service = service();
+        let result = service.register(username("alice"), "123", None);
+
+        assert!(matches!(result, Err(ServiceError::WeakPassword { .. })));
+        assert!(service.lookup_user(&username("alice")).is_none());
+    }
+
+    fn test_register_uses_password_policy(service: fn() -> Service) {
+        let policy = PasswordPolicy::with_min_score(0).unwrap();
+        let mut service = service().with_password_policy(policy);
+        assert!(service.register(username("alice"), "123", None).is_ok());
+    }
+
+    fn test_change_password(service: fn() -> Service) {
+        let mut service = service();
+        service
+            .register(username("alice"), STRONG_PASSWORD, None)
+            .unwrap();
+        service.login(&username("alice"), STRONG_PASSWORD).unwrap();
+
+        let result = service.change_password("wrong", "An0ther-G00d-One!");
+        assert!(matches!(result, Err(ServiceError::WrongPassword)));
+        let result = service.change_password(STRONG_PASSWORD, "alice");
+        assert!(matches!(result, Err(ServiceError::WeakPassword { .. })));
+
+        service
+            .change_password(STRONG_PASSWORD, "An0ther-G00d-One!")
+            .unwrap();
+        service.logout();
+        assert!(service.login(&username("alice"), STRONG_PASSWORD).is_err());
+        assert!(service
+            .login(&username("alice"), "An0ther-G00d-One!")
+            .is_ok());
+    }
+
+    fn test_reset_password(service: fn() -> Service) {
+        let mut service = service();
+        let alice = service
+            .register(username("alice"), STRONG_PASSWORD, None)
+            .unwrap();
+        let admin = service
+            .register(username("root_admin"), STRONG_PASSWORD, None)
+            .unwrap();
+        service
+            .db
+            .update_user(admin, |user| user.role = Role::Admin)
+            .unwrap();
+
+        // Un patient ne peut pas réinitialiser le mot de passe d'un autre
+        service.login(&username("alice"), STRONG_PASSWORD).unwrap();
+        let result = service.reset_password(admin, "Temp0rary-Passw0rd");
+        assert!(matches!(result, Err(ServiceError::AccessDenied(_))));
+
+        service
+            .login(&username("root_admin"), STRONG_PASSWORD)
+            .unwrap();
+        service.reset_password(alice, "Temp0rary-Passw0rd").unwrap();
+
+        service
+            .login(&username("alice"), "Temp0rary-Passw0rd")
+            .unwrap();
+        assert!(service.must_change_password());
+        // Rien d'autre n'est permis avant le changement de mot de passe
+        assert!(matches!(
+            service.get_data(alice),
+            Err(ServiceError::AccessDenied(_))
+        ));
+        assert!(matches!(
+            service.change_password("Temp0rary-Passw0rd", "Temp0rary-Passw0rd"),
+            Err(ServiceError::SamePassword)
+        ));
+        assert!(service.must_change_password());
+        service
+            .change_password("Temp0rary-Passw0rd", "An0ther-G00d-One!")
+            .unwrap();
+        assert!(!service.must_change_password());
+        service.get_data(alice).unwrap();
+    }
+
+    fn test_login_rehashes_weak_hash(service: fn() -> Service) {
+        let weak = HashConfig {
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+            pepper: None,
+        };
+        let mut service = service().with_hasher(Hasher::new(weak.clone()).unwrap());
+        let alice = service
+            .register(username("alice"), STRONG_PASSWORD, None)
+            .unwrap();
+
+        let strong = HashConfig {
+            iterations: 2,
+            pepper: Pepper::new(b"1", b"pepper".to_vec()),
+            ..weak
+        };
+        service.hasher = Hasher::new(strong).unwrap();
+        assert!(service
+            .hasher
+            .needs_rehash(&service.db.get_user(alice).unwrap().password));
+
+        service.login(&username("alice"), STRONG_PASSWORD).unwrap();
+        assert!(!service
+            .hasher
+            .needs_rehash(&service.db.get_user(alice).unwrap().password));
+
+        service.logout();
+        assert!(service.login(&username("alice"), STRONG_PASSWORD).is_ok());
+    }
+
+    fn test_login_lockout(service: fn() -> Service) {
+        let policy = LockoutPolicy {
+            free_attempts: 2,
+            max_attempts: 2,
+            ..Default::default()
+        };
+        let mut service = service().with_lockout_policy(policy);
+        let alice = service
+            .register(username("alice"), STRONG_PASSWORD, None)
+            .unwrap();
+
+        for _ in 0..2 {
+            let result = service.login(&username("alice"), "wrong");
+            assert!(matches!(result, Err(LoginError::InvalidCredentials)));
+        }
+        // Même le bon mot de passe est refusé pendant le verrouillage
+        let result = service.login(&username("alice"), STRONG_PASSWORD);
+        assert!(matches!(result, Err(LoginError::Locked { .. })));
+
+        // Un nom inconnu se comporte exactement de la même manière
+        for _ in 0..2 {
+            let result = service.login(&username("nobody"), "wrong");
+            assert!(matches!(result, Err(LoginError::InvalidCredentials)));
+        }
+        let result = service.login(&username("nobody"), STRONG_PASSWORD);
+        assert!(matches!(result, Err(LoginError::Locked { .. })));
+
+        let admin = service
+            .register(username("root_admin"), STRONG_PASSWORD, None)
+            .unwrap();
+        service
+            .db
+            .update_user(admin, |user| user.role = Role::Admin)
+            .unwrap();
+        service
+            .login(&username("root_admin"), STRONG_PASSWORD)
+            .unwrap();
+        service.unlock_account(alice).unwrap();
+
+        assert_eq!(
+            service.login(&username("alice"), STRONG_PASSWORD).unwrap(),
+            alice
+        );
+    }
+
+    fn test_second_factor_login(service: fn() -> Service) {
+        let mut service = service();
+        let alice = service
+            .register(username("alice"), STRONG_PASSWORD, None)
+            .unwrap();
+        service.login(&username("alice"), STRONG_PASSWORD).unwrap();
+
+        let uri = service.begin_totp_enrolment().unwrap();
+        assert!(uri.starts_with("otpauth://totp/"));
+        assert!(matches!(
+            service.confirm_totp_enrolment("000000x"),
+            Err(ServiceError::InvalidSecondFactor)
+        ));
+
+        let now = Utc::now().timestamp() as u64;
+        let secret = |service: &Service| {
+            let second_factor = service.db.get_user(alice).unwrap().second_factor.as_ref();
+            second_factor.unwrap().secret.clone()
+        };
+        let code = secret(&service).code_at(now - 30);
+        let recovery_codes = service.confirm_totp_enrolment(&code).unwrap();
+        service.logout();
+
+        let result = service.login(&username("alice"), STRONG_PASSWORD);
+        assert!(matches!(result, Err(LoginError::SecondFactorRequired)));
+        assert!(service.get_data(alice).is_err());
+
+        // Un code déjà utilisé ne peut pas être rejoué
+        let result = service.verify_second_factor(&code);
+        assert!(matches!(result, Err(LoginError::InvalidCredentials)));
+
+        let code = secret(&service).code_at(now);
+        assert_eq!(service.verify_second_factor(&code).unwrap(), alice);
+        assert!(service.get_data(alice).is_ok());
+        service.logout();
+
+        // Un code de récupération ne fonctionne qu'une fois
+        for expected in [true, false] {
+            let _ = service.login(&username("alice"), STRONG_PASSWORD);
+            let result = service.verify_second_factor(&recovery_codes[0]);
+            assert_eq!(result.is_ok(), expected);
+            service.logout();
+        }
+    }
+
+    fn test_second_factor_mandatory_for_staff(service: fn() -> Service) {
+        let policy = SecondFactorPolicy {
+            mandatory_for_staff: true,
+        };
+        let mut service = service().with_second_factor_policy(policy);
+        let doctor = service
+            .register(username("doctor"), STRONG_PASSWORD, None)
+            .unwrap();
+        service
+            .db
+            .update_user(doctor, |user| user.role = Role::Doctor)
+            .unwrap();
+
+        service.login(&username("doctor"), STRONG_PASSWORD).unwrap();
+        assert!(service.must_enroll_second_factor());
+        assert!(service.get_data(doctor).is_err());
+
+        service.begin_totp_enrolment().unwrap();
+        let second_factor = service.db.get_user(doctor).unwrap().second_factor.as_ref();
+        let code = second_factor
+            .unwrap()
+            .secret
+            .code_at(Utc::now().timestamp() as u64);
+        service.confirm_totp_enrolment(&code).unwrap();
+
+        assert!(!service.must_enroll_second_factor());
+        assert!(service.get_data(doctor).is_ok());
+    }
+
+    fn test_audit_trail(service: fn() -> Service) {
+        let start = DateTime::parse_from_rfc3339("2024-05-02T08:00:00Z")
+            .unwrap()
+            .to_utc();
+        let clock = ManualClock::new(start);
+        let mut service = service().with_clock(clock.clone());
+        let alice = service
+            .register(username("alice"), STRONG_PASSWORD, None)
+            .unwrap();
+        let bob = service
+            .register(username("bob"), STRONG_PASSWORD, None)
+            .unwrap();
+
+        service.login(&username("bob"), STRONG_PASSWORD).unwrap();
+        assert!(service.get_data(alice).is_err());
+        assert!(service.audit_trail(alice, None).is_err());
+
+        // Les entrées sont datées par l'horloge du service
+        let later = start + TimeDelta::hours(1);
+        clock.set(later);
+        service.login(&username("alice"), STRONG_PASSWORD).unwrap();
+        let trail = service.audit_trail(alice, None).unwrap();
+        let register = &trail[0];
+        assert_eq!(register.action, "register");
+        assert_eq!(register.subject, alice);
+        assert_eq!(register.decision, Decision::Applied);
+        assert_eq!(register.timestamp, start);
+
+        let read = trail
+            .iter()
+            .find(|record| record.action == "read-data")
+            .unwrap();
+        assert_eq!(read.subject, bob);
+        assert_eq!(read.decision, Decision::Denied);
+        assert_eq!(read.timestamp, start);
 
+        let trail = service.audit_trail(alice, Some(later)).unwrap();
+        assert!(!trail.is_empty());
+        assert!(trail.iter().all(|record| record.action == "read-audit"));
+        assert!(trail.iter().all(|record| record.timestamp == later));
+    }
+
+    fn test_register_rejects_duplicate_username(service: fn() -> Service) {
+        let mut service = service();
+        service
+            .register(username("alice"), STRONG_PASSWORD, None)
+            .unwrap();
+
+        let result = service.register(username("alice"), STRONG_PASSWORD, None);
+        assert!(matches!(result, Err(ServiceError::UserAlreadyExists)));
+    }
+
+    fn test_usernames_compared_canonically(service: fn() -> Service) {
+        let mut service = service();
+        let alice = service
+            .register(username("Alice"), STRONG_PASSWORD, None)
+            .unwrap();
+
+        for spoof in ["alice", "ALICE", "Ａｌｉｃｅ"] {
+            let result = service.register(username(spoof), STRONG_PASSWORD, None);
+            assert!(matches!(result, Err(ServiceError::UserAlreadyExists)));
+        }
+        assert_eq!(service.lookup_user(&username("aLiCe")), Some(alice));
+        assert_eq!(
+            service.login(&username("alice"), STRONG_PASSWORD).unwrap(),
+            alice
+        );
+        // Le nom reste affiché tel qu'il a été choisi
+        assert_eq!(
+            service.db.get_user(alice).unwrap().username.as_ref(),
+            "Alice"
+        );
+    }
+
+    fn test_register_uses_username_policy(service: fn() -> Service) {
+        let mut service =
+            service().with_username_policy(UsernamePolicy::with_length(3, 8).unwrap());
+
+        for (name, expected) in [
+            ("ab", UsernameError::Length { min: 3, max: 8 }),
+            (
+                "alice_in_wonderland",
+                UsernameError::Length { min: 3, max: 8 },
+            ),
+            ("Root", UsernameError::Reserved("Root".to_owned())),
+        ] {
+            let result = service.register(username(name), STRONG_PASSWORD, None);
+            assert!(
+                matches!(&result, Err(ServiceError::InvalidUsername(e)) if *e == expected),
+                "{name}: {result:?}"
+            );
+        }
+        assert!(service
+            .register(username("medecin1"), STRONG_PASSWORD, None)
+            .is_ok());
+    }
+
+    fn test_register_with_role_requires_admin(service: fn() -> Service) {
+        let mut service = service();
+        let result = service.register(username("mallory"), STRONG_PASSWORD, Some(Role::Doctor));
+        assert!(matches!(result, Err(ServiceError::AccessDenied(_))));
+
+        let admin = service
+            .register(username("root_admin"), STRONG_PASSWORD, None)
+            .unwrap();
+        service
+            .db
+            .update_user(admin, |user| user.role = Role::Admin)
+            .unwrap();
+        service
+            .login(&username("root_admin"), STRONG_PASSWORD)
+            .unwrap();
+
+        let doctor = service
+            .register(username("doctor"), STRONG_PASSWORD, Some(Role::Doctor))
+            .unwrap();
+        assert!(matches!(
+            service.db.get_user(doctor).unwrap().role,
+            Role::Doctor
+        ));
+    }
+
+    fn personal_data() -> PersonalData {
+        PersonalData::new(
+            AVSNumber::try_from("756.1234.5678.97".to_owned()).unwrap(),
+            BloodType::O,
+        )
+    }
+
+    fn register_as(service: &mut Service, name: &str, role: Role) -> UserID {
+        let id = service
+            .register(username(name), STRONG_PASSWORD, None)
+            .unwrap();
+        service.db.update_user(id, |user| user.role = role).unwrap();
+        id
+    }
+
+    fn test_avs_number_unique(service: fn() -> Service) {
+        let mut service = service();
+        let alice = register_as(&mut service, "alice", Role::Patient);
+        let bob = register_as(&mut service, "bob", Role::Patient);
+        service.login(&username("alice"), STRONG_PASSWORD).unwrap();
+        service.update_data(alice, personal_data()).unwrap();
+        // Modifier son propre dossier reste possible
+        service.update_data(alice, personal_data()).unwrap();
+
+        service.login(&username("bob"), STRONG_PASSWORD).unwrap();
+        let same_number =
+            PersonalData::new(AVSNumber::parse("7561234567897").unwrap(), BloodType::A);
+        assert!(matches!(
+            service.update_data(bob, same_number),
+            Err(ServiceError::AvsNumberInUse)
+        ));
+        let other_number =
+            PersonalData::new(AVSNumber::parse("756.0905.7171.04").unwrap(), BloodType::A);
+        service.update_data(bob, other_number).unwrap();
+    }
+
+    fn test_personal_data_fields(service: fn() -> Service) {
+        let mut service = service();
+        let alice = register_as(&mut service, "alice", Role::Patient);
+        service.login(&username("alice"), STRONG_PASSWORD).unwrap();
+        service.update_data(alice, personal_data()).unwrap();
+
+        let mut data = personal_data();
+        data.rhesus = Some(Rhesus::Negative);
+        data.sex = Some(Sex::Female);
+        data.date_of_birth = Some("12.03.1985".to_owned().try_into().unwrap());
+        data.insurance_number = Some("80756 01234 56789 01234".to_owned().try_into().unwrap());
+        service.update_data(alice, data).unwrap();
+
+        let folder = service.get_data(alice).unwrap().medical_folder.as_ref();
+        let data = &folder.unwrap().personal_data;
+        assert_eq!(data.blood_group(), "O-");
+        assert_eq!(data.date_of_birth.unwrap().to_string(), "12.03.1985");
+        assert_eq!(
+            data.insurance_number.as_ref().unwrap().to_string(),
+            "80756012345678901234"
+        );
+        assert!(data.full_name.is_none());
+    }
+
+    fn read_report(
+        service: &mut Service,
+        name: &str,
+        report: ReportID,
+    ) -> Result<String, ServiceError> {
+        service.login(&username(name), STRONG_PASSWORD).unwrap();
+        let report = service.db.get_report(report).unwrap();
+        service.report_content(report)
+    }
+
+    fn test_clinical_entries(service: fn() -> Service) {
+        let now = DateTime::parse_from_rfc3339("2024-05-02T08:00:00Z")
+            .unwrap()
+            .to_utc();
+        let mut service = service().with_clock(ManualClock::new(now));
+        let alice = register_as(&mut service, "alice", Role::Patient);
+        let bob = register_as(&mut service, "bob", Role::Doctor);
+        let carol = register_as(&mut service, "carol", Role::Doctor);
+        service.login(&username("alice"), STRONG_PASSWORD).unwrap();
+        service.update_data(alice, personal_data()).unwrap();
+        service.add_doctor(alice, bob).unwrap();
+
+        let date = NaiveDate::from_ymd_opt(2024, 5, 2).unwrap();
+        let diagnosis = ClinicalEntry::Diagnosis(Diagnosis {
+            code: Icd10Code::try_from("j45".to_owned()).unwrap(),
+            date,
+            author: alice,
+        });
+        // Le patient lit ses données cliniques, mais ne les complète pas
+        assert!(matches!(
+            service.add_clinical_entry(alice, diagnosis.clone()),
+            Err(ServiceError::AccessDenied(_))
+        ));
+
+        service.login(&username("carol"), STRONG_PASSWORD).unwrap();
+        assert!(service.clinical_record(alice).is_err());
+        assert!(service
+            .add_clinical_entry(alice, diagnosis.clone())
+            .is_err());
+
+        service.login(&username("bob"), STRONG_PASSWORD).unwrap();
+        service.add_clinical_entry(alice, diagnosis).unwrap();
+        let allergy = Allergy {
+            substance: TextLine::try_from("Pénicilline".to_owned()).unwrap(),
+            severity: Severity::Severe,
+        };
+        service
+            .add_clinical_entry(alice, ClinicalEntry::Allergy(allergy))
+            .unwrap();
+
+        // Les dates sont validées comme celles d'une ordonnance
+        let line = |text: &str| TextLine::try_from(text.to_owned()).unwrap();
+        let medication = ClinicalEntry::Medication(Medication {
+            drug: line("Ventolin"),
+            dose: line("2 bouffées"),
+            start: date,
+            end: date.pred_opt(),
+        });
+        assert!(matches!(
+            service.add_clinical_entry(alice, medication),
+            Err(ServiceError::InvalidEntry(InvalidDetails::EndBeforeStart))
+        ));
+        let future = ClinicalEntry::Diagnosis(Diagnosis {
+            code: Icd10Code::try_from("J45".to_owned()).unwrap(),
+            date: date.succ_opt().unwrap(),
+            author: bob,
+        });
+        assert!(matches!(
+            service.add_clinical_entry(alice, future),
+            Err(ServiceError::InvalidEntry(InvalidDetails::FutureDate))
+        ));
+        assert!(matches!(
+            service.add_clinical_entry(
+                carol,
+                ClinicalEntry::Diagnosis(Diagnosis {
+                    code: Icd10Code::try_from("E11.9".to_owned()).unwrap(),
+                    date,
+                    author: bob,
+                })
+            ),
+            Err(ServiceError::NotAPatient)
+        ));
+
+        service.login(&username("alice"), STRONG_PASSWORD).unwrap();
+        let record = service.clinical_record(alice).unwrap();
+        assert_eq!(record.allergies[0].to_string(), "Pénicilline (sévère)");
+        assert!(record.medications.is_empty());
+        // L'auteur du diagnostic est le médecin connecté
+        assert_eq!(record.diagnoses[0].author, bob);
+        assert_eq!(record.diagnoses[0].code.to_string(), "J45");
+    }
+
+    fn test_reports_encrypted_per_folder(service: fn() -> Service) {
+        let mut service = service();
+        let alice = register_as(&mut service, "alice", Role::Patient);
+        let bob = register_as(&mut service, "bob", Role::Doctor);
+        register_as(&mut service, "eve", Role::Admin);
+
+        service.login(&username("alice"), STRONG_PASSWORD).unwrap();
+        service.update_data(alice, personal_data()).unwrap();
+
+        service.login(&username("bob"), STRONG_PASSWORD).unwrap();
+        service
+            .add_report(
+                bob,
+                alice,
+                "Consultation".to_owned(),
+                "Grippe".to_owned(),
+                None,
+            )
+            .unwrap();
+        let report = service.db.list_reports().next().unwrap().id;
+        assert!(service.db.get_report(report).unwrap().content.is_sealed());
+
+        // Bob a pu écrire le rapport, mais pas le relire sans copie de la clé
+        assert!(matches!(
+            read_report(&mut service, "bob", report),
+            Err(ServiceError::NoKeyGrant)
+        ));
+        assert_eq!(
+            read_report(&mut service, "alice", report).unwrap(),
+            "Grippe"
+        );
+
+        service.add_doctor(alice, bob).unwrap();
+        assert_eq!(read_report(&mut service, "bob", report).unwrap(), "Grippe");
+
+        // L'admin peut voir le rapport, mais pas le lire
+        assert!(matches!(
+            read_report(&mut service, "eve", report),
+            Err(ServiceError::NoKeyGrant)
+        ));
+
+        // L'admin, sans copie de la clé, retire le médecin: la clé sera
+        // renouvelée par le prochain de ses détenteurs à consulter le dossier
+        let folder_keys = |service: &Service| {
+            let folder = service.db.get_user(alice).unwrap().medical_folder.clone();
+            folder.unwrap().keys.unwrap()
+        };
+        let before = folder_keys(&service);
+        service.remove_doctor(alice, bob).unwrap();
+        let pending = folder_keys(&service);
+        assert!(pending.rotation_pending);
+        assert!(!pending.grants.contains_key(&bob));
+        assert_eq!(pending.public_key, before.public_key);
+
+        assert_eq!(
+            read_report(&mut service, "alice", report).unwrap(),
+            "Grippe"
+        );
+        let rotated = folder_keys(&service);
+        assert!(!rotated.rotation_pending);
+        assert_ne!(rotated.public_key, before.public_key);
+        service
+            .db
+            .update_user(alice, |user| {
+                user.medical_folder.as_mut().unwrap().doctors.insert(bob)
+            })
+            .unwrap();
+        assert!(matches!(
+            read_report(&mut service, "bob", report),
+            Err(ServiceError::NoKeyGrant)
+        ));
+    }
+
+    fn test_report_revisions(service: fn() -> Service) {
+        let mut service = service();
+        let alice = register_as(&mut service, "alice", Role::Patient);
+        let bob = register_as(&mut service, "bob", Role::Doctor);
+        register_as(&mut service, "carol", Role::Doctor);
+        service.login(&username("alice"), STRONG_PASSWORD).unwrap();
+        service.update_data(alice, personal_data()).unwrap();
+        service.add_doctor(alice, bob).unwrap();
+
+        service.login(&username("bob"), STRONG_PASSWORD).unwrap();
+        service
+            .add_report(
+                bob,
+                alice,
+                "Consultation".to_owned(),
+                "Grippe".to_owned(),
+                None,
+            )
+            .unwrap();
+        let report = service.db.list_reports().next().unwrap().id;
+        let reason = |reason: &str| TextLine::try_from(reason.to_owned()).unwrap();
+        service
+            .update_report(report, "Angine".to_owned(), reason("Erreur de saisie"))
+            .unwrap();
+        service
+            .update_report(report, "Angine blanche".to_owned(), reason("Précision"))
+            .unwrap();
+
+        let history = |service: &Service| -> Result<Vec<String>, ServiceError> {
+            let report = service.db.get_report(report).unwrap();
+            let versions = service.report_history(report)?;
+            Ok(versions.into_iter().map(|version| version.text).collect())
+        };
+        assert_eq!(
+            history(&service).unwrap(),
+            ["Grippe", "Angine", "Angine blanche"]
+        );
+        let stored = service.db.get_report(report).unwrap();
+        assert!(stored.revisions.iter().all(|r| r.previous.is_sealed()));
+        assert_eq!(stored.revisions[1].editor, bob);
+        assert_eq!(stored.revisions[1].reason.to_string(), "Précision");
+
+        service.login(&username("carol"), STRONG_PASSWORD).unwrap();
+        assert!(matches!(
+            history(&service),
+            Err(ServiceError::AccessDenied(_))
+        ));
+        assert!(service
+            .update_report(report, "Rien".to_owned(), reason("Test"))
+            .is_err());
+
+        // Les anciennes versions sont chiffrées à nouveau avec la clé du dossier
+        service.login(&username("alice"), STRONG_PASSWORD).unwrap();
+        service.remove_doctor(alice, bob).unwrap();
+        let versions = history(&service).unwrap();
+        assert_eq!(versions, ["Grippe", "Angine", "Angine blanche"]);
+        let report = service.db.get_report(report).unwrap();
+        let revisions = service.report_history(report).unwrap();
+        assert!(revisions[0].revision.is_none());
+        assert_eq!(revisions[2].revision.unwrap().editor, bob);
+    }
+
+    fn test_provenance(service: fn() -> Service) {
+        let start = DateTime::parse_from_rfc3339("2024-05-02T08:00:00Z")
+            .unwrap()
+            .to_utc();
+        let clock = ManualClock::new(start);
+        let mut service = service().with_clock(clock.clone());
+        let alice = register_as(&mut service, "alice", Role::Patient);
+        let bob = register_as(&mut service, "bob", Role::Doctor);
+        let provenance =
+            |service: &Service, user: UserID| service.db.get_user(user).unwrap().provenance;
+
+        let created = provenance(&service, alice);
+        assert_eq!(created.created_at, Some(start));
+        assert_eq!(created.updated_by, Some(alice));
+
+        clock.advance(TimeDelta::hours(1));
+        service.login(&username("alice"), STRONG_PASSWORD).unwrap();
+        service.update_data(alice, personal_data()).unwrap();
+        service.add_doctor(alice, bob).unwrap();
+        let updated = provenance(&service, alice);
+        assert_eq!(updated.created_at, Some(start));
+        assert_eq!(updated.updated_at, Some(start + TimeDelta::hours(1)));
+
+        clock.advance(TimeDelta::hours(1));
+        service.login(&username("bob"), STRONG_PASSWORD).unwrap();
+        service
+            .add_report(
+                bob,
+                alice,
+                "Consultation".to_owned(),
+                "Grippe".to_owned(),
+                None,
+            )
+            .unwrap();
+        let report = service.db.list_reports().next().unwrap().id;
+        clock.advance(TimeDelta::minutes(5));
+        let reason = TextLine::try_from("Précision".to_owned()).unwrap();
+        service
+            .update_report(report, "Angine".to_owned(), reason)
+            .unwrap();
+
+        let report = service.db.get_report(report).unwrap();
+        let edited = start + TimeDelta::hours(2) + TimeDelta::minutes(5);
+        assert_eq!(
+            report.provenance.created_at,
+            Some(start + TimeDelta::hours(2))
+        );
+        assert_eq!(report.provenance.updated_at, Some(edited));
+        assert_eq!(report.provenance.updated_by, Some(bob));
+        assert_eq!(report.revisions[0].timestamp, edited);
+        // Seules les modifications datent un compte, pas les connexions
+        assert_eq!(provenance(&service, bob).updated_at, Some(start));
+    }
+
+    fn test_report_signing(service: fn() -> Service) {
+        let mut service = service();
+        let alice = register_as(&mut service, "alice", Role::Patient);
+        let bob = register_as(&mut service, "bob", Role::Doctor);
+        let carol = register_as(&mut service, "carol", Role::Doctor);
+        service.login(&username("alice"), STRONG_PASSWORD).unwrap();
+        service.update_data(alice, personal_data()).unwrap();
+        service.add_doctor(alice, bob).unwrap();
+        service.add_doctor(alice, carol).unwrap();
+
+        service.login(&username("bob"), STRONG_PASSWORD).unwrap();
+        service
+            .add_report(
+                bob,
+                alice,
+                "Consultation".to_owned(),
+                "Grippe".to_owned(),
+                None,
+            )
+            .unwrap();
+        let report = service.db.list_reports().next().unwrap().id;
+        let verify = |service: &Service| {
+            service
+                .verify_report(service.db.get_report(report).unwrap())
+                .unwrap()
+        };
+        let verification = verify(&service);
+        assert_eq!(verification.original, SignatureStatus::Valid);
+        assert!(verification.seal.is_none());
+        assert!(matches!(
+            service.add_addendum(report, "Fièvre".to_owned()),
+            Err(ServiceError::ReportNotFinal)
+        ));
+
+        // Un médecin sans accès au dossier n'apprend rien du rapport, et sa
+        // tentative est consignée
+        let dave = register_as(&mut service, "dave", Role::Doctor);
+        service.login(&username("dave"), STRONG_PASSWORD).unwrap();
+        assert!(matches!(
+            service.add_addendum(report, "Fièvre".to_owned()),
+            Err(ServiceError::AccessDenied(_))
+        ));
+        let denied = service.enforcer.audit_log().query(&AuditFilter {
+            subject: Some(dave),
+            action: Some("read-report".to_owned()),
+            ..Default::default()
+        });
+        assert_eq!(denied.len(), 1);
+        assert_eq!(denied[0].decision, Decision::Denied);
+        service.login(&username("bob"), STRONG_PASSWORD).unwrap();
+
+        let reason = TextLine::try_from("Précision".to_owned()).unwrap();
+        service
+            .update_report(report, "Angine".to_owned(), reason.clone())
+            .unwrap();
+
+        // Seul l'auteur peut rendre le rapport définitif
+        service.login(&username("carol"), STRONG_PASSWORD).unwrap();
+        assert!(matches!(
+            service.seal_report(report),
+            Err(ServiceError::AccessDenied(_))
+        ));
+
+        service.login(&username("bob"), STRONG_PASSWORD).unwrap();
+        service.seal_report(report).unwrap();
+        assert!(matches!(
+            service.seal_report(report),
+            Err(ServiceError::ReportSealed)
+        ));
+        assert!(matches!(
+            service.update_report(report, "Rien".to_owned(), reason),
+            Err(ServiceError::ReportSealed)
+        ));
+        let verification = verify(&service);
+        assert_eq!(verification.original, SignatureStatus::Valid);
+        assert_eq!(verification.revisions, 1);
+        assert_eq!(verification.seal.unwrap().1, SignatureStatus::Valid);
+
+        // Un autre médecin traitant peut compléter le rapport définitif
+        service.login(&username("carol"), STRONG_PASSWORD).unwrap();
+        let addendum = service.add_addendum(report, "Fièvre".to_owned()).unwrap();
+        let stored = service.db.get_report(addendum).unwrap();
+        assert!(stored.is_final());
+        assert_eq!(stored.parent, Some(report));
+        assert_eq!(stored.author, carol);
+        assert_eq!(stored.title, "Addendum au rapport: Consultation");
+        assert_eq!(
+            service.verify_report(stored).unwrap().seal.unwrap().1,
+            SignatureStatus::Valid
+        );
+
+        // Le patient vérifie les signatures, et voit toute modification
+        service.login(&username("alice"), STRONG_PASSWORD).unwrap();
+        assert_eq!(verify(&service).original, SignatureStatus::Valid);
+        service
+            .db
+            .update_report(report, |report| report.title = "Bilan".to_owned())
+            .unwrap();
+        let verification = verify(&service);
+        assert_eq!(verification.original, SignatureStatus::Invalid);
+        assert_eq!(verification.seal.unwrap().1, SignatureStatus::Invalid);
+    }
+
+    fn test_report_kinds(service: fn() -> Service) {
+        let today = DateTime::parse_from_rfc3339("2024-05-02T08:00:00Z")
+            .unwrap()
+            .to_utc();
+        let mut service = service().with_clock(ManualClock::new(today));
+        let alice = register_as(&mut service, "alice", Role::Patient);
+        let bob = register_as(&mut service, "bob", Role::Doctor);
+        service.login(&username("alice"), STRONG_PASSWORD).unwrap();
+        service.update_data(alice, personal_data()).unwrap();
+        service.add_doctor(alice, bob).unwrap();
+
+        let date = |date: &str| date.parse::<NaiveDate>().unwrap();
+        let line = |line: &str| TextLine::try_from(line.to_owned()).unwrap();
+        let measurement = |value: &str| Measurement::try_from(value.to_owned()).unwrap();
+        let lab_result = |sampled_on: &str, low: &str, high: &str| ReportDetails::LabResult {
+            sampled_on: date(sampled_on),
+            results: vec![LabValue {
+                analysis: line("Hémoglobine"),
+                value: measurement("11,2"),
+                unit: line("g/dl"),
+                low: Some(measurement(low)),
+                high: Some(measurement(high)),
+            }],
+        };
+
+        service.login(&username("bob"), STRONG_PASSWORD).unwrap();
+        let mut add = |title: &str, details: Option<ReportDetails>| {
+            service.add_report(bob, alice, title.to_owned(), "Rien".to_owned(), details)
+        };
+        add("Bilan", Some(lab_result("2024-05-01", "12", "16"))).unwrap();
+        add("Note", None).unwrap();
+        add(
+            "Sortie",
+            Some(ReportDetails::DischargeLetter {
+                admitted: date("2024-04-20"),
+                discharged: date("2024-04-28"),
+                diagnoses: vec![Icd10Code::try_from("J18.9".to_owned()).unwrap()],
+            }),
+        )
+        .unwrap();
+
+        // Les champs sont validés entre eux et avec la date du jour
+        assert!(matches!(
+            add("Bilan", Some(lab_result("2024-05-03", "12", "16"))),
+            Err(ServiceError::InvalidReport(InvalidDetails::FutureDate))
+        ));
+        assert!(matches!(
+            add("Bilan", Some(lab_result("2024-05-01", "16", "12"))),
+            Err(ServiceError::InvalidReport(InvalidDetails::InvertedRange))
+        ));
+        assert!(matches!(
+            add(
+                "Ordonnance",
+                Some(ReportDetails::Prescription {
+                    medications: Vec::new(),
+                    valid_until: date("2024-08-01"),
+                })
+            ),
+            Err(ServiceError::InvalidReport(InvalidDetails::Empty))
+        ));
+        assert!(matches!(
+            add(
+                "Sortie",
+                Some(ReportDetails::DischargeLetter {
+                    admitted: date("2024-04-28"),
+                    discharged: date("2024-04-20"),
+                    diagnoses: Vec::new(),
+                })
+            ),
+            Err(ServiceError::InvalidReport(InvalidDetails::EndBeforeStart))
+        ));
+
+        let titles = |service: &Service, kind| {
+            let mut titles: Vec<_> = service
+                .list_reports(alice, kind)
+                .map(|report| report.title.clone())
+                .collect();
+            titles.sort();
+            titles
+        };
+        assert_eq!(titles(&service, None), ["Bilan", "Note", "Sortie"]);
+        assert_eq!(titles(&service, Some(ReportKind::LabResult)), ["Bilan"]);
+        assert!(titles(&service, Some(ReportKind::Prescription)).is_empty());
+
+        let report = service
+            .list_reports(alice, Some(ReportKind::LabResult))
+            .next()
+            .unwrap();
+        let Some(ReportDetails::LabResult { results, .. }) = &report.details else {
+            panic!("résultat de laboratoire attendu");
+        };
+        assert!(results[0].is_abnormal());
+        assert_eq!(
+            results[0].to_string(),
+            "Hémoglobine: 11.2 g/dl (12 - 16) [!]"
+        );
+
+        // Les champs structurés sont couverts par la signature
+        let id = report.id;
+        service
+            .db
+            .update_report(id, |report| {
+                report.details = Some(lab_result("2024-05-01", "10", "16"))
+            })
+            .unwrap();
+        let report = service.db.get_report(id).unwrap();
+        assert_eq!(
+            service.verify_report(report).unwrap().original,
+            SignatureStatus::Invalid
+        );
+
+        // Un addendum est listé avec le rapport qu'il complète
+        let letter = service
+            .list_reports(alice, Some(ReportKind::DischargeLetter))
+            .next()
+            .unwrap()
+            .id;
+        service.seal_report(letter).unwrap();
+        service.add_addendum(letter, "Suivi".to_owned()).unwrap();
+        assert_eq!(
+            titles(&service, Some(ReportKind::DischargeLetter)),
+            ["Addendum au rapport: Sortie", "Sortie"]
+        );
+        assert_eq!(titles(&service, Some(ReportKind::LabResult)), ["Bilan"]);
+    }
+
+    fn test_attachments(service: fn() -> Service) {
+        let mut service = service();
+        let alice = register_as(&mut service, "alice", Role::Patient);
+        let bob = register_as(&mut service, "bob", Role::Doctor);
+        register_as(&mut service, "carol", Role::Doctor);
+        service.login(&username("alice"), STRONG_PASSWORD).unwrap();
+        service.update_data(alice, personal_data()).unwrap();
+        service.add_doctor(alice, bob).unwrap();
+
+        service.login(&username("bob"), STRONG_PASSWORD).unwrap();
+        service
+            .add_report(bob, alice, "Bilan".to_owned(), "Voir PDF".to_owned(), None)
+            .unwrap();
+        let report = service.db.list_reports().next().unwrap().id;
+        let name = || TextLine::try_from("bilan.pdf".to_owned()).unwrap();
+        let pdf = b"%PDF-1.7 hemoglobine 11.2 g/dl".to_vec();
+        service.add_attachment(report, name(), &pdf).unwrap();
+        assert!(matches!(
+            service.add_attachment(report, name(), b"MZ\x90\x00"),
+            Err(ServiceError::UnsupportedAttachment)
+        ));
+        let mut large = pdf.clone();
+        large.resize(MAX_ATTACHMENT_SIZE + 1, 0);
+        assert!(matches!(
+            service.add_attachment(report, name(), &large),
+            Err(ServiceError::AttachmentTooLarge)
+        ));
+
+        let stored = service.db.get_report(report).unwrap();
+        let attachment = &stored.attachments[0];
+        assert_eq!(attachment.mime_type, MimeType::Pdf);
+        assert_eq!(attachment.size, pdf.len());
+        let blob = attachment.blob.clone();
+        // Le magasin ne contient que le fichier chiffré
+        assert_ne!(service.blobs.get(&blob).unwrap(), pdf);
+        assert_eq!(service.attachment_content(stored, &blob).unwrap(), pdf);
+
+        // Les pièces jointes font partie de la version définitive
+        service.seal_report(report).unwrap();
+        assert!(matches!(
+            service.add_attachment(report, name(), &pdf),
+            Err(ServiceError::ReportSealed)
+        ));
+
+        let content = |service: &Service| {
+            service.attachment_content(service.db.get_report(report).unwrap(), &blob)
+        };
+        service.login(&username("carol"), STRONG_PASSWORD).unwrap();
+        assert!(matches!(
+            content(&service),
+            Err(ServiceError::AccessDenied(_))
+        ));
+
+        // Les clés des pièces jointes suivent le renouvellement de la clé du dossier
+        service.login(&username("alice"), STRONG_PASSWORD).unwrap();
+        service.remove_doctor(alice, bob).unwrap();
+        assert_eq!(content(&service).unwrap(), pdf);
+        let verification = service
+            .verify_report(service.db.get_report(report).unwrap())
+            .unwrap();
+        assert_eq!(verification.seal.unwrap().1, SignatureStatus::Valid);
+
+        service.delete_data(alice).unwrap();
+        assert_eq!(
+            service.blobs.get(&blob).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    fn test_keys_survive_password_change(service: fn() -> Service) {
+        let mut service = service();
+        let alice = register_as(&mut service, "alice", Role::Patient);
+        let bob = register_as(&mut service, "bob", Role::Doctor);
+        service.login(&username("alice"), STRONG_PASSWORD).unwrap();
+        service.update_data(alice, personal_data()).unwrap();
+        service.login(&username("bob"), STRONG_PASSWORD).unwrap();
+        service
+            .add_report(bob, alice, "Note".to_owned(), "Allergie".to_owned(), None)
+            .unwrap();
+        let report = service.db.list_reports().next().unwrap().id;
+
+        service.login(&username("alice"), STRONG_PASSWORD).unwrap();
+        service
+            .change_password(STRONG_PASSWORD, "An0ther$trongOne")
+            .unwrap();
+        service
+            .login(&username("alice"), "An0ther$trongOne")
+            .unwrap();
+        let content = service.db.get_report(report).unwrap();
+        assert_eq!(service.report_content(content).unwrap(), "Allergie");
+    }
+
+    fn test_legacy_folder_encrypted_at_login(service: fn() -> Service) {
+        let mut service = service();
+        let alice = register_as(&mut service, "alice", Role::Patient);
+        let bob = register_as(&mut service, "bob", Role::Doctor);
+        let report = ReportID::new();
+        service
+            .db
+            .update_user(alice, |user| {
+                user.keys = None;
+                user.medical_folder = Some(MedicalFolder::new(personal_data()));
+            })
+            .unwrap();
+        service
+            .db
+            .store_report(MedicalReport {
+                id: report,
+                title: "Ancien rapport".to_owned(),
+                author: bob,
+                patient: alice,
+                content: ReportContent::Plain("Varicelle".to_owned()),
+                details: None,
+                attachments: Vec::new(),
+                revisions: Vec::new(),
+                provenance: Default::default(),
+                parent: None,
+                signature: None,
+                seal: None,
+            })
+            .unwrap();
+
+        // Sans clé de dossier, aucun nouveau rapport n'est écrit en clair
+        service.login(&username("bob"), STRONG_PASSWORD).unwrap();
+        assert!(matches!(
+            service.add_report(bob, alice, "Suivi".to_owned(), "Rougeole".to_owned(), None),
+            Err(ServiceError::FolderNotEncrypted)
+        ));
+        assert!(matches!(
+            service.update_report(
+                report,
+                "Rougeole".to_owned(),
+                TextLine::try_from("Correction".to_owned()).unwrap()
+            ),
+            Err(ServiceError::FolderNotEncrypted)
+        ));
+        assert_eq!(service.db.list_reports().count(), 1);
+
+        assert_eq!(
+            read_report(&mut service, "alice", report).unwrap(),
+            "Varicelle"
+        );
+        assert!(service.db.get_user(alice).unwrap().keys.is_some());
+        assert!(service.db.get_report(report).unwrap().content.is_sealed());
+    }
+}
@@ -0,0 +1,205 @@
+//! Signature des rapports médicaux par leur auteur (Ed25519).
+//!
+//! Chaque médecin possède une paire de clés de signature, dont la clé privée
+//! est chiffrée pour sa clé publique X25519: elle n'est utilisable que pendant
+//! ses sessions. Un rapport est signé à sa création; il est scellé par une
+//! seconde signature de sa version définitive, après quoi il ne peut plus
+//! être modifié.
+
+use crate::models::{MedicalReport, ReportSignature, SigningKeys, UserID};
+use crate::utils::crypto::{open_box, seal_to, Bytes, PublicKey, StaticSecret};
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand_core::OsRng;
+use serde_json::json;
+use zeroize::Zeroizing;
+
+/// Lie la clé privée chiffrée à son propriétaire
+fn signing_key_aad(owner: UserID) -> Vec<u8> {
+    format!("karak-signing-key:{owner}").into_bytes()
+}
+
+impl SigningKeys {
+    /// Tire une nouvelle paire de clés, chiffrée pour la clé publique
+    /// X25519 de son propriétaire
+    pub fn generate(owner: UserID, recipient: &PublicKey) -> Self {
+        let key = SigningKey::generate(&mut OsRng);
+        Self {
+            public_key: Bytes(key.verifying_key().to_bytes().to_vec()),
+            sealed_private_key: seal_to(recipient, &key.to_bytes(), &signing_key_aad(owner)),
+            recipient: Bytes::from(recipient),
+            retired: Vec::new(),
+        }
+    }
+
+    /// Indique si la clé privée peut être déchiffrée avec la clé X25519 actuelle
+    pub fn is_sealed_for(&self, recipient: &PublicKey) -> bool {
+        self.recipient.public_key().as_ref() == Some(recipient)
+    }
+
+    /// Remplace la paire de clés, en gardant l'ancienne clé publique
+    pub fn renew(&mut self, owner: UserID, recipient: &PublicKey) {
+        let mut retired = std::mem::take(&mut self.retired);
+        retired.push(self.public_key.clone());
+        *self = Self {
+            retired,
+            ..Self::generate(owner, recipient)
+        };
+    }
+
+    /// Déchiffre la clé privée avec la clé X25519 de son propriétaire
+    pub fn unlock(&self, owner: UserID, secret: &StaticSecret) -> Option<SigningKey> {
+        let bytes = Zeroizing::new(
+            open_box(secret, &self.sealed_private_key, &signing_key_aad(owner)).ok()?,
+        );
+        let key = SigningKey::from_bytes(bytes.as_slice().try_into().ok()?);
+        (key.verifying_key().as_bytes() == self.public_key.0.as_slice()).then_some(key)
+    }
+
+    /// Indique si une clé publique est, ou a été, celle de ce médecin
+    pub fn owns(&self, public_key: &Bytes) -> bool {
+        self.public_key == *public_key || self.retired.contains(public_key)
+    }
+}
+
+/// Le message signé pour un rapport: son identifiant, son patient, son
+/// auteur, son titre, ses champs structurés et un texte, ainsi que la date
+/// de scellement pour la version définitive.
+///
+/// Seule la version définitive couvre les pièces jointes, ajoutées après
+/// la signature de l'originale. Leurs clés, chiffrées à nouveau à chaque
+/// renouvellement de la clé du dossier, ne sont pas signées.
+pub fn report_message(
+    report: &MedicalReport,
+    text: &str,
+    sealed_at: Option<DateTime<Utc>>,
+) -> Vec<u8> {
+    let attachments: Vec<_> = match sealed_at {
+        Some(_) => report
+            .attachments
+            .iter()
+            .map(|attachment| {
+                json!([
+                    attachment.name,
+                    attachment.mime_type,
+                    attachment.size,
+                    attachment.blob,
+                ])
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    json!([
+        "karak-report",
+        report.id,
+        report.patient,
+        report.author,
+        report.title,
+        report.details,
+        text,
+        sealed_at,
+        attachments,
+    ])
+    .to_string()
+    .into_bytes()
+}
+
+impl ReportSignature {
+    pub fn sign(key: &SigningKey, message: &[u8]) -> Self {
+        Self {
+            public_key: Bytes(key.verifying_key().to_bytes().to_vec()),
+            signature: Bytes(key.sign(message).to_bytes().to_vec()),
+        }
+    }
+
+    /// Vérifie la signature d'un message, sans savoir à qui est la clé
+    pub fn verify(&self, message: &[u8]) -> bool {
+        let Ok(key) = <[u8; 32]>::try_from(self.public_key.0.as_slice()) else {
+            return false;
+        };
+        let Ok(signature) = Signature::from_slice(&self.signature.0) else {
+            return false;
+        };
+        VerifyingKey::from_bytes(&key)
+            .and_then(|key| key.verify(message, &signature))
+            .is_ok()
+    }
+
+    /// Vérifie la signature d'un message par un médecin donné
+    pub fn check(&self, message: &[u8], author: Option<&SigningKeys>) -> SignatureStatus {
+        if !self.verify(message) {
+            SignatureStatus::Invalid
+        } else if author.is_some_and(|keys| keys.owns(&self.public_key)) {
+            SignatureStatus::Valid
+        } else {
+            SignatureStatus::UnknownKey
+        }
+    }
+}
+
+/// Le résultat de la vérification d'une signature
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum SignatureStatus {
+    #[display("non signé")]
+    Unsigned,
+    #[display("signature valide de l'auteur")]
+    Valid,
+    #[display("signature invalide: le texte a été modifié")]
+    Invalid,
+    #[display("signature valide, mais pas par une clé de l'auteur")]
+    UnknownKey,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ReportContent, ReportID};
+    use crate::utils::crypto::generate_keypair;
+
+    #[test]
+    fn test_signing_keys() {
+        let owner = UserID::new();
+        let (secret, public) = generate_keypair();
+        let mut keys = SigningKeys::generate(owner, &public);
+        assert!(keys.is_sealed_for(&public));
+        let key = keys.unlock(owner, &secret).unwrap();
+        assert!(keys.unlock(UserID::new(), &secret).is_none());
+
+        let report = MedicalReport {
+            id: ReportID::new(),
+            title: "Consultation".to_owned(),
+            author: owner,
+            patient: UserID::new(),
+            content: ReportContent::Plain(String::new()),
+            details: None,
+            attachments: Vec::new(),
+            revisions: Vec::new(),
+            provenance: Default::default(),
+            parent: None,
+            signature: None,
+            seal: None,
+        };
+        let message = report_message(&report, "Grippe", None);
+        let signature = ReportSignature::sign(&key, &message);
+        assert_eq!(
+            signature.check(&message, Some(&keys)),
+            SignatureStatus::Valid
+        );
+        assert_eq!(
+            signature.check(&report_message(&report, "Angine", None), Some(&keys)),
+            SignatureStatus::Invalid
+        );
+        assert_eq!(signature.check(&message, None), SignatureStatus::UnknownKey);
+
+        // Une clé renouvelée vérifie toujours les anciennes signatures
+        let (secret, public) = generate_keypair();
+        keys.renew(owner, &public);
+        assert!(keys.unlock(owner, &secret).is_some());
+        assert_eq!(
+            signature.check(&message, Some(&keys)),
+            SignatureStatus::Valid
+        );
+        assert_eq!(keys.retired.len(), 1);
+    }
+}
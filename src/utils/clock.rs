@@ -0,0 +1,45 @@
+//! Source de l'heure courante, remplaçable pour rendre les tests déterministes
+
+use chrono::{DateTime, TimeDelta, Utc};
+use std::sync::{Arc, Mutex};
+
+pub trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// L'horloge du système
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Une horloge qui n'avance que lorsqu'on le lui demande.
+///
+/// Ses copies partagent la même heure: un test garde une copie pour faire
+/// avancer celle donnée au service.
+#[derive(Debug, Clone)]
+pub struct ManualClock(Arc<Mutex<DateTime<Utc>>>);
+
+impl ManualClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self(Arc::new(Mutex::new(now)))
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = now;
+    }
+
+    pub fn advance(&self, delta: TimeDelta) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) += delta;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
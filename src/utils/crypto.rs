@@ -0,0 +1,274 @@
+//! Chiffrement authentifié (XChaCha20-Poly1305), chiffrement pour le détenteur
+//! d'une clé privée X25519 et dérivation de clés
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::{
+    aead::{Aead, Payload},
+    AeadCore, KeyInit, XChaCha20Poly1305, XNonce,
+};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+pub use x25519_dalek::{PublicKey, StaticSecret};
+
+pub const KEY_LEN: usize = 32;
+pub const SALT_LEN: usize = 16;
+pub const NONCE_LEN: usize = 24;
+
+#[derive(Debug, Error)]
+pub enum CryptoError {
+    #[error("Déchiffrement impossible: clé incorrecte ou données modifiées")]
+    Decryption,
+    #[error("Dérivation de clé impossible: {0}")]
+    KeyDerivation(argon2::Error),
+}
+
+/// Une clé symétrique, effacée de la mémoire quand elle est libérée
+#[derive(Clone)]
+pub struct SecretKey(Zeroizing<[u8; KEY_LEN]>);
+
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretKey(..)")
+    }
+}
+
+impl SecretKey {
+    /// Tire une nouvelle clé au hasard
+    pub fn generate() -> Self {
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        OsRng.fill_bytes(key.as_mut());
+        Self(key)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        key.copy_from_slice(bytes.get(..KEY_LEN).filter(|_| bytes.len() == KEY_LEN)?);
+        Some(Self(key))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; KEY_LEN] {
+        &self.0
+    }
+
+    /// Dérive une clé d'un secret de l'opérateur (phrase de passe ou contenu
+    /// d'un fichier clé) avec Argon2id
+    pub fn derive(secret: &[u8], salt: &[u8; SALT_LEN]) -> Result<Self, CryptoError> {
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, Params::default())
+            .hash_password_into(secret, salt, key.as_mut())
+            .map_err(CryptoError::KeyDerivation)?;
+        Ok(Self(key))
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(self.0.as_ref().into())
+    }
+}
+
+/// Tire un sel au hasard
+pub fn random_salt() -> [u8; SALT_LEN] {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+/// Chiffre des données avec un nonce aléatoire.
+///
+/// Les données associées `aad` sont authentifiées mais pas chiffrées.
+/// Le résultat contient le nonce suivi du texte chiffré.
+pub fn seal(key: &SecretKey, plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = key
+        .cipher()
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .expect("plaintext length is bounded");
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    sealed
+}
+
+/// Des octets, sérialisés en base64
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Bytes(pub Vec<u8>);
+
+impl Serialize for Bytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&BASE64.encode(&self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        BASE64
+            .decode(encoded)
+            .map(Bytes)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl Bytes {
+    /// Interprète les octets comme une clé publique X25519
+    pub fn public_key(&self) -> Option<PublicKey> {
+        <[u8; KEY_LEN]>::try_from(self.0.as_slice())
+            .ok()
+            .map(PublicKey::from)
+    }
+}
+
+impl From<&PublicKey> for Bytes {
+    fn from(key: &PublicKey) -> Self {
+        Self(key.as_bytes().to_vec())
+    }
+}
+
+/// Données chiffrées pour le détenteur d'une clé privée X25519.
+///
+/// Une clé éphémère est tirée à chaque chiffrement; la clé symétrique est
+/// dérivée du secret partagé entre la clé éphémère et celle du destinataire.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SealedBox {
+    pub ephemeral_key: Bytes,
+    pub ciphertext: Bytes,
+}
+
+/// Tire une nouvelle paire de clés X25519
+pub fn generate_keypair() -> (StaticSecret, PublicKey) {
+    let secret = StaticSecret::random_from_rng(OsRng);
+    let public = PublicKey::from(&secret);
+    (secret, public)
+}
+
+fn box_key(shared: &[u8; KEY_LEN], ephemeral: &PublicKey, recipient: &PublicKey) -> SecretKey {
+    let digest = Sha256::new()
+        .chain_update(b"karak-sealed-box")
+        .chain_update(shared)
+        .chain_update(ephemeral.as_bytes())
+        .chain_update(recipient.as_bytes())
+        .finalize();
+    SecretKey::from_bytes(&digest).expect("SHA-256 digests are 32 bytes long")
+}
+
+/// Chiffre des données que seul le détenteur de la clé privée associée à
+/// `recipient` pourra déchiffrer
+pub fn seal_to(recipient: &PublicKey, plaintext: &[u8], aad: &[u8]) -> SealedBox {
+    let (ephemeral, ephemeral_public) = generate_keypair();
+    let shared = ephemeral.diffie_hellman(recipient);
+    let key = box_key(shared.as_bytes(), &ephemeral_public, recipient);
+
+    SealedBox {
+        ephemeral_key: Bytes::from(&ephemeral_public),
+        ciphertext: Bytes(seal(&key, plaintext, aad)),
+    }
+}
+
+/// Déchiffre des données produites par [`seal_to`]
+pub fn open_box(
+    secret: &StaticSecret,
+    sealed: &SealedBox,
+    aad: &[u8],
+) -> Result<Vec<u8>, CryptoError> {
+    let ephemeral = sealed
+        .ephemeral_key
+        .public_key()
+        .ok_or(CryptoError::Decryption)?;
+    let shared = secret.diffie_hellman(&ephemeral);
+    if !shared.was_contributory() {
+        return Err(CryptoError::Decryption);
+    }
+    let key = box_key(shared.as_bytes(), &ephemeral, &PublicKey::from(secret));
+    open(&key, &sealed.ciphertext.0, aad)
+}
+
+/// Déchiffre des données produites par [`seal`] avec les mêmes données associées
+pub fn open(key: &SecretKey, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    if sealed.len() < NONCE_LEN {
+        return Err(CryptoError::Decryption);
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+
+    key.cipher()
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| CryptoError::Decryption)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open() {
+        let key = SecretKey::generate();
+        let sealed = seal(&key, b"groupe sanguin: O", b"header");
+
+        assert_eq!(
+            open(&key, &sealed, b"header").unwrap(),
+            b"groupe sanguin: O"
+        );
+        assert!(open(&key, &sealed, b"other header").is_err());
+        assert!(open(&SecretKey::generate(), &sealed, b"header").is_err());
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(open(&key, &tampered, b"header").is_err());
+        assert!(open(&key, &sealed[..10], b"header").is_err());
+    }
+
+    #[test]
+    fn test_sealed_box() {
+        let (secret, public) = generate_keypair();
+        let sealed = seal_to(&public, b"rapport de consultation", b"patient");
+
+        assert_eq!(
+            open_box(&secret, &sealed, b"patient").unwrap(),
+            b"rapport de consultation"
+        );
+        assert!(open_box(&secret, &sealed, b"other patient").is_err());
+        assert!(open_box(&generate_keypair().0, &sealed, b"patient").is_err());
+
+        let json = serde_json::to_string(&sealed).unwrap();
+        let decoded: SealedBox = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, sealed);
+    }
+
+    #[test]
+    fn test_derive() {
+        let salt = random_salt();
+        let key = SecretKey::derive(b"passphrase", &salt).unwrap();
+
+        assert_eq!(
+            key.as_bytes(),
+            SecretKey::derive(b"passphrase", &salt).unwrap().as_bytes()
+        );
+        assert_ne!(
+            key.as_bytes(),
+            SecretKey::derive(b"other passphrase", &salt)
+                .unwrap()
+                .as_bytes()
+        );
+        assert_ne!(
+            key.as_bytes(),
+            SecretKey::derive(b"passphrase", &random_salt())
+                .unwrap()
+                .as_bytes()
+        );
+    }
+}
@@ -1,121 +1,510 @@
-use casbin::function_map::regex_match;
+use chrono::NaiveDate;
 use derive_more::derive::Display;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::LazyLock;
 use thiserror::Error;
-use zxcvbn::Score::Three;
+use zxcvbn::feedback::{Suggestion, Warning};
+use zxcvbn::time_estimates::CrackTimeSeconds;
+use zxcvbn::Score;
 
 extern crate zxcvbn;
 
-//Needed for the tests in authorization.rs
-impl Username {
-    pub fn new(username: String) -> Username {
-        Username(username)
+use super::clock::{Clock, SystemClock};
+pub use super::username::{canonical_username, username_input_validation, Username};
+
+/// Minimum zxcvbn score a password must reach when nothing else is configured
+pub const DEFAULT_MIN_PASSWORD_SCORE: Score = Score::Three;
+
+/// Password requirements enforced on registration
+#[derive(Debug, Clone, Copy)]
+pub struct PasswordPolicy {
+    pub min_score: Score,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_score: DEFAULT_MIN_PASSWORD_SCORE,
+        }
     }
 }
 
-/// This function checks if the given password is valid
-/// Returns true if the password is strong enough, false otherwise
-fn password_validation(password: &str, username: &str) -> bool {
-    let estimated_strength = zxcvbn::zxcvbn(password, &[username]);
-    estimated_strength.score() >= Three
-}
-
-/// Interactively prompts the user for a password
-pub fn password_input_validation(username: &str) -> String {
-    loop {
-        eprintln!("Please enter a password.");
-        let password = inquire::Password::new("Enter your password: ")
-            .prompt()
-            .unwrap();
-
-        if password_validation(&password, username) {
-            return password;
-        } else {
-            eprintln!("Password is too weak. Please try again.");
+impl PasswordPolicy {
+    /// Builds a policy from a score between 0 and 4
+    pub fn with_min_score(min_score: u8) -> Result<Self, InvalidInput> {
+        let min_score = Score::try_from(min_score).map_err(|_| InvalidInput)?;
+        Ok(Self { min_score })
+    }
+}
+
+/// Strength report of a password, as estimated by zxcvbn
+#[derive(Debug, Clone)]
+pub struct PasswordStrength {
+    /// Estimated score, from 0 (trivial) to 4 (very strong)
+    pub score: Score,
+    /// Estimated time to crack the password offline against a slow hash
+    pub crack_time: CrackTimeSeconds,
+    /// What is wrong with the password, if zxcvbn found something specific
+    pub warning: Option<Warning>,
+    /// Hints to choose a better password
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl PasswordStrength {
+    /// Estimates the strength of a password, penalising passwords close to the username
+    pub fn estimate(password: &str, username: &str) -> Self {
+        let entropy = zxcvbn::zxcvbn(password, &[username]);
+        let feedback = entropy.feedback();
+
+        Self {
+            score: entropy.score(),
+            crack_time: entropy.crack_times().offline_slow_hashing_1e4_per_second(),
+            warning: feedback.and_then(|feedback| feedback.warning()),
+            suggestions: feedback
+                .map(|feedback| feedback.suggestions().to_vec())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn is_acceptable(&self, policy: &PasswordPolicy) -> bool {
+        self.score >= policy.min_score
+    }
+
+    /// The estimated crack time, in French
+    pub fn crack_time_text(&self) -> String {
+        let seconds = match self.crack_time {
+            CrackTimeSeconds::Integer(seconds) => seconds,
+            CrackTimeSeconds::Float(seconds) => seconds as u64,
+        };
+        const UNITS: [(u64, &str, &str); 6] = [
+            (1, "seconde", "secondes"),
+            (60, "minute", "minutes"),
+            (60 * 60, "heure", "heures"),
+            (24 * 60 * 60, "jour", "jours"),
+            (31 * 24 * 60 * 60, "mois", "mois"),
+            (12 * 31 * 24 * 60 * 60, "an", "ans"),
+        ];
+        if seconds < 1 {
+            return "moins d'une seconde".to_owned();
+        }
+        if seconds >= 100 * UNITS[5].0 {
+            return "plusieurs siècles".to_owned();
+        }
+        let (unit, singular, plural) = UNITS
+            .into_iter()
+            .rev()
+            .find(|(unit, ..)| seconds >= *unit)
+            .unwrap_or(UNITS[0]);
+        let count = seconds / unit;
+        format!("{count} {}", if count > 1 { plural } else { singular })
+    }
+
+    /// What is wrong with the password, in French
+    pub fn warning_text(&self) -> Option<&'static str> {
+        self.warning.map(|warning| match warning {
+            Warning::StraightRowsOfKeysAreEasyToGuess => {
+                "Les rangées de touches sont faciles à deviner."
+            }
+            Warning::ShortKeyboardPatternsAreEasyToGuess => {
+                "Les motifs courts sur le clavier sont faciles à deviner."
+            }
+            Warning::RepeatsLikeAaaAreEasyToGuess => {
+                "Les répétitions comme \"aaa\" sont faciles à deviner."
+            }
+            Warning::RepeatsLikeAbcAbcAreOnlySlightlyHarderToGuess => {
+                "Les répétitions comme \"abcabcabc\" sont à peine plus difficiles à deviner que \"abc\"."
+            }
+            Warning::ThisIsATop10Password => "C'est l'un des 10 mots de passe les plus courants.",
+            Warning::ThisIsATop100Password => "C'est l'un des 100 mots de passe les plus courants.",
+            Warning::ThisIsACommonPassword => "C'est un mot de passe très courant.",
+            Warning::ThisIsSimilarToACommonlyUsedPassword => {
+                "Ce mot de passe ressemble à un mot de passe courant."
+            }
+            Warning::SequencesLikeAbcAreEasyToGuess => {
+                "Les suites comme abc ou 6543 sont faciles à deviner."
+            }
+            Warning::RecentYearsAreEasyToGuess => "Les années récentes sont faciles à deviner.",
+            Warning::AWordByItselfIsEasyToGuess => "Un mot seul est facile à deviner.",
+            Warning::DatesAreOftenEasyToGuess => "Les dates sont souvent faciles à deviner.",
+            Warning::NamesAndSurnamesByThemselvesAreEasyToGuess => {
+                "Les noms et prénoms seuls sont faciles à deviner."
+            }
+            Warning::CommonNamesAndSurnamesAreEasyToGuess => {
+                "Les noms et prénoms courants sont faciles à deviner."
+            }
+        })
+    }
+
+    /// Hints to choose a better password, in French
+    pub fn suggestion_texts(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.suggestions.iter().map(|suggestion| match suggestion {
+            Suggestion::UseAFewWordsAvoidCommonPhrases => {
+                "Utilisez quelques mots, en évitant les expressions courantes."
+            }
+            Suggestion::NoNeedForSymbolsDigitsOrUppercaseLetters => {
+                "Les symboles, chiffres et majuscules ne sont pas nécessaires."
+            }
+            Suggestion::AddAnotherWordOrTwo => {
+                "Ajoutez un ou deux mots. Les mots peu courants sont préférables."
+            }
+            Suggestion::CapitalizationDoesntHelpVeryMuch => "Les majuscules n'aident pas beaucoup.",
+            Suggestion::AllUppercaseIsAlmostAsEasyToGuessAsAllLowercase => {
+                "Tout en majuscules est presque aussi facile à deviner que tout en minuscules."
+            }
+            Suggestion::ReversedWordsArentMuchHarderToGuess => {
+                "Les mots à l'envers ne sont pas beaucoup plus difficiles à deviner."
+            }
+            Suggestion::PredictableSubstitutionsDontHelpVeryMuch => {
+                "Les substitutions prévisibles comme '@' au lieu de 'a' n'aident pas beaucoup."
+            }
+            Suggestion::UseALongerKeyboardPatternWithMoreTurns => {
+                "Utilisez un motif de clavier plus long, avec plus de changements de direction."
+            }
+            Suggestion::AvoidRepeatedWordsAndCharacters => "Évitez les mots et caractères répétés.",
+            Suggestion::AvoidSequences => "Évitez les suites.",
+            Suggestion::AvoidRecentYears => "Évitez les années récentes.",
+            Suggestion::AvoidYearsThatAreAssociatedWithYou => {
+                "Évitez les années qui vous sont associées."
+            }
+            Suggestion::AvoidDatesAndYearsThatAreAssociatedWithYou => {
+                "Évitez les dates et années qui vous sont associées."
+            }
+        })
+    }
+}
+
+impl fmt::Display for PasswordStrength {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "score {}/4, pourrait être cassé en {}.",
+            self.score,
+            self.crack_time_text()
+        )?;
+        if let Some(warning) = self.warning_text() {
+            write!(f, " {warning}")?;
+        }
+        for suggestion in self.suggestion_texts() {
+            write!(f, " {suggestion}")?;
         }
+        Ok(())
+    }
+}
+
+/// This function checks if the given password is valid
+/// Returns the strength report as an error if the password is too weak
+pub fn password_validation(
+    password: &str,
+    username: &str,
+    policy: &PasswordPolicy,
+) -> Result<PasswordStrength, PasswordStrength> {
+    let strength = PasswordStrength::estimate(password, username);
+    if strength.is_acceptable(policy) {
+        Ok(strength)
+    } else {
+        Err(strength)
     }
 }
 
+/// Interactively prompts the user for a new password, asked twice for confirmation.
+/// The strength check is left to the caller; cancelling the prompt returns its error.
+pub fn password_input(message: &str) -> Result<String, inquire::InquireError> {
+    inquire::Password::new(message)
+        .with_custom_confirmation_message("Confirmez le mot de passe : ")
+        .with_custom_confirmation_error_message("Les mots de passe ne correspondent pas.")
+        .prompt()
+}
+
 #[derive(Debug, Clone, Copy, Display, Error)]
 pub struct InvalidInput;
 
-/// Wrapper type for a username thas has been validated
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, Display)]
-pub struct Username(String);
+/// Wrapper type for an AVS number that has been validated
+///
+/// The number is kept as its 13 digits, and always displayed as
+/// `756.XXXX.XXXX.XX`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct AVSNumber(String);
+
+impl AVSNumber {
+    /// Accepts the digits alone or the usual dotted form, nothing in between
+    pub fn parse(value: &str) -> Result<Self, InvalidInput> {
+        let value = value.trim();
+        let digits = match value.len() {
+            13 => value.to_owned(),
+            16 if [3, 8, 13].iter().all(|&i| value.as_bytes()[i] == b'.') => value.replace('.', ""),
+            _ => return Err(InvalidInput),
+        };
+        Self::from_digits(digits)
+    }
+
+    /// Accepts dots anywhere, as numbers were stored before being normalised
+    pub fn parse_lenient(value: &str) -> Result<Self, InvalidInput> {
+        Self::from_digits(value.replace('.', ""))
+    }
 
-impl TryFrom<String> for Username {
+    fn from_digits(digits: String) -> Result<Self, InvalidInput> {
+        if validate_avs_number(&digits) {
+            Ok(AVSNumber(digits))
+        } else {
+            Err(InvalidInput)
+        }
+    }
+
+    /// The 13 digits of the number
+    pub fn digits(&self) -> &str {
+        &self.0
+    }
+
+    /// Only the country prefix and the check digits, for lists
+    pub fn masked(&self) -> String {
+        format!("756.****.****.{}", &self.0[11..])
+    }
+}
+
+impl fmt::Display for AVSNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = &self.0;
+        write!(
+            f,
+            "{}.{}.{}.{}",
+            &digits[..3],
+            &digits[3..7],
+            &digits[7..11],
+            &digits[11..]
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for AVSNumber {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        Self::parse_lenient(&value)
+            .map_err(|_| serde::de::Error::custom(format!("invalid AVS number: {value}")))
+    }
+}
+
+impl TryFrom<String> for AVSNumber {
     type Error = InvalidInput;
 
-    fn try_from(username: String) -> Result<Self, Self::Error> {
-        username_validation(&username)?;
-        Ok(Self(username))
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
     }
 }
 
-impl TryFrom<&str> for Username {
+fn validate_avs_number(avs_number: &str) -> bool {
+    avs_number.len() == 13
+        && avs_number.starts_with("756")
+        && avs_number.bytes().all(|b| b.is_ascii_digit())
+        && gtin_validate::gtin13::check(avs_number)
+}
+
+/// A first or last name: letters, spaces, hyphens and apostrophes
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Display, Serialize, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PersonName(String);
+
+pub const MAX_NAME_LEN: usize = 100;
+
+impl TryFrom<String> for PersonName {
     type Error = InvalidInput;
 
-    fn try_from(username: &str) -> Result<Self, Self::Error> {
-        username_validation(username)?;
-        Ok(Self(username.to_owned()))
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let value = value.trim();
+        let valid = !value.is_empty()
+            && value.chars().count() <= MAX_NAME_LEN
+            && value.starts_with(char::is_alphabetic)
+            && value
+                .chars()
+                .all(|c| c.is_alphabetic() || matches!(c, ' ' | '-' | '\'' | '’' | '.'));
+        if valid {
+            Ok(Self(value.to_owned()))
+        } else {
+            Err(InvalidInput)
+        }
     }
 }
 
-impl AsRef<str> for Username {
-    fn as_ref(&self) -> &str {
-        &self.0
+/// A date of birth, neither in the future nor before 1900
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Display, Serialize, Deserialize)]
+#[serde(try_from = "NaiveDate")]
+#[display("{}", _0.format("%d.%m.%Y"))]
+pub struct DateOfBirth(NaiveDate);
+
+impl DateOfBirth {
+    pub fn date(&self) -> NaiveDate {
+        self.0
     }
 }
 
-fn username_validation(username: &str) -> Result<(), InvalidInput> {
-    if username.len() < 3 || username.len() > 30 || regex_match(r"^[a-zA-Z0-9_-]+$",username){
-        return Err(InvalidInput);
+impl TryFrom<NaiveDate> for DateOfBirth {
+    type Error = InvalidInput;
+
+    fn try_from(date: NaiveDate) -> Result<Self, Self::Error> {
+        let earliest = NaiveDate::from_ymd_opt(1900, 1, 1).expect("valid date");
+        if date < earliest || date > SystemClock.now().date_naive() {
+            return Err(InvalidInput);
+        }
+        Ok(Self(date))
+    }
+}
+
+impl TryFrom<String> for DateOfBirth {
+    type Error = InvalidInput;
+
+    /// Accepts `JJ.MM.AAAA` as well as `AAAA-MM-JJ`
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let value = value.trim();
+        NaiveDate::parse_from_str(value, "%d.%m.%Y")
+            .or_else(|_| NaiveDate::parse_from_str(value, "%Y-%m-%d"))
+            .map_err(|_| InvalidInput)?
+            .try_into()
     }
-    Ok(())
 }
 
-pub fn username_input_validation(message: &str) -> Result<Username, InvalidInput> {
-    loop {
-        let username = inquire::Text::new(message)
-            .prompt()
-            .unwrap();
+/// A phone number: an optional `+`, then 6 to 15 digits, possibly separated
+/// by spaces
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Display, Serialize, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PhoneNumber(String);
 
-        if username_validation(&username).is_ok() {
-            return username.try_into();
+impl TryFrom<String> for PhoneNumber {
+    type Error = InvalidInput;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let value = value.trim();
+        let digits = value.strip_prefix('+').unwrap_or(value);
+        let count = digits.chars().filter(char::is_ascii_digit).count();
+        let valid = (6..=15).contains(&count)
+            && digits.starts_with(|c: char| c.is_ascii_digit())
+            && digits.chars().all(|c| c.is_ascii_digit() || c == ' ');
+        if valid {
+            Ok(Self(value.to_owned()))
         } else {
-            eprintln!("Invalid username. Please try again. \
-            \nUsername must be between 3 and 30 characters long and can only contain letters, numbers, underscores and hyphens.");
+            Err(InvalidInput)
         }
     }
 }
 
-/// Wrapper type for an AVS number that has been validated
-#[derive(Debug, Display, Serialize, Deserialize, Hash)]
-pub struct AVSNumber(String);
+/// A Swiss health insurance card number: 20 digits starting with `80756`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Display, Serialize, Deserialize)]
+#[serde(try_from = "String")]
+pub struct InsuranceNumber(String);
 
-impl TryFrom<String> for AVSNumber {
+impl TryFrom<String> for InsuranceNumber {
     type Error = InvalidInput;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        if validate_avs_number(&value) {
-            Ok(AVSNumber(value))
+        let digits: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+        if digits.len() == 20
+            && digits.starts_with("80756")
+            && digits.bytes().all(|b| b.is_ascii_digit())
+        {
+            Ok(Self(digits))
         } else {
             Err(InvalidInput)
         }
     }
 }
 
-fn validate_avs_number(avs_number: &str) -> bool {
+/// A single line of free text, such as a street, a substance or a dose
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Display, Serialize, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TextLine(String);
+
+pub const MAX_TEXT_LINE_LEN: usize = 100;
+
+impl TryFrom<String> for TextLine {
+    type Error = InvalidInput;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let value = value.trim();
+        if value.is_empty()
+            || value.chars().count() > MAX_TEXT_LINE_LEN
+            || value.chars().any(char::is_control)
+        {
+            return Err(InvalidInput);
+        }
+        Ok(Self(value.to_owned()))
+    }
+}
+
+/// A postal code: 3 to 10 letters, digits, spaces or hyphens
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Display, Serialize, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PostalCode(String);
+
+impl TryFrom<String> for PostalCode {
+    type Error = InvalidInput;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let value = value.trim();
+        let valid = (3..=10).contains(&value.len())
+            && value.starts_with(|c: char| c.is_ascii_alphanumeric())
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, ' ' | '-'));
+        if valid {
+            Ok(Self(value.to_uppercase()))
+        } else {
+            Err(InvalidInput)
+        }
+    }
+}
+
+/// A diagnosis code of the ICD-10 classification, such as `J45` or `E11.9`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Display, Serialize, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Icd10Code(String);
+
+/// A chapter letter, two characters, then an optional subdivision
+static ICD10: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^[A-Z][0-9][0-9A-Z](\.[0-9A-Z]{1,4})?$").expect("valid ICD-10 regex")
+});
+
+impl TryFrom<String> for Icd10Code {
+    type Error = InvalidInput;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let code = value.trim().to_uppercase();
+        if ICD10.is_match(&code) {
+            Ok(Self(code))
+        } else {
+            Err(InvalidInput)
+        }
+    }
+}
+
+/// A measured value, a decimal number such as `4.5` or `-0,25`
+///
+/// The value is kept as entered, with a decimal point, so that it is shown
+/// with its original precision.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Display, Serialize, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Measurement(String);
+
+static MEASUREMENT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^-?[0-9]{1,9}(\.[0-9]{1,6})?$").expect("valid measurement regex")
+});
 
-    //Check if the AVS number starts with 756
-    if avs_number.chars().take(3).collect::<String>() != "756" {
-        return false;
+impl Measurement {
+    pub fn value(&self) -> f64 {
+        self.0.parse().unwrap_or_default()
+    }
+}
+
+impl TryFrom<String> for Measurement {
+    type Error = InvalidInput;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let value = value.trim().replace(',', ".");
+        if MEASUREMENT.is_match(&value) {
+            Ok(Self(value))
+        } else {
+            Err(InvalidInput)
+        }
     }
-    //Remove the dots
-    let avs_number_no_dot = avs_number.replace(".","");
-    
-    gtin_validate::gtin13::check(&avs_number_no_dot)
-    
 }
 
 #[cfg(test)]
@@ -129,31 +518,153 @@ mod tests {
         let strong_password = "Str0ngP@ssw0rd!";
         let weak_password = "123";
         let weak_password_because_of_username = "valid_user1!##d";
+        let policy = PasswordPolicy::default();
 
         // Test strong password
-        assert!(password_validation(strong_password, valid_username));
+        assert!(password_validation(strong_password, valid_username, &policy).is_ok());
 
         // Test weak password
-        assert!(!password_validation(weak_password, valid_username));
-        
+        assert!(password_validation(weak_password, valid_username, &policy).is_err());
+
         // Test weak password because of username
-        assert!(!password_validation(weak_password_because_of_username, valid_username));
-        
+        assert!(
+            password_validation(weak_password_because_of_username, valid_username, &policy)
+                .is_err()
+        );
+
         // Making sure the other test wasn't a fluke
-        assert!(password_validation(weak_password_because_of_username, valid_but_unrelated_username));
+        assert!(password_validation(
+            weak_password_because_of_username,
+            valid_but_unrelated_username,
+            &policy
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_password_strength_feedback() {
+        let strength =
+            password_validation("password", "valid_user", &PasswordPolicy::default()).unwrap_err();
+
+        assert!(strength.score < DEFAULT_MIN_PASSWORD_SCORE);
+        assert!(strength.warning.is_some());
+        assert!(!strength.suggestions.is_empty());
+        let feedback = strength.to_string();
+        assert!(feedback.contains(strength.warning_text().unwrap()));
+        assert!(feedback.contains("pourrait être cassé en"));
+        assert!(!feedback.contains(&strength.warning.unwrap().to_string()));
+    }
+
+    #[test]
+    fn test_password_policy_threshold() {
+        let lenient = PasswordPolicy::with_min_score(0).unwrap();
+        let strict = PasswordPolicy::with_min_score(4).unwrap();
+
+        assert!(password_validation("123", "valid_user", &lenient).is_ok());
+        assert!(password_validation("Str0ngP@ssw0rd!", "valid_user", &strict).is_err());
+        assert!(PasswordPolicy::with_min_score(5).is_err());
     }
 
     #[test]
     fn test_validate_avs_number() {
         // Valid AVS numbers
-        assert!(validate_avs_number("756.1234.5678.97"));
-        assert!(validate_avs_number("756.0905.7171.04"));
-        assert!(validate_avs_number("7561234567897"));
+        assert!(AVSNumber::parse("756.1234.5678.97").is_ok());
+        assert!(AVSNumber::parse("756.0905.7171.04").is_ok());
+        assert!(AVSNumber::parse("7561234567897").is_ok());
 
         // Invalid AVS numbers
-        assert!(!validate_avs_number("123.4567.8901.23"));
-        assert!(!validate_avs_number("756.0905.7171.05"));
-        assert!(!validate_avs_number("756.1234.5678.98"));
-        assert!(!validate_avs_number("invalid_avs_number"));
+        assert!(AVSNumber::parse("123.4567.8901.23").is_err());
+        assert!(AVSNumber::parse("756.0905.7171.05").is_err());
+        assert!(AVSNumber::parse("756.1234.5678.98").is_err());
+        assert!(AVSNumber::parse("invalid_avs_number").is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_avs_number_canonical_form() {
+        let dotted = AVSNumber::parse("756.1234.5678.97").unwrap();
+        let digits = AVSNumber::parse("7561234567897").unwrap();
+        assert_eq!(dotted, digits);
+        assert_eq!(digits.digits(), "7561234567897");
+        assert_eq!(digits.to_string(), "756.1234.5678.97");
+        assert_eq!(digits.masked(), "756.****.****.97");
+
+        // Points mal placés
+        assert!(AVSNumber::parse("7561.234.5678.97").is_err());
+        assert!(AVSNumber::parse("756.12345678.97").is_err());
+        assert!(AVSNumber::parse("756..1234567897").is_err());
+        assert!(AVSNumber::parse("756.1234.5678.9.7").is_err());
+        assert!(AVSNumber::parse("+756123456789").is_err());
+
+        // Les anciens fichiers sont relus quelle que soit la place des points
+        let stored: AVSNumber = serde_json::from_str("\"7561.234.5678.97\"").unwrap();
+        assert_eq!(stored, digits);
+        assert_eq!(serde_json::to_string(&stored).unwrap(), "\"7561234567897\"");
+    }
+
+    #[test]
+    fn test_personal_data_fields() {
+        let name = |value: &str| PersonName::try_from(value.to_owned());
+        assert!(name("Jean-Luc").is_ok());
+        assert!(name("D'Alembert").is_ok());
+        assert_eq!(name("  Zoé ").unwrap().to_string(), "Zoé");
+        assert!(name("").is_err());
+        assert!(name("R2D2").is_err());
+        assert!(name("-Jean").is_err());
+
+        let date = |value: &str| DateOfBirth::try_from(value.to_owned());
+        assert_eq!(date("1985-03-12").unwrap(), date("12.03.1985").unwrap());
+        assert!(date("31.02.1985").is_err());
+        assert!(date("01.01.1899").is_err());
+        assert!(date("01.01.3000").is_err());
+
+        let phone = |value: &str| PhoneNumber::try_from(value.to_owned());
+        assert!(phone("+41 79 123 45 67").is_ok());
+        assert!(phone("0791234567").is_ok());
+        assert!(phone("12345").is_err());
+        assert!(phone("+41-79").is_err());
+
+        let insurance = |value: &str| InsuranceNumber::try_from(value.to_owned());
+        assert!(insurance("80756012345678901234").is_ok());
+        assert!(insurance("80276012345678901234").is_err());
+        assert!(insurance("8075601234567890123").is_err());
+
+        assert_eq!(
+            PostalCode::try_from("sw1a 1aa".to_owned())
+                .unwrap()
+                .to_string(),
+            "SW1A 1AA"
+        );
+        assert!(PostalCode::try_from("12".to_owned()).is_err());
+        assert!(TextLine::try_from("Rue du Lac 1\n".to_owned()).is_ok());
+        assert!(TextLine::try_from("Rue\tdu Lac".to_owned()).is_err());
+
+        // Les valeurs relues sont validées
+        assert!(serde_json::from_str::<DateOfBirth>("\"1985-03-12\"").is_ok());
+        assert!(serde_json::from_str::<DateOfBirth>("\"2999-03-12\"").is_err());
+        assert!(serde_json::from_str::<PersonName>("\"R2D2\"").is_err());
+    }
+
+    #[test]
+    fn test_icd10_code() {
+        let code = |value: &str| Icd10Code::try_from(value.to_owned());
+        assert_eq!(code(" e11.9 ").unwrap().to_string(), "E11.9");
+        assert!(code("J45").is_ok());
+        assert!(code("S72.001A").is_ok());
+        assert!(code("U07.1").is_ok());
+        assert!(code("J4").is_err());
+        assert!(code("J45.").is_err());
+        assert!(code("45J").is_err());
+    }
+
+    #[test]
+    fn test_measurement() {
+        let measurement = |value: &str| Measurement::try_from(value.to_owned());
+        assert_eq!(measurement(" 4,50 ").unwrap().to_string(), "4.50");
+        assert_eq!(measurement("-0.25").unwrap().value(), -0.25);
+        assert!(measurement("120").is_ok());
+        assert!(measurement("4.").is_err());
+        assert!(measurement("1e3").is_err());
+        assert!(measurement("NaN").is_err());
+        assert!(measurement("").is_err());
+    }
+}
@@ -1,2 +1,7 @@
+pub mod clock;
+pub mod crypto;
 pub mod input_validation;
 pub mod password_utils;
+pub mod throttling;
+pub mod totp;
+pub mod username;
@@ -1,15 +1,17 @@
 //! Hachage et vérification des mots de passe
 
-use argon2::{password_hash::{rand_core::OsRng, PasswordHashString, PasswordVerifier, SaltString}, Argon2, PasswordHasher};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHashString, PasswordVerifier, SaltString},
+    Algorithm, Argon2, KeyId, Params, ParamsBuilder, PasswordHasher, Version,
+};
 use derive_more::derive::Display;
 use serde::{Deserialize, Serialize};
-use std::{str::FromStr, sync::LazyLock};
+use std::{
+    str::FromStr,
+    sync::{LazyLock, OnceLock},
+};
 
-static DEFAULT_HASHER: LazyLock<Argon2<'static>> = LazyLock::new(|| Argon2::default());
-
-/// Le hash d'un mot de passe vide, à utiliser quand l'utilisateur n'existe pas
-/// pour éviter une attaque par canal auxiliaire
-static EMPTY_HASH: LazyLock<PWHash> = LazyLock::new(|| hash(""));
+static DEFAULT_HASHER: LazyLock<Hasher> = LazyLock::new(Hasher::default);
 
 /// Un mot de passe haché
 #[derive(Clone, Debug, Display)]
@@ -36,38 +38,245 @@ impl<'de> Deserialize<'de> for PWHash {
     }
 }
 
-/// Calcule un haché a partir d'un mot de passe en clair, en choisissant un sel au hasard
+/// Paramètres de coût d'Argon2id, et poivre optionnel
+#[derive(Clone, Debug)]
+pub struct HashConfig {
+    /// Mémoire utilisée, en KiB
+    pub memory_kib: u32,
+    /// Nombre de passes
+    pub iterations: u32,
+    /// Degré de parallélisme
+    pub parallelism: u32,
+    /// Secret commun à tous les hachés, qui n'est jamais stocké dans la base
+    pub pepper: Option<Pepper>,
+}
+
+/// Un poivre, et l'identifiant choisi par l'opérateur qui est stocké dans
+/// chaque haché pour savoir avec quel poivre le vérifier.
+///
+/// L'identifiant ne dit rien du poivre: changer de poivre demande de
+/// changer d'identifiant, et les hachés sont remplacés à la connexion.
+#[derive(Clone, Debug)]
+pub struct Pepper {
+    id: KeyId,
+    secret: Vec<u8>,
+}
+
+impl Pepper {
+    /// L'identifiant compte de 1 à 8 octets
+    pub fn new(id: &[u8], secret: Vec<u8>) -> Option<Self> {
+        if id.is_empty() {
+            return None;
+        }
+        Some(Self {
+            id: KeyId::new(id).ok()?,
+            secret,
+        })
+    }
+}
+
+impl Default for HashConfig {
+    fn default() -> Self {
+        Self {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+            pepper: None,
+        }
+    }
+}
+
+/// Hache et vérifie les mots de passe selon une configuration donnée.
+///
+/// Les hachés produits avec des paramètres plus faibles (ou sans le poivre
+/// courant) restent vérifiables, et sont signalés par [`Hasher::needs_rehash`].
+#[derive(Debug)]
+pub struct Hasher {
+    params: Params,
+    pepper: Option<Vec<u8>>,
+    /// Le hash d'un mot de passe vide, à utiliser quand l'utilisateur n'existe pas
+    /// pour éviter une attaque par canal auxiliaire
+    empty_hash: OnceLock<PWHash>,
+}
+
+impl Default for Hasher {
+    fn default() -> Self {
+        Self::new(HashConfig::default()).expect("default Argon2 parameters are valid")
+    }
+}
+
+impl Hasher {
+    pub fn new(config: HashConfig) -> Result<Self, argon2::Error> {
+        let mut builder = ParamsBuilder::new();
+        builder
+            .m_cost(config.memory_kib)
+            .t_cost(config.iterations)
+            .p_cost(config.parallelism);
+
+        // L'identifiant du poivre est stocké dans le PHC, pour savoir avec
+        // quel secret un haché doit être vérifié
+        if let Some(pepper) = &config.pepper {
+            builder.keyid(pepper.id);
+        }
+
+        Ok(Self {
+            params: builder.build()?,
+            pepper: config.pepper.map(|pepper| pepper.secret),
+            empty_hash: OnceLock::new(),
+        })
+    }
+
+    /// Construit une instance d'Argon2id capable de vérifier un haché
+    /// produit avec l'identifiant de poivre donné
+    fn argon2(&self, keyid: &[u8]) -> Argon2<'_> {
+        match &self.pepper {
+            Some(pepper) if self.is_pepper_id(keyid) => Argon2::new_with_secret(
+                pepper,
+                Algorithm::Argon2id,
+                Version::V0x13,
+                self.params.clone(),
+            )
+            .expect("pepper length is bounded"),
+            _ => Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone()),
+        }
+    }
+
+    /// Indique si un identifiant désigne le poivre courant
+    fn is_pepper_id(&self, keyid: &[u8]) -> bool {
+        !keyid.is_empty() && keyid == self.params.keyid()
+    }
+
+    /// Calcule un haché a partir d'un mot de passe en clair, en choisissant un sel au hasard
+    pub fn hash(&self, password: &str) -> PWHash {
+        let salt = SaltString::generate(&mut OsRng);
+
+        PWHash(
+            self.argon2(self.params.keyid())
+                .hash_password(password.as_bytes(), &salt)
+                .unwrap()
+                .serialize(),
+        )
+    }
+
+    /// Vérifie si le mot de passe correspond au hash stocké.
+    ///
+    /// Si un hash n'est pas fourni, on doit quand même tester
+    /// le mot de passe avec un faux hash pour éviter une timing
+    /// attack.
+    pub fn verify(&self, password: &str, maybe_hash: Option<&PWHash>) -> bool {
+        let hash = maybe_hash.unwrap_or_else(|| self.empty_hash.get_or_init(|| self.hash("")));
+        let hash = hash.0.password_hash();
+        let keyid = Params::try_from(&hash)
+            .map(|params| params.keyid().to_vec())
+            .unwrap_or_default();
+
+        let verified = self
+            .argon2(&keyid)
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok();
+
+        // Sans le poivre correspondant, le haché ne peut pas être vérifié
+        verified && (keyid.is_empty() || self.is_pepper_id(&keyid))
+    }
+
+    /// Indique si un haché a été produit avec des paramètres plus faibles que
+    /// la configuration courante, ou sans le poivre courant.
+    pub fn needs_rehash(&self, hash: &PWHash) -> bool {
+        let hash = hash.0.password_hash();
+        let Ok(params) = Params::try_from(&hash) else {
+            return true;
+        };
+
+        hash.algorithm != Algorithm::Argon2id.ident()
+            || hash.version != Some(Version::V0x13.into())
+            || params.m_cost() < self.params.m_cost()
+            || params.t_cost() < self.params.t_cost()
+            || params.p_cost() < self.params.p_cost()
+            || params.keyid() != self.params.keyid()
+    }
+}
+
+/// Calcule un haché avec les paramètres par défaut
 pub fn hash(password: &str) -> PWHash {
-    let salt = SaltString::generate(&mut OsRng);
-    
-    PWHash(DEFAULT_HASHER.hash_password(password.as_bytes(), &salt).unwrap().serialize())
+    DEFAULT_HASHER.hash(password)
 }
 
-/// Vérifie si le mot de passe correspond au hash stocké.
-/// 
-/// Si un hash n'est pas fourni, on doit quand même tester
-/// le mot de passe avec un faux hash pour éviter une timing
-/// attack.
+/// Vérifie un mot de passe avec les paramètres par défaut
 pub fn verify(password: &str, maybe_hash: Option<&PWHash>) -> bool {
-        let hash = maybe_hash.unwrap_or(&*EMPTY_HASH);
-    
-        DEFAULT_HASHER.verify_password(password.as_bytes(), &hash.0.password_hash()).is_ok()
+    DEFAULT_HASHER.verify(password, maybe_hash)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn weak_config() -> HashConfig {
+        HashConfig {
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+            pepper: None,
+        }
+    }
+
     #[test]
     fn test_password_hashing_and_validation() {
-        
         let password = "password";
         let hash = hash(password);
         assert!(verify(password, Some(&hash)));
         assert!(!verify("wrong password", Some(&hash)));
         assert!(!verify(password, None));
-        
     }
-    
-}
 
+    #[test]
+    fn test_needs_rehash_with_stronger_params() {
+        let weak = Hasher::new(weak_config()).unwrap();
+        let strong = Hasher::new(HashConfig {
+            memory_kib: 2048,
+            iterations: 2,
+            ..weak_config()
+        })
+        .unwrap();
+
+        let hash = weak.hash("password");
+        assert!(!weak.needs_rehash(&hash));
+        assert!(strong.needs_rehash(&hash));
+
+        // Les anciens hachés restent vérifiables
+        assert!(strong.verify("password", Some(&hash)));
+        assert!(!strong.needs_rehash(&strong.hash("password")));
+    }
+
+    #[test]
+    fn test_pepper() {
+        let plain = Hasher::new(weak_config()).unwrap();
+        let with_pepper = |id: &[u8], secret: &[u8]| {
+            Hasher::new(HashConfig {
+                pepper: Pepper::new(id, secret.to_vec()),
+                ..weak_config()
+            })
+            .unwrap()
+        };
+        let peppered = with_pepper(b"2024", b"pepper");
+        let other_pepper = with_pepper(b"2025", b"another pepper");
+        assert!(Pepper::new(b"", b"pepper".to_vec()).is_none());
+        assert!(Pepper::new(b"too long id", b"pepper".to_vec()).is_none());
+
+        let legacy = plain.hash("password");
+        assert!(peppered.verify("password", Some(&legacy)));
+        assert!(peppered.needs_rehash(&legacy));
+
+        let hash = peppered.hash("password");
+        assert!(peppered.verify("password", Some(&hash)));
+        assert!(!peppered.verify("wrong password", Some(&hash)));
+        assert!(!peppered.needs_rehash(&hash));
+
+        // Le haché porte l'identifiant configuré, rien qui dérive du poivre
+        assert!(hash.to_string().contains("keyid=MjAyNA"));
+
+        // Un haché poivré ne peut pas être vérifié sans le bon poivre
+        assert!(!plain.verify("password", Some(&hash)));
+        assert!(!other_pepper.verify("password", Some(&hash)));
+        assert!(!with_pepper(b"2024", b"another pepper").verify("password", Some(&hash)));
+    }
+}
@@ -0,0 +1,131 @@
+//! Limitation des tentatives de connexion et verrouillage temporaire des comptes
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Règles de limitation des tentatives de connexion
+#[derive(Debug, Clone, Copy)]
+pub struct LockoutPolicy {
+    /// Nombre d'échecs tolérés avant d'imposer un délai entre les tentatives
+    pub free_attempts: u32,
+    /// Délai imposé après le premier échec non toléré, doublé à chaque nouvel échec
+    pub base_delay: TimeDelta,
+    /// Nombre d'échecs après lequel le compte est verrouillé
+    pub max_attempts: u32,
+    /// Durée du verrouillage
+    pub lockout_duration: TimeDelta,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            free_attempts: 3,
+            base_delay: TimeDelta::seconds(2),
+            max_attempts: 10,
+            lockout_duration: TimeDelta::minutes(15),
+        }
+    }
+}
+
+/// Les échecs de connexion récents pour un nom d'utilisateur.
+///
+/// Ces compteurs existent aussi pour les noms inconnus, afin de ne pas
+/// révéler quels comptes existent.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LoginAttempts {
+    pub failures: u32,
+    pub last_failure: Option<DateTime<Utc>>,
+}
+
+impl LoginAttempts {
+    /// Instant avant lequel aucune nouvelle tentative n'est acceptée, s'il y en a un
+    pub fn blocked_until(&self, policy: &LockoutPolicy) -> Option<DateTime<Utc>> {
+        let last_failure = self.last_failure?;
+
+        if self.failures >= policy.max_attempts {
+            return Some(last_failure + policy.lockout_duration);
+        }
+        if self.failures < policy.free_attempts {
+            return None;
+        }
+
+        // Back-off exponentiel, plafonné à la durée du verrouillage
+        let exponent = (self.failures - policy.free_attempts).min(30);
+        let delay = policy
+            .base_delay
+            .checked_mul(1 << exponent)
+            .unwrap_or(policy.lockout_duration)
+            .min(policy.lockout_duration);
+        Some(last_failure + delay)
+    }
+
+    /// Indique si une tentative est refusée à l'instant donné, et jusqu'à quand
+    pub fn is_blocked(&self, policy: &LockoutPolicy, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.blocked_until(policy).filter(|until| *until > now)
+    }
+
+    pub fn record_failure(&mut self, now: DateTime<Utc>) {
+        self.failures = self.failures.saturating_add(1);
+        self.last_failure = Some(now);
+    }
+
+    /// Indique si le compteur peut être oublié, le dernier échec étant assez ancien
+    pub fn is_expired(&self, policy: &LockoutPolicy, now: DateTime<Utc>) -> bool {
+        self.last_failure
+            .is_none_or(|last| now - last > policy.lockout_duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failures(count: u32, now: DateTime<Utc>) -> LoginAttempts {
+        let mut attempts = LoginAttempts::default();
+        for _ in 0..count {
+            attempts.record_failure(now);
+        }
+        attempts
+    }
+
+    #[test]
+    fn test_exponential_backoff() {
+        let policy = LockoutPolicy::default();
+        let now = Utc::now();
+
+        assert_eq!(failures(2, now).is_blocked(&policy, now), None);
+        assert_eq!(
+            failures(3, now).is_blocked(&policy, now),
+            Some(now + policy.base_delay)
+        );
+        assert_eq!(
+            failures(4, now).is_blocked(&policy, now),
+            Some(now + policy.base_delay * 2)
+        );
+        assert_eq!(
+            failures(5, now).is_blocked(&policy, now),
+            Some(now + policy.base_delay * 4)
+        );
+
+        // Le délai est écoulé
+        let later = now + policy.base_delay * 4;
+        assert_eq!(failures(5, now).is_blocked(&policy, later), None);
+    }
+
+    #[test]
+    fn test_lockout() {
+        let policy = LockoutPolicy::default();
+        let now = Utc::now();
+        let attempts = failures(policy.max_attempts, now);
+
+        assert_eq!(
+            attempts.is_blocked(&policy, now),
+            Some(now + policy.lockout_duration)
+        );
+        assert!(!attempts.is_expired(&policy, now));
+
+        let later = now + policy.lockout_duration + TimeDelta::seconds(1);
+        assert_eq!(attempts.is_blocked(&policy, later), None);
+        assert!(attempts.is_expired(&policy, later));
+    }
+}
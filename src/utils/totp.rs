@@ -0,0 +1,126 @@
+//! Authentification à deux facteurs par codes à usage unique (TOTP, RFC 6238)
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+/// Nom du service affiché dans les applications d'authentification
+pub const ISSUER: &str = "KARAK";
+
+/// Nombre de codes de récupération générés à l'activation
+pub const RECOVERY_CODES: usize = 10;
+
+const DIGITS: usize = 6;
+const STEP: u64 = 30;
+/// Nombre de pas de temps tolérés avant et après l'instant courant
+const SKEW: u64 = 1;
+
+/// Un secret TOTP, stocké en base32
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+pub struct TotpSecret(String);
+
+impl TotpSecret {
+    /// Tire un nouveau secret de 160 bits au hasard
+    pub fn generate() -> Self {
+        let mut secret = [0u8; 20];
+        OsRng.fill_bytes(&mut secret);
+        match Secret::Raw(secret.to_vec()).to_encoded() {
+            Secret::Encoded(encoded) => Self(encoded),
+            Secret::Raw(_) => unreachable!(),
+        }
+    }
+
+    fn totp(&self, account_name: &str) -> TOTP {
+        let secret = Secret::Encoded(self.0.clone())
+            .to_bytes()
+            .expect("stored TOTP secrets are valid base32");
+        TOTP::new_unchecked(
+            Algorithm::SHA1,
+            DIGITS,
+            0,
+            STEP,
+            secret,
+            Some(ISSUER.to_owned()),
+            account_name.to_owned(),
+        )
+    }
+
+    /// L'URI `otpauth://` à saisir dans une application d'authentification
+    pub fn provisioning_uri(&self, account_name: &str) -> String {
+        self.totp(account_name).get_url()
+    }
+
+    /// Le code attendu à un instant donné (en secondes depuis l'epoch)
+    pub fn code_at(&self, time: u64) -> String {
+        self.totp("").generate(time)
+    }
+
+    /// Vérifie un code à un instant donné, en tolérant un léger décalage d'horloge.
+    ///
+    /// Retourne le pas de temps correspondant au code, afin que l'appelant
+    /// puisse refuser qu'un même code soit utilisé deux fois.
+    pub fn verify(&self, code: &str, time: u64) -> Option<u64> {
+        let totp = self.totp("");
+        let current = time / STEP;
+
+        (current.saturating_sub(SKEW)..=current + SKEW)
+            .find(|step| totp.check(code.trim(), step * STEP))
+    }
+}
+
+/// Génère des codes de récupération aléatoires, de la forme `abcd-efgh`
+pub fn generate_recovery_codes() -> Vec<String> {
+    const ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let mut bytes = [0u8; 8];
+            OsRng.fill_bytes(&mut bytes);
+            let code: String = bytes
+                .iter()
+                .map(|b| ALPHABET[*b as usize % ALPHABET.len()] as char)
+                .collect();
+            format!("{}-{}", &code[..4], &code[4..])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_code() {
+        let secret = TotpSecret::generate();
+        let now = 1_700_000_000;
+
+        let step = secret.verify(&secret.code_at(now), now);
+        assert_eq!(step, Some(now / STEP));
+
+        // Un léger décalage d'horloge est toléré
+        assert!(secret.verify(&secret.code_at(now - STEP), now).is_some());
+        assert!(secret.verify(&secret.code_at(now + STEP), now).is_some());
+
+        // Mais pas un code trop ancien
+        assert!(secret
+            .verify(&secret.code_at(now - 10 * STEP), now)
+            .is_none());
+        assert!(secret.verify("000000x", now).is_none());
+    }
+
+    #[test]
+    fn test_provisioning_uri() {
+        let secret = TotpSecret::generate();
+        let uri = secret.provisioning_uri("medecin1");
+
+        assert!(uri.starts_with("otpauth://totp/KARAK:medecin1?"));
+        assert!(uri.contains(&format!("secret={}", secret.0)));
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODES);
+        assert!(codes.iter().all(|code| code.len() == 9));
+    }
+}
@@ -0,0 +1,291 @@
+//! Validated usernames
+//!
+//! A username is made of ASCII letters, digits, underscores and hyphens.
+//! Usernames are compared by their canonical form (NFKC and case folded), but
+//! displayed as they were entered.
+
+use derive_more::derive::Display;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+use std::sync::LazyLock;
+use thiserror::Error;
+use unicode_normalization::UnicodeNormalization;
+
+/// Characters accepted in a username, compiled once
+static ALPHABET: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[A-Za-z0-9_-]+$").expect("valid username regex"));
+
+pub const DEFAULT_MIN_USERNAME_LEN: usize = 3;
+pub const DEFAULT_MAX_USERNAME_LEN: usize = 30;
+
+/// Upper bound of any username, whatever the policy, to bound the input
+pub const MAX_USERNAME_LEN: usize = 64;
+
+/// Names that cannot be registered, as they could be mistaken for the system
+pub const RESERVED_USERNAMES: &[&str] = &["admin", "root", "system"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum UsernameError {
+    #[error("Le nom d'utilisateur doit contenir entre {min} et {max} caractères")]
+    Length { min: usize, max: usize },
+    #[error("Le nom d'utilisateur ne peut contenir que des lettres, des chiffres, des tirets bas et des tirets")]
+    InvalidCharacters,
+    #[error("Le nom d'utilisateur '{0}' est réservé")]
+    Reserved(String),
+}
+
+/// Username requirements enforced on registration
+#[derive(Debug, Clone)]
+pub struct UsernamePolicy {
+    pub min_len: usize,
+    pub max_len: usize,
+    /// Compared by canonical form
+    pub reserved: Vec<String>,
+}
+
+impl Default for UsernamePolicy {
+    fn default() -> Self {
+        Self {
+            min_len: DEFAULT_MIN_USERNAME_LEN,
+            max_len: DEFAULT_MAX_USERNAME_LEN,
+            reserved: RESERVED_USERNAMES
+                .iter()
+                .map(|&name| name.to_owned())
+                .collect(),
+        }
+    }
+}
+
+impl UsernamePolicy {
+    /// Builds the default policy with other length bounds
+    pub fn with_length(min_len: usize, max_len: usize) -> Result<Self, UsernameError> {
+        if min_len == 0 || min_len > max_len || max_len > MAX_USERNAME_LEN {
+            return Err(UsernameError::Length {
+                min: 1,
+                max: MAX_USERNAME_LEN,
+            });
+        }
+        Ok(Self {
+            min_len,
+            max_len,
+            ..Self::default()
+        })
+    }
+
+    /// Checks a name chosen for a new account
+    pub fn validate(&self, username: &Username) -> Result<(), UsernameError> {
+        check_alphabet(username.as_ref())?;
+        if !(self.min_len..=self.max_len).contains(&username.as_ref().chars().count()) {
+            return Err(UsernameError::Length {
+                min: self.min_len,
+                max: self.max_len,
+            });
+        }
+        if self
+            .reserved
+            .iter()
+            .any(|reserved| canonical_username(reserved) == username.canonical())
+        {
+            return Err(UsernameError::Reserved(username.to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Only checks the alphabet and the absolute length bound: existing accounts
+/// must stay reachable if the policy becomes stricter
+fn check_alphabet(username: &str) -> Result<(), UsernameError> {
+    if username.is_empty() || username.len() > MAX_USERNAME_LEN {
+        return Err(UsernameError::Length {
+            min: 1,
+            max: MAX_USERNAME_LEN,
+        });
+    }
+    if !ALPHABET.is_match(username) {
+        return Err(UsernameError::InvalidCharacters);
+    }
+    Ok(())
+}
+
+/// NFKC normalisation followed by case folding, normalised again because
+/// folding can produce non-normalised sequences
+pub fn canonical_username(username: &str) -> String {
+    let folded = caseless::default_case_fold_str(&username.nfkc().collect::<String>());
+    folded.nfkc().collect()
+}
+
+/// Wrapper type for a username that has been validated
+///
+/// The name is displayed as it was entered, but two usernames are equal when
+/// their canonical forms are, so `Admin` and `admin` are the same user.
+#[derive(Debug, Clone, Display)]
+#[display("{display}")]
+pub struct Username {
+    display: String,
+    canonical: String,
+}
+
+//Needed for the tests in authorization.rs
+impl Username {
+    pub fn new(username: String) -> Username {
+        Username {
+            canonical: canonical_username(&username),
+            display: username,
+        }
+    }
+
+    /// The form used to compare usernames: NFKC and case folded
+    pub fn canonical(&self) -> &str {
+        &self.canonical
+    }
+}
+
+impl PartialEq for Username {
+    fn eq(&self, other: &Self) -> bool {
+        self.canonical == other.canonical
+    }
+}
+
+impl Eq for Username {}
+
+impl PartialOrd for Username {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Username {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.canonical.cmp(&other.canonical)
+    }
+}
+
+impl Hash for Username {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.canonical.hash(state)
+    }
+}
+
+impl Serialize for Username {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.display)
+    }
+}
+
+impl<'de> Deserialize<'de> for Username {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::new)
+    }
+}
+
+impl TryFrom<String> for Username {
+    type Error = UsernameError;
+
+    fn try_from(username: String) -> Result<Self, Self::Error> {
+        check_alphabet(&username)?;
+        Ok(Self::new(username))
+    }
+}
+
+impl TryFrom<&str> for Username {
+    type Error = UsernameError;
+
+    fn try_from(username: &str) -> Result<Self, Self::Error> {
+        username.to_owned().try_into()
+    }
+}
+
+impl AsRef<str> for Username {
+    fn as_ref(&self) -> &str {
+        &self.display
+    }
+}
+
+pub fn username_input_validation(message: &str) -> Result<Username, UsernameError> {
+    loop {
+        let username = inquire::Text::new(message).prompt().unwrap();
+
+        match Username::try_from(username) {
+            Ok(username) => return Ok(username),
+            Err(e) => eprintln!("Nom d'utilisateur invalide, réessayez.\n{e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn validate(name: &str) -> Result<(), UsernameError> {
+        UsernamePolicy::default().validate(&name.try_into()?)
+    }
+
+    #[test]
+    fn test_username_validation() {
+        assert!(validate("medecin1").is_ok());
+        assert!(validate("jean-luc_2").is_ok());
+        assert_eq!(
+            validate("ab"),
+            Err(UsernameError::Length { min: 3, max: 30 })
+        );
+        assert_eq!(validate("jean luc"), Err(UsernameError::InvalidCharacters));
+        assert_eq!(validate("médecin"), Err(UsernameError::InvalidCharacters));
+        assert_eq!(validate("bob\n"), Err(UsernameError::InvalidCharacters));
+        assert_eq!(
+            validate("ROOT"),
+            Err(UsernameError::Reserved("ROOT".to_owned()))
+        );
+
+        // Un compte réservé existant peut toujours être désigné
+        assert!(Username::try_from("admin").is_ok());
+        let short = UsernamePolicy::with_length(2, 5).unwrap();
+        assert!(short.validate(&Username::new("ab".to_owned())).is_ok());
+        assert!(UsernamePolicy::with_length(5, 2).is_err());
+    }
+
+    #[test]
+    fn test_username_canonical_form() {
+        let display = Username::new("Ａｄｍｉｎ".to_owned());
+        assert_eq!(display.canonical(), "admin");
+        assert_eq!(display, Username::new("ADMIN".to_owned()));
+        assert_eq!(display.to_string(), "Ａｄｍｉｎ");
+        assert_eq!(canonical_username("Straße"), "strasse");
+        assert_ne!(display, Username::new("admin2".to_owned()));
+
+        let json = serde_json::to_string(&display).unwrap();
+        assert_eq!(json, "\"Ａｄｍｉｎ\"");
+        let parsed: Username = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.canonical(), "admin");
+    }
+
+    proptest! {
+        #[test]
+        fn test_accepts_alphabet(name in "[A-Za-z0-9_-]{3,30}") {
+            prop_assume!(!RESERVED_USERNAMES.contains(&canonical_username(&name).as_str()));
+            prop_assert!(validate(&name).is_ok());
+        }
+
+        #[test]
+        fn test_rejects_other_characters(
+            prefix in "[A-Za-z0-9_-]{0,10}",
+            other in any::<char>().prop_filter("outside the alphabet", |c| {
+                !c.is_ascii_alphanumeric() && *c != '_' && *c != '-'
+            }),
+            suffix in "[A-Za-z0-9_-]{0,10}",
+        ) {
+            let name = format!("{prefix}{other}{suffix}");
+            prop_assert_eq!(Username::try_from(name), Err(UsernameError::InvalidCharacters));
+        }
+
+        #[test]
+        fn test_enforces_length(name in "[a-z]{0,2}|[a-z]{31,64}") {
+            prop_assert!(
+                matches!(validate(&name), Err(UsernameError::Length { .. })),
+                "{name} should be rejected"
+            );
+        }
+    }
+}